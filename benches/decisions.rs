@@ -0,0 +1,121 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use futures::channel::mpsc;
+use futures::future::join_all;
+use paxos::paxos::node::Node;
+use paxos::paxos::proposal::{Datagram, Incoming, Request};
+use paxos::paxos::Tx;
+use paxos::shell::Console;
+use paxos::shutdown::Shutdown;
+use tokio::stream::StreamExt;
+
+// 每种规模重复的次数；单决议 Paxos 每个集群只能选定一次，因此每次都新建集群
+const ROUNDS: usize = 20;
+const SIZES: [usize; 4] = [3, 5, 7, 9];
+
+// 不经过网络，用通道把结点直接连起来，直到提案者广播 Learn 即为达成决议
+async fn decide_in_memory(size: usize) {
+    let mut inputs: HashMap<usize, Tx<Incoming>> = HashMap::new();
+    let (otx, mut orx) = mpsc::unbounded();
+    let (_shutdown, shutdown_rx) = Shutdown::new();
+    for id in 1..=size {
+        let (itx, irx) = mpsc::unbounded();
+        // 所有结点的输出汇到一起，附上来源后转交给目的结点
+        let (node_tx, mut node_rx) = mpsc::unbounded();
+        let otx = otx.clone();
+        tokio::spawn(async move {
+            while let Some(outgoing) = node_rx.next().await {
+                let _ = otx.unbounded_send((id, outgoing));
+            }
+        });
+        let node = Node::new(id, (1..=size).collect(), node_tx, irx);
+        tokio::spawn(node.run(shutdown_rx.clone()));
+        inputs.insert(id, itx);
+    }
+
+    let propose = Request::Propose {
+        value: 1,
+        priority: 0,
+        seq: None,
+    };
+    let _ = inputs[&1].unbounded_send(Incoming {
+        src: 0,
+        dgram: Datagram::Request(propose),
+    });
+    while let Some((src, outgoing)) = orx.next().await {
+        if let Datagram::Request(Request::Learn { .. }) = outgoing.dgram {
+            return;
+        }
+        for dst in outgoing.dst {
+            if let Some(tx) = inputs.get(&dst) {
+                let dgram = outgoing.dgram.clone();
+                let _ = tx.unbounded_send(Incoming { src, dgram });
+            }
+        }
+    }
+}
+
+fn report(name: &str, size: usize, elapsed: Duration) {
+    eprintln!(
+        "{:<20} {} nodes: {:>8.1} decisions/s",
+        name,
+        size,
+        ROUNDS as f64 / elapsed.as_secs_f64()
+    );
+}
+
+fn bench_in_memory() {
+    let mut rt = tokio::runtime::Runtime::new().unwrap();
+    for &size in SIZES.iter() {
+        let start = Instant::now();
+        for _ in 0..ROUNDS {
+            rt.block_on(decide_in_memory(size));
+        }
+        report("memory/sequential", size, start.elapsed());
+
+        let start = Instant::now();
+        rt.block_on(join_all((0..ROUNDS).map(|_| decide_in_memory(size))));
+        report("memory/concurrent", size, start.elapsed());
+    }
+}
+
+// 走本机 TCP，每条消息一个连接
+fn bench_tcp() {
+    for (i, &size) in SIZES.iter().enumerate() {
+        let base_port = 11000 + i * 1000;
+
+        let start = Instant::now();
+        for round in 0..ROUNDS {
+            let mut console = Console::new();
+            console.start_servers(size, base_port + round * 10);
+            console.propose(1, 1);
+            console.query_wait(1, Duration::from_secs(5));
+            console.exit();
+        }
+        report("tcp/sequential", size, start.elapsed());
+
+        // 每一组是一个独立的集群，同时提案
+        let start = Instant::now();
+        let mut console = Console::new();
+        for group in 0..ROUNDS {
+            console.select_group(group);
+            console.start_servers(size, base_port + 500 + group * 10);
+        }
+        for group in 0..ROUNDS {
+            console.select_group(group);
+            console.propose(1, 1);
+        }
+        for group in 0..ROUNDS {
+            console.select_group(group);
+            console.query_wait(1, Duration::from_secs(5));
+        }
+        report("tcp/concurrent", size, start.elapsed());
+        console.exit();
+    }
+}
+
+fn main() {
+    bench_in_memory();
+    bench_tcp();
+}
@@ -0,0 +1,260 @@
+use futures::channel::mpsc;
+use std::collections::HashMap;
+use std::fmt;
+use std::net::SocketAddr;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+use tokio::stream::StreamExt;
+
+use crate::net_proxy::{AddrTable, Proxy};
+use crate::paxos::node::Node;
+use crate::paxos::proposal::{Datagram, Incoming, Request, Response, DEFAULT_GROUP};
+use crate::paxos::ValueType;
+use crate::shutdown::Shutdown;
+use crate::task_name::named;
+
+/*
+分布式部署时每个进程只跑一个结点：
+    paxos node --id 3 [--config cluster.toml] [--bind addr] 常驻运行 #3，Ctrl-C 退出
+    paxos propose --server 3 --value 42 [--config ...]      向 #3 提案并等到选定后退出
+配置文件列出所有成员的地址，#0 是客户端：
+    [servers]
+    0 = "127.0.0.1:9527"
+    1 = "127.0.0.1:9528"
+*/
+
+pub const DEFAULT_CONFIG: &str = "cluster.toml";
+
+// 一次性客户端等待选定的最长时间
+const PROPOSE_TIMEOUT: Duration = Duration::from_secs(5);
+// 服务器还没起来时重发提案的间隔
+const RETRY_INTERVAL: Duration = Duration::from_millis(100);
+
+#[derive(Debug, PartialEq)]
+pub enum Cli {
+    Node {
+        id: usize,
+        config: String,
+        bind: Option<SocketAddr>, // 监听地址，缺省时监听配置中公布的地址
+    },
+    Propose {
+        server: usize,
+        value: ValueType,
+        config: String,
+    },
+}
+
+#[derive(Debug, PartialEq)]
+pub enum CliError {
+    Usage(String),   // 参数不对
+    Config(String),  // 配置文件读不出或格式不对
+    Network(String), // 监听失败或服务器没有回应
+}
+
+impl fmt::Display for CliError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Usage(msg) => write!(f, "usage: {}", msg),
+            Self::Config(msg) => write!(f, "config: {}", msg),
+            Self::Network(msg) => write!(f, "{}", msg),
+        }
+    }
+}
+
+impl std::error::Error for CliError {}
+
+impl Cli {
+    // 参数不含程序名；不是子命令时返回 None，交给交互式控制台
+    pub fn parse(args: &[String]) -> Option<Result<Self, CliError>> {
+        let (sub, rest) = args.split_first()?;
+        let mut flags = HashMap::new();
+        let mut rest = rest.iter();
+        while let Some(flag) = rest.next() {
+            match (flag.strip_prefix("--"), rest.next()) {
+                (Some(name), Some(value)) => flags.insert(name, value.as_str()),
+                _ => return Some(Err(CliError::Usage(format!("bad argument `{}`", flag)))),
+            };
+        }
+        let config = flags.get("config").unwrap_or(&DEFAULT_CONFIG).to_string();
+        let number = |name: &str| -> Result<usize, CliError> {
+            flags
+                .get(name)
+                .and_then(|v| v.parse().ok())
+                .ok_or_else(|| CliError::Usage(format!("{} needs --{} <number>", sub, name)))
+        };
+        Some(match sub.as_str() {
+            "node" => {
+                let bind = match flags.get("bind").map(|addr| addr.parse()) {
+                    None => None,
+                    Some(Ok(addr)) => Some(addr),
+                    Some(Err(_)) => {
+                        return Some(Err(CliError::Usage("node needs --bind <addr>".to_string())))
+                    }
+                };
+                number("id").map(|id| Self::Node { id, config, bind })
+            }
+            "propose" => number("server").and_then(|server| {
+                Ok(Self::Propose {
+                    server,
+                    value: number("value")? as ValueType,
+                    config,
+                })
+            }),
+            _ => return None,
+        })
+    }
+
+    // 返回进程的退出码
+    pub fn run(self) -> i32 {
+        let result = match self {
+            Self::Node { id, config, bind } => {
+                load_config(&config).and_then(|table| run_node(id, table, bind))
+            }
+            Self::Propose {
+                server,
+                value,
+                config,
+            } => load_config(&config).and_then(|table| propose(server, value, table)),
+        };
+        match result {
+            Ok(()) => 0,
+            Err(e) => {
+                println!("error: {}", e);
+                1
+            }
+        }
+    }
+}
+
+// 每行一个 `ID = "地址"`，忽略空行、注释与表头
+pub fn parse_config(text: &str) -> Result<HashMap<usize, SocketAddr>, CliError> {
+    let mut table = HashMap::new();
+    for (no, line) in text.lines().enumerate() {
+        let line = line.split('#').next().unwrap().trim();
+        if line.is_empty() || line.starts_with('[') {
+            continue;
+        }
+        let bad_line = || CliError::Config(format!("line {}: `{}`", no + 1, line));
+        let (id, addr) = line.split_once('=').ok_or_else(bad_line)?;
+        let id = id.trim().parse().map_err(|_| bad_line())?;
+        let addr = addr
+            .trim()
+            .trim_matches('"')
+            .parse()
+            .map_err(|_| bad_line())?;
+        table.insert(id, addr);
+    }
+    Ok(table)
+}
+
+fn load_config(path: &str) -> Result<AddrTable, CliError> {
+    let text =
+        std::fs::read_to_string(path).map_err(|e| CliError::Config(format!("{}: {}", path, e)))?;
+    Ok(Arc::new(RwLock::new(parse_config(&text)?)))
+}
+
+// 常驻运行一个结点，成员是配置中除客户端 #0 之外的所有 ID
+fn run_node(id: usize, addr_table: AddrTable, bind: Option<SocketAddr>) -> Result<(), CliError> {
+    let peers_id = addr_table
+        .read()
+        .unwrap()
+        .keys()
+        .copied()
+        .filter(|&id| id != 0)
+        .collect();
+    if id == 0 || !addr_table.read().unwrap().contains_key(&id) {
+        return Err(CliError::Usage(format!("server #{} isn't in config", id)));
+    }
+    let mut rt = tokio::runtime::Runtime::new().unwrap();
+    rt.block_on(async {
+        let proxy = Proxy::with_bind_addr(DEFAULT_GROUP, id, addr_table, bind);
+        let listener = proxy
+            .bind()
+            .await
+            .map_err(|e| CliError::Network(format!("server #{} can't listen: {}", id, e)))?;
+        let (itx, irx) = mpsc::unbounded();
+        let (otx, orx) = mpsc::unbounded();
+        let (shutdown, shutdown_rx) = Shutdown::new();
+        let node = Node::new(id, peers_id, otx, irx);
+        let proxy = tokio::spawn(named(
+            format!("proxy-{}", id),
+            proxy.run(listener, itx, orx, shutdown_rx.clone()),
+        ));
+        let node = tokio::spawn(named(format!("node-{}", id), node.run(shutdown_rx)));
+        let _ = tokio::signal::ctrl_c().await;
+        let _ = shutdown.send(());
+        let _ = proxy.await;
+        let _ = node.await;
+        Ok(())
+    })
+}
+
+// 以客户端 #0 的身份提案，等到 server 学习到值后打印并返回
+fn propose(server: usize, value: ValueType, addr_table: AddrTable) -> Result<(), CliError> {
+    if !addr_table.read().unwrap().contains_key(&0) {
+        return Err(CliError::Config("client #0 isn't in config".to_string()));
+    }
+    let mut rt = tokio::runtime::Runtime::new().unwrap();
+    rt.block_on(async {
+        let proxy = Proxy::new(0, addr_table);
+        let listener = proxy
+            .bind()
+            .await
+            .map_err(|e| CliError::Network(format!("client can't listen: {}", e)))?;
+        let (itx, mut inbox) = mpsc::unbounded();
+        let (_otx, orx) = mpsc::unbounded();
+        let (_shutdown, shutdown_rx) = Shutdown::new();
+        tokio::spawn(named(
+            "proxy-0".to_string(),
+            proxy.clone().run(listener, itx, orx, shutdown_rx),
+        ));
+
+        let start = Instant::now();
+        let deadline = start + PROPOSE_TIMEOUT;
+        let req = Datagram::Request(Request::Propose {
+            value,
+            priority: 0,
+            seq: None,
+        });
+        // 服务器可能还在启动，连不上就稍后重试
+        while proxy.send(server, &req).await.is_err() {
+            if Instant::now() >= deadline {
+                return Err(CliError::Network(format!(
+                    "server #{} is unreachable",
+                    server
+                )));
+            }
+            tokio::time::delay_for(RETRY_INTERVAL).await;
+        }
+        let _ = proxy
+            .send(server, &Datagram::Request(Request::WaitQuery))
+            .await;
+        let answer =
+            tokio::time::timeout(deadline.saturating_duration_since(Instant::now()), async {
+                while let Some(Incoming { src, dgram }) = inbox.next().await {
+                    if let (true, Datagram::Response(Response::Query { val: Some(val) })) =
+                        (src == server, dgram)
+                    {
+                        return Some(val);
+                    }
+                }
+                None
+            })
+            .await;
+        match answer {
+            Ok(Some(chosen)) => {
+                println!(
+                    "Server #{} Answer: {} (decided in {}ms).",
+                    server,
+                    chosen,
+                    start.elapsed().as_millis()
+                );
+                Ok(())
+            }
+            _ => Err(CliError::Network(format!(
+                "server #{} didn't answer",
+                server
+            ))),
+        }
+    })
+}
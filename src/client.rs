@@ -0,0 +1,335 @@
+use futures::channel::{mpsc, oneshot};
+use std::collections::HashMap;
+use std::fmt;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::net::TcpListener;
+use tokio::stream::StreamExt;
+use tokio::sync::Semaphore;
+
+use crate::net_proxy::{AddrTable, Proxy};
+use crate::paxos::proposal::{Datagram, DecisionRecord, Incoming, Request, Response};
+use crate::paxos::{Rx, ValueType};
+use crate::shutdown::Shutdown;
+use crate::task_name::named;
+
+/*
+供库使用者同时发起大量提案的客户端：
+    等待中的提案按服务器登记。回应里没有提案编号，结点对同一个客户端也只记一次等待，
+    所以一个服务器的回应交给在它那里等待的所有提案：同一服务器学习到的值只有一个，
+    重定向也对它们同样适用；
+    服务器回应重定向时自动改向提案者重新提案，超时后可以按设定的次数重发。
+每个会话在地址表中以自己的 ID 收发报文，与控制台的客户端 #0 和其他会话互不干扰。
+可以限制同时在途的提案数，超出的提案排队等待空位，减少提案者之间的相互抢占。
+*/
+
+// 会话的 ID 从这里往下分配，与 HTTP 网关的 usize::MAX 错开
+static NEXT_SESSION_ID: AtomicUsize = AtomicUsize::new(usize::MAX - 1);
+
+// 分配一个还没有会话用过的 ID
+pub fn next_session_id() -> usize {
+    NEXT_SESSION_ID.fetch_sub(1, Ordering::Relaxed)
+}
+
+// 最多跟随的重定向次数，防止结点之间互相指向
+const MAX_REDIRECTS: usize = 3;
+
+#[derive(Debug, PartialEq)]
+pub enum SessionError {
+    Unreachable(usize), // 连不上服务器
+    Timeout(usize),     // 服务器没有在时限内学习到值
+    TooManyRedirects,
+}
+
+impl fmt::Display for SessionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Unreachable(id) => write!(f, "server #{} is unreachable", id),
+            Self::Timeout(id) => write!(f, "server #{} didn't answer", id),
+            Self::TooManyRedirects => write!(f, "too many redirects"),
+        }
+    }
+}
+
+impl std::error::Error for SessionError {}
+
+#[derive(Debug, Clone)]
+enum Answer {
+    Chosen(ValueType, Option<DecisionRecord>),
+    Redirect(usize, SocketAddr),
+}
+
+// 在某个服务器上等待的提案
+struct Waiter {
+    id: u64,           // 提案编号，超时的提案据此撤下自己
+    want_record: bool, // 是否等待决策记录
+    tx: oneshot::Sender<Answer>,
+}
+
+// 服务器 -> 在它那里等待的提案
+type Pending = Arc<Mutex<HashMap<usize, Vec<Waiter>>>>;
+
+pub struct ClientSession {
+    id: usize,
+    proxy: Arc<Proxy>,
+    addr_table: AddrTable,
+    pending: Pending,
+    next_id: AtomicU64,
+    admission: Option<Semaphore>, // 在途提案的名额，None 时不限
+    retries: usize,               // 超时后重发的次数
+    in_flight: AtomicUsize,
+    peak_in_flight: AtomicUsize,
+    _shutdown: oneshot::Sender<()>, // 会话被丢弃时停掉收发任务
+}
+
+impl ClientSession {
+    // 在 bind 上监听并加入 group 组，把实际监听的地址登记到地址表。
+    // 端口为 0 时任选空闲端口；结点按登记的地址回应，bind 不能是未指定地址
+    pub async fn start(
+        group: usize,
+        id: usize,
+        bind: SocketAddr,
+        addr_table: AddrTable,
+    ) -> Result<Self, tokio::io::Error> {
+        let listener = TcpListener::bind(bind).await?;
+        addr_table
+            .write()
+            .unwrap()
+            .insert(id, listener.local_addr()?);
+        let (itx, irx) = mpsc::unbounded();
+        let (_, orx) = mpsc::unbounded();
+        let (shutdown, shutdown_rx) = Shutdown::new();
+        let proxy = Proxy::with_group(group, id, addr_table.clone());
+        tokio::spawn(named(
+            format!("proxy-session-{}", id),
+            proxy.clone().run(listener, itx, orx, shutdown_rx.clone()),
+        ));
+        let pending = Pending::default();
+        tokio::spawn(named(
+            format!("session-{}", id),
+            dispatch(irx, pending.clone(), shutdown_rx),
+        ));
+        Ok(Self {
+            id,
+            proxy,
+            addr_table,
+            pending,
+            next_id: AtomicU64::new(0),
+            admission: None,
+            retries: 0,
+            in_flight: AtomicUsize::new(0),
+            peak_in_flight: AtomicUsize::new(0),
+            _shutdown: shutdown,
+        })
+    }
+
+    // 最多 cap 个提案同时在途，其余按到达顺序排队
+    pub fn with_max_in_flight(mut self, cap: usize) -> Self {
+        self.admission = Some(Semaphore::new(cap.max(1)));
+        self
+    }
+
+    // 服务器没有在时限内回应时，重发提案和等待请求，最多重发 retries 次
+    pub fn with_retries(mut self, retries: usize) -> Self {
+        self.retries = retries;
+        self
+    }
+
+    pub fn id(&self) -> usize {
+        self.id
+    }
+
+    // 当前在途的提案数，不含排队中的
+    pub fn in_flight(&self) -> usize {
+        self.in_flight.load(Ordering::Relaxed)
+    }
+
+    // 会话启动以来同时在途的最大提案数
+    pub fn peak_in_flight(&self) -> usize {
+        self.peak_in_flight.load(Ordering::Relaxed)
+    }
+
+    // 向 server_id 提案并等到它学习到值，返回选定的值。可以同时调用多次，
+    // 设置了在途上限时先等到名额，timeout 从拿到名额后开始计算，每次重发重新计算
+    pub async fn propose(
+        &self,
+        server_id: usize,
+        value: ValueType,
+        timeout: Duration,
+    ) -> Result<ValueType, SessionError> {
+        let (chosen, _) = self.admit(server_id, value, timeout, false).await?;
+        Ok(chosen)
+    }
+
+    // 同 propose，另外带回选定该值的提案者的决策记录。
+    // 值不是由最后一次提案的服务器选定的时候，该服务器没有记录，返回 None
+    pub async fn propose_decision(
+        &self,
+        server_id: usize,
+        value: ValueType,
+        timeout: Duration,
+    ) -> Result<(ValueType, Option<DecisionRecord>), SessionError> {
+        self.admit(server_id, value, timeout, true).await
+    }
+
+    async fn admit(
+        &self,
+        server_id: usize,
+        value: ValueType,
+        timeout: Duration,
+        want_record: bool,
+    ) -> Result<(ValueType, Option<DecisionRecord>), SessionError> {
+        let _permit = match &self.admission {
+            Some(admission) => Some(admission.acquire().await),
+            None => None,
+        };
+        let in_flight = self.in_flight.fetch_add(1, Ordering::Relaxed) + 1;
+        self.peak_in_flight.fetch_max(in_flight, Ordering::Relaxed);
+        // 提案中途被丢弃时也要归还计数
+        let _in_flight = InFlight(&self.in_flight);
+        self.propose_admitted(server_id, value, timeout, want_record)
+            .await
+    }
+
+    async fn propose_admitted(
+        &self,
+        server_id: usize,
+        value: ValueType,
+        timeout: Duration,
+        want_record: bool,
+    ) -> Result<(ValueType, Option<DecisionRecord>), SessionError> {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let mut server_id = server_id;
+        let mut addr = None;
+        let wait = match want_record {
+            true => Request::WaitDecision,
+            false => Request::WaitQuery,
+        };
+        let mut redirects = 0;
+        let mut retries = 0;
+        loop {
+            // 先登记再发送，回应不会早于登记到达
+            let (tx, rx) = oneshot::channel();
+            self.pending
+                .lock()
+                .unwrap()
+                .entry(server_id)
+                .or_default()
+                .push(Waiter {
+                    id,
+                    want_record,
+                    tx,
+                });
+            let req = Request::Propose {
+                value,
+                priority: 0,
+                seq: None,
+            };
+            let sent = async {
+                self.send(server_id, addr, Datagram::Request(req)).await?;
+                self.send(server_id, addr, Datagram::Request(wait.clone()))
+                    .await
+            };
+            if sent.await.is_err() {
+                self.withdraw(server_id, id);
+                return Err(SessionError::Unreachable(server_id));
+            }
+            match tokio::time::timeout(timeout, rx).await {
+                Ok(Ok(Answer::Chosen(chosen, record))) => return Ok((chosen, record)),
+                // 按回应中的地址重新提案，提案者可能不在本地的地址表中
+                Ok(Ok(Answer::Redirect(leader, leader_addr))) => {
+                    if redirects == MAX_REDIRECTS {
+                        return Err(SessionError::TooManyRedirects);
+                    }
+                    redirects += 1;
+                    server_id = leader;
+                    addr = Some(leader_addr);
+                }
+                // 报文可能丢了，也可能服务器暂时没空，重发对 Paxos 无害
+                _ if retries < self.retries => {
+                    self.withdraw(server_id, id);
+                    retries += 1;
+                }
+                _ => {
+                    self.withdraw(server_id, id);
+                    return Err(SessionError::Timeout(server_id));
+                }
+            }
+        }
+    }
+
+    // 撤下 id 号提案在 server_id 那里的等待
+    fn withdraw(&self, server_id: usize, id: u64) {
+        if let Some(waiters) = self.pending.lock().unwrap().get_mut(&server_id) {
+            waiters.retain(|waiter| waiter.id != id);
+        }
+    }
+
+    async fn send(
+        &self,
+        server_id: usize,
+        addr: Option<SocketAddr>,
+        dgram: Datagram,
+    ) -> Result<(), crate::net_proxy::ProxyError> {
+        match addr {
+            Some(addr) => self.proxy.send_to(server_id, addr, &dgram).await,
+            None => self.proxy.send(server_id, &dgram).await,
+        }
+    }
+}
+
+// 丢弃会话时从地址表中撤下自己，不再用的 ID 不会一直留在表里
+impl Drop for ClientSession {
+    fn drop(&mut self) {
+        self.addr_table.write().unwrap().remove(&self.id);
+    }
+}
+
+struct InFlight<'a>(&'a AtomicUsize);
+
+impl Drop for InFlight<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+// 把服务器的回应交给在它那里等待同一种回应的所有提案
+async fn dispatch(mut inbox: Rx<Incoming>, pending: Pending, shutdown: Shutdown) {
+    loop {
+        let Incoming { src, dgram } = tokio::select! {
+            incoming = inbox.next() => match incoming {
+                Some(incoming) => incoming,
+                None => break,
+            },
+            _ = shutdown.wait() => break,
+        };
+        // (回应, 交给等待决策记录的提案还是其余的提案，None 时都交给)
+        let (resolved, for_record) = match dgram {
+            Datagram::Response(Response::Query { val: Some(val) })
+            | Datagram::Response(Response::AlreadyChosen { value: val }) => {
+                (Answer::Chosen(val, None), Some(false))
+            }
+            Datagram::Response(Response::Decision { value, record }) => {
+                (Answer::Chosen(value, record), Some(true))
+            }
+            Datagram::Response(Response::Redirect { leader, addr }) => {
+                (Answer::Redirect(leader, addr), None)
+            }
+            _ => continue,
+        };
+        let mut pending = pending.lock().unwrap();
+        let waiters = match pending.get_mut(&src) {
+            Some(waiters) => waiters,
+            None => continue,
+        };
+        let (ready, waiting) = std::mem::take(waiters).into_iter().partition(|waiter| {
+            for_record.is_none_or(|for_record| for_record == waiter.want_record)
+        });
+        *waiters = waiting;
+        for waiter in ready {
+            let _ = waiter.tx.send(resolved.clone());
+        }
+    }
+}
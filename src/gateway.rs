@@ -0,0 +1,198 @@
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::prelude::*;
+use tokio::stream::StreamExt;
+
+use crate::net_proxy::{Proxy, MAX_FRAME_LEN};
+use crate::paxos::proposal::{Datagram, Incoming, Request, Response};
+use crate::paxos::{Rx, ValueType};
+use crate::shutdown::Shutdown;
+
+/*
+一个最简单的 HTTP 网关，让 curl 或网页也能驱动集群：
+    POST /propose {"value":42}  向指定服务器提案
+    GET  /value                 查询指定服务器学习到的值，返回 {"value":42} 或 {"value":null}
+网关在地址表中以 GATEWAY_ID 的身份收发报文，与控制台的客户端 #0 互不干扰。
+*/
+
+pub const GATEWAY_ID: usize = usize::MAX;
+
+// 等待服务器应答的最长时间
+const ANSWER_TIMEOUT: Duration = Duration::from_millis(500);
+
+// 连接逐个处理，请求必须在这么久之内读完，闲置的客户端不能一直占着网关
+const READ_DEADLINE: Duration = Duration::from_millis(1000);
+
+// 请求行与每个报头的最大长度
+const MAX_LINE_LEN: usize = 8192;
+
+// 请求体与提案报文一样不超过 MAX_FRAME_LEN，长度由客户端给出，不能照单分配
+const MAX_BODY_LEN: usize = MAX_FRAME_LEN;
+
+// 读到的请求：方法、路径与请求体；出错时给出要回应的状态
+type Parsed = Result<(String, String, Vec<u8>), &'static str>;
+
+#[derive(Deserialize)]
+struct ProposeBody {
+    value: ValueType,
+}
+
+#[derive(Serialize)]
+struct ValueBody {
+    value: Option<ValueType>,
+}
+
+pub struct Gateway {
+    server_id: usize, // 请求都转给这台服务器
+    proxy: Arc<Proxy>,
+    inbox: Rx<Incoming>,
+}
+
+impl Gateway {
+    pub fn new(server_id: usize, proxy: Arc<Proxy>, inbox: Rx<Incoming>) -> Self {
+        Self {
+            server_id,
+            proxy,
+            inbox,
+        }
+    }
+
+    // 逐个处理 HTTP 连接，直到收到停机信号
+    pub async fn serve(mut self, mut listener: TcpListener, shutdown: Shutdown) {
+        loop {
+            tokio::select! {
+                accepted = listener.accept() => match accepted {
+                    Ok((stream, _)) => {
+                        let _ = self.handle(stream).await;
+                    }
+                    Err(_) => break,
+                },
+                _ = shutdown.wait() => break,
+            }
+        }
+    }
+
+    async fn handle(&mut self, stream: TcpStream) -> Result<(), tokio::io::Error> {
+        let mut stream = BufReader::new(stream);
+        let parsed = tokio::time::timeout(READ_DEADLINE, read_request(&mut stream)).await;
+        let (status, body) = match parsed {
+            Err(_) => ("408 Request Timeout", String::new()),
+            Ok(Err(e)) => return Err(e),
+            Ok(Ok(Err(status))) => (status, String::new()),
+            Ok(Ok(Ok((method, path, body)))) => match (method.as_str(), path.as_str()) {
+                ("POST", "/propose") => self.propose(&body).await,
+                ("GET", "/value") => self.value().await,
+                _ => ("404 Not Found", String::new()),
+            },
+        };
+        let response = format!(
+            "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            status,
+            body.len(),
+            body
+        );
+        stream.get_mut().write_all(response.as_bytes()).await
+    }
+
+    async fn propose(&mut self, body: &[u8]) -> (&'static str, String) {
+        let body = match std::str::from_utf8(body).map(crate::json::from_str::<ProposeBody>) {
+            Ok(Ok(body)) => body,
+            _ => return ("400 Bad Request", String::new()),
+        };
+        let req = Request::Propose {
+            value: body.value,
+            priority: 0,
+            seq: None,
+        };
+        match self
+            .proxy
+            .send(self.server_id, &Datagram::Request(req))
+            .await
+        {
+            Ok(()) => ("202 Accepted", String::new()),
+            Err(_) => ("502 Bad Gateway", String::new()),
+        }
+    }
+
+    async fn value(&mut self) -> (&'static str, String) {
+        // 丢掉之前超时后才到达的回应
+        while let Ok(Some(_)) = self.inbox.try_next() {}
+        let req = Datagram::Request(Request::Query);
+        if self.proxy.send(self.server_id, &req).await.is_err() {
+            return ("502 Bad Gateway", String::new());
+        }
+        let server_id = self.server_id;
+        let inbox = &mut self.inbox;
+        let answer = tokio::time::timeout(ANSWER_TIMEOUT, async {
+            while let Some(Incoming { src, dgram }) = inbox.next().await {
+                if let (true, Datagram::Response(Response::Query { val })) =
+                    (src == server_id, dgram)
+                {
+                    return Some(val);
+                }
+            }
+            None
+        })
+        .await;
+        match answer {
+            Ok(Some(value)) => (
+                "200 OK",
+                crate::json::to_string(&ValueBody { value }).unwrap(),
+            ),
+            _ => ("504 Gateway Timeout", String::new()),
+        }
+    }
+}
+
+// 读一行，超过 MAX_LINE_LEN 时返回 None
+async fn read_bounded_line(
+    stream: &mut BufReader<TcpStream>,
+) -> Result<Option<String>, tokio::io::Error> {
+    let mut line = String::new();
+    let read = stream
+        .take(MAX_LINE_LEN as u64)
+        .read_line(&mut line)
+        .await?;
+    if read == MAX_LINE_LEN && !line.ends_with('\n') {
+        return Ok(None);
+    }
+    Ok(Some(line))
+}
+
+async fn read_request(stream: &mut BufReader<TcpStream>) -> Result<Parsed, tokio::io::Error> {
+    let request_line = match read_bounded_line(stream).await? {
+        Some(line) => line,
+        None => return Ok(Err("414 URI Too Long")),
+    };
+
+    // 只关心 Content-Length，其余报头忽略
+    let mut content_length = 0;
+    loop {
+        let header = match read_bounded_line(stream).await? {
+            Some(header) => header,
+            None => return Ok(Err("431 Request Header Fields Too Large")),
+        };
+        if header.trim().is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.trim().eq_ignore_ascii_case("content-length") {
+                content_length = match value.trim().parse() {
+                    Ok(len) if len > MAX_BODY_LEN => return Ok(Err("413 Payload Too Large")),
+                    Ok(len) => len,
+                    Err(_) => return Ok(Err("400 Bad Request")),
+                };
+            }
+        }
+    }
+    let mut body = vec![0u8; content_length];
+    stream.read_exact(&mut body).await?;
+
+    let mut parts = request_line.split_whitespace();
+    let method = parts.next().unwrap_or_default().to_string();
+    let path = parts.next().unwrap_or_default().to_string();
+    Ok(Ok((method, path, body)))
+}
@@ -1,4 +1,14 @@
+pub mod cli;
+pub mod client;
+#[cfg(feature = "http")]
+pub mod gateway;
+pub mod json;
 pub mod shell;
 
-mod net_proxy;
-mod paxos;
+pub mod net_proxy;
+pub mod paxos;
+pub mod prelude;
+pub mod shutdown;
+pub mod task_name;
+#[cfg(feature = "ws")]
+pub mod ws;
@@ -1,10 +1,92 @@
-use shell::Console;
+// 二进制只是库的一层外壳，所有模块都来自 paxos 库，测试与二进制用的是同一份代码
+use paxos::cli::Cli;
+use paxos::prelude::{Console, ValueType, Verbosity};
+use rand::seq::SliceRandom;
+use std::time::Duration;
 
-mod net_proxy;
-mod paxos;
-pub mod shell;
+const DEMO_SERVERS: usize = 5;
+
+// 各服务器同时提出不同的值，等到全部学习后报告达成的共识
+fn demo(verbosity: Verbosity, seed: Option<u64>) -> bool {
+    let mut console = console(verbosity, seed);
+    console.start_servers(DEMO_SERVERS, 9527);
+    let mut values: Vec<ValueType> = (1..=20).collect();
+    values.shuffle(console.rng());
+    for (i, &value) in values.iter().enumerate() {
+        console.propose(i % DEMO_SERVERS + 1, value);
+    }
+
+    let answers: Vec<_> = (1..=DEMO_SERVERS)
+        .map(|id| console.query_wait(id, Duration::from_secs(5)))
+        .collect();
+    let agreed = match answers[0] {
+        Some(value) if answers.iter().all(|&answer| answer == Some(value)) => value,
+        _ => {
+            println!("Consensus wasn't reached: {:?}", answers);
+            return false;
+        }
+    };
+    match console.who(1) {
+        Some(chosen) => println!(
+            "Consensus reached: value {} proposed by server #{}.",
+            agreed,
+            chosen.proposer()
+        ),
+        None => println!("Consensus reached: value {}.", agreed),
+    }
+    console.exit();
+    true
+}
+
+fn console(verbosity: Verbosity, seed: Option<u64>) -> Console {
+    let mut console = Console::new();
+    console.set_verbosity(verbosity);
+    if let Some(seed) = seed {
+        console.set_seed(seed);
+    }
+    console
+}
 
 fn main() {
-    let console = Console::new();
-    console.run();
+    let mut args: Vec<String> = std::env::args().skip(1).collect();
+    // --quiet 只留下控制台的输出与最终结果，--verbose 连每个连接也记录
+    let mut verbosity = Verbosity::Normal;
+    args.retain(|arg| match arg.as_str() {
+        "--quiet" => {
+            verbosity = Verbosity::Quiet;
+            false
+        }
+        "--verbose" => {
+            verbosity = Verbosity::Verbose;
+            false
+        }
+        _ => true,
+    });
+    // --seed <n> 让整个运行可以复现
+    let seed = match args.iter().position(|arg| arg == "--seed") {
+        Some(i) => match args.get(i + 1).map(|seed| seed.parse::<u64>()) {
+            Some(Ok(seed)) => {
+                args.drain(i..i + 2);
+                Some(seed)
+            }
+            _ => {
+                println!("error: --seed expects a number");
+                std::process::exit(2);
+            }
+        },
+        None => None,
+    };
+    if args.iter().any(|arg| arg == "--demo") {
+        std::process::exit(if demo(verbosity, seed) { 0 } else { 1 });
+    }
+    // node 与 propose 子命令用于分布式部署，其余情况进入交互式控制台
+    match Cli::parse(&args) {
+        Some(Ok(cli)) => std::process::exit(cli.run()),
+        Some(Err(e)) => {
+            println!("error: {}", e);
+            std::process::exit(2);
+        }
+        None => {}
+    }
+    console(verbosity, seed).run();
 }
@@ -1,69 +1,734 @@
+use futures::stream::FuturesUnordered;
+use net2::TcpBuilder;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
 use std::collections::HashMap;
+use std::fmt;
 use std::net::SocketAddr;
-use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+use tokio::io::{self, AsyncRead, BufReader};
 use tokio::net::{TcpListener, TcpStream};
 use tokio::prelude::*;
 use tokio::stream::StreamExt;
 
-use crate::paxos::proposal::{Datagram, Incoming, Outgoing};
+use crate::paxos::node::LogSink;
+use crate::paxos::proposal::{self, Datagram, Incoming, Outgoing, DEFAULT_GROUP, PROTOCOL_VERSION};
 use crate::paxos::*;
+use crate::shutdown::Shutdown;
+use crate::task_name::named;
+
+// 所有代理共享的 ID -> 地址表，迁移结点时只需更新一处
+pub type AddrTable = Arc<RwLock<HashMap<usize, SocketAddr>>>;
+
+// 单个报文数据部分的最大长度
+pub const MAX_FRAME_LEN: usize = proposal::DECODE_LIMIT as usize;
+
+// 报文头：版本 1 字节，组、来源与数据长度各 8 字节
+pub const FRAME_HEADER_LEN: usize = 1 + 8 + 8 + 8;
+
+// 读到报文头后，数据部分必须在这么久之内到达
+pub const DEFAULT_READ_DEADLINE_MS: u64 = 1000;
+
+// 发往同一目的地最多同时有几个连接在发送
+pub const DEFAULT_SEND_CONCURRENCY: usize = 4;
+
+// 最多同时服务的接入连接数，超出的连接直接关闭
+pub const DEFAULT_MAX_INFLOWS: usize = 1024;
+
+// 一个批次最多尝试发送几次，第 n 次重试前等待 SEND_BACKOFF * 2^(n-1)
+const SEND_ATTEMPTS: u32 = 3;
+const SEND_BACKOFF: Duration = Duration::from_millis(20);
+
+// 某个方向上最近一次与某个结点之间连接的情况。每条消息都走新连接，因此发送方向反映
+// 最近一次发送：正在建立、已经写出或者失败；接收方向反映最近一次读到完整的报文还是出错
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnState {
+    Connecting,
+    Connected,
+    Failed,
+}
+
+impl fmt::Display for ConnState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Connecting => write!(f, "connecting"),
+            Self::Connected => write!(f, "connected"),
+            Self::Failed => write!(f, "failed"),
+        }
+    }
+}
+
+// 与某个结点之间的连接表项，字节数包括报文头。还没往某个方向收发过时该方向的状态为空
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct PeerConn {
+    pub outbound: Option<ConnState>,
+    pub inbound: Option<ConnState>,
+    pub bytes_sent: u64,
+    pub bytes_received: u64,
+}
+
+// 监听与收发连接的套接字选项，构造代理时给定
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SocketOptions {
+    pub backlog: i32,        // 等待 accept 的连接队列长度
+    pub reuse_address: bool, // SO_REUSEADDR：端口上还有 TIME_WAIT 的连接时也能立即重新监听
+    pub nodelay: bool,       // TCP_NODELAY：关掉 Nagle 算法，小报文立即发出
+}
+
+// 与 tokio 自带的 bind 相同
+impl Default for SocketOptions {
+    fn default() -> Self {
+        Self {
+            backlog: 1024,
+            reuse_address: true,
+            nodelay: false,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ProxyError {
+    Io(tokio::io::Error),
+    UnknownPeer(usize),   // 地址表中没有这个 ID
+    FrameTooLarge(usize), // 报文长度超过 MAX_FRAME_LEN
+    Decode(bincode::Error),
+    VersionMismatch(u8),            // 对方的报文版本
+    WrongGroup(usize),              // 报文属于另一个组
+    Timeout,                        // 报文头之后数据迟迟不到
+    TruncatedHeader(usize),         // 报文头只到了这么多字节对方就关闭了
+    TruncatedPayload(usize, usize), // (已读, 应有)，数据部分没读完对方就关闭了
+}
+
+impl fmt::Display for ProxyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "io error: {}", e),
+            Self::UnknownPeer(id) => write!(f, "unknown peer #{}", id),
+            Self::FrameTooLarge(len) => write!(f, "frame of {} bytes is too large", len),
+            Self::Decode(e) => write!(f, "decode failed: {}", e),
+            Self::VersionMismatch(version) => write!(
+                f,
+                "version mismatch: expect {}, got {}",
+                PROTOCOL_VERSION, version
+            ),
+            Self::WrongGroup(group) => write!(f, "frame belongs to group {}", group),
+            Self::Timeout => write!(f, "frame payload timed out"),
+            Self::TruncatedHeader(read) => write!(
+                f,
+                "connection closed after {} of {} header bytes",
+                read, FRAME_HEADER_LEN
+            ),
+            Self::TruncatedPayload(read, len) => write!(
+                f,
+                "connection closed after {} of {} payload bytes",
+                read, len
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ProxyError {}
+
+impl From<tokio::io::Error> for ProxyError {
+    fn from(e: tokio::io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+impl From<bincode::Error> for ProxyError {
+    fn from(e: bincode::Error) -> Self {
+        Self::Decode(e)
+    }
+}
 
 #[derive(Debug)]
 pub struct Proxy {
+    group: usize,
     local_id: usize,
-    id2addr: HashMap<usize, SocketAddr>,
+    id2addr: AddrTable,            // 对外公布的地址，其他结点据此发送与回复
+    bind_addr: Option<SocketAddr>, // 实际监听的地址，为空时就监听公布的地址
+    socket_options: SocketOptions,
+    batch_window: AtomicU64,                // 毫秒
+    read_deadline: AtomicU64,               // 毫秒
+    send_concurrency: AtomicUsize,          // 每个目的地的发送并发数
+    drop_rate: AtomicU64,                   // 发往其他结点的报文被丢弃的千分比
+    drop_dice: Mutex<StdRng>,               // 给定种子后丢弃哪些报文是确定的
+    dropped: Mutex<HashMap<usize, u64>>,    // 各目的地被丢弃的报文数
+    max_inflows: AtomicUsize,               // 同时服务的接入连接上限
+    rejected: AtomicU64,                    // 因超出上限而被关闭的连接数
+    retries: AtomicU64,                     // 发送失败后重试的次数
+    active_inflows: AtomicUsize,            // 正在服务的接入连接数
+    log_connections: AtomicBool,            // 记录每个接入连接的建立与关闭
+    protocol_errors: AtomicU64,             // 因报文出错而断开的接入连接数
+    queued: AtomicUsize,                    // 已从结点取走、还没有写完或丢弃的报文数
+    quiet: AtomicBool,                      // 不输出任何日志
+    log_sink: Mutex<LogSink>,               // 日志去向，未设置时写到标准输出
+    conns: Mutex<HashMap<usize, PeerConn>>, // 与各结点之间的连接表，只在收发过报文后出现
 }
 
 impl Proxy {
-    pub fn new(local_id: usize, id2addr: HashMap<usize, SocketAddr>) -> Arc<Self> {
-        let proxy = Self { local_id, id2addr };
+    pub fn new(local_id: usize, id2addr: AddrTable) -> Arc<Self> {
+        Self::with_group(DEFAULT_GROUP, local_id, id2addr)
+    }
+
+    // 每个组有各自的地址表，代理只收发本组的报文
+    pub fn with_group(group: usize, local_id: usize, id2addr: AddrTable) -> Arc<Self> {
+        Self::with_bind_addr(group, local_id, id2addr, None)
+    }
+
+    // 在 NAT 之后或者监听 0.0.0.0 时，监听地址与地址表中公布的地址不同
+    pub fn with_bind_addr(
+        group: usize,
+        local_id: usize,
+        id2addr: AddrTable,
+        bind_addr: Option<SocketAddr>,
+    ) -> Arc<Self> {
+        Self::with_socket_options(
+            group,
+            local_id,
+            id2addr,
+            bind_addr,
+            SocketOptions::default(),
+        )
+    }
+
+    pub fn with_socket_options(
+        group: usize,
+        local_id: usize,
+        id2addr: AddrTable,
+        bind_addr: Option<SocketAddr>,
+        socket_options: SocketOptions,
+    ) -> Arc<Self> {
+        let proxy = Self {
+            group,
+            local_id,
+            id2addr,
+            bind_addr,
+            socket_options,
+            batch_window: AtomicU64::new(0),
+            read_deadline: AtomicU64::new(DEFAULT_READ_DEADLINE_MS),
+            send_concurrency: AtomicUsize::new(DEFAULT_SEND_CONCURRENCY),
+            drop_rate: AtomicU64::new(0),
+            drop_dice: Mutex::new(StdRng::seed_from_u64(0)),
+            dropped: Mutex::default(),
+            max_inflows: AtomicUsize::new(DEFAULT_MAX_INFLOWS),
+            rejected: AtomicU64::new(0),
+            retries: AtomicU64::new(0),
+            active_inflows: AtomicUsize::new(0),
+            log_connections: AtomicBool::new(false),
+            protocol_errors: AtomicU64::new(0),
+            queued: AtomicUsize::new(0),
+            quiet: AtomicBool::new(false),
+            log_sink: Mutex::default(),
+            conns: Mutex::new(HashMap::new()),
+        };
         Arc::new(proxy)
     }
 
+    fn addr_of(&self, id: usize) -> Option<SocketAddr> {
+        self.id2addr.read().unwrap().get(&id).copied()
+    }
+
+    pub fn socket_options(&self) -> SocketOptions {
+        self.socket_options
+    }
+
+    pub async fn bind(&self) -> Result<TcpListener, tokio::io::Error> {
+        let addr = self
+            .bind_addr
+            .or_else(|| self.addr_of(self.local_id))
+            .unwrap();
+        let builder = match addr {
+            SocketAddr::V4(_) => TcpBuilder::new_v4()?,
+            SocketAddr::V6(_) => TcpBuilder::new_v6()?,
+        };
+        builder.reuse_address(self.socket_options.reuse_address)?;
+        let listener = builder.bind(addr)?.listen(self.socket_options.backlog)?;
+        listener.set_nonblocking(true)?;
+        TcpListener::from_std(listener)
+    }
+
+    // 按套接字选项连接 addr
+    pub async fn connect(&self, addr: SocketAddr) -> Result<TcpStream, tokio::io::Error> {
+        let stream = TcpStream::connect(addr).await?;
+        self.configure(&stream)?;
+        Ok(stream)
+    }
+
+    // 发出的与接受的连接都按套接字选项设置
+    pub fn configure(&self, stream: &TcpStream) -> Result<(), tokio::io::Error> {
+        stream.set_nodelay(self.socket_options.nodelay)
+    }
+
+    // 在已绑定的 listener 上运行直到收到停机信号，停机后不再接受新连接，
+    // 并等到收发任务都退出后才返回
     pub async fn run(
         self: Arc<Self>,
+        mut listener: TcpListener,
         tx: Tx<Incoming>,
         rx: Rx<Outgoing>,
+        shutdown: Shutdown,
     ) -> Result<(), tokio::io::Error> {
-        let mut listener = TcpListener::bind(self.id2addr[&self.local_id]).await?;
-        tokio::spawn(self.clone().serve_outflow(rx));
-        while let Some(socket) = listener.incoming().next().await {
-            tokio::spawn(Self::serve_inflow(socket?, tx.clone()));
+        let name = format!("proxy-outflow-{}", self.local_id);
+        let outflow = tokio::spawn(named(
+            name,
+            self.clone().serve_outflow(rx, tx.clone(), shutdown.clone()),
+        ));
+        let mut inflows = FuturesUnordered::new();
+        let mut incoming = listener.incoming();
+        let result = loop {
+            tokio::select! {
+                socket = incoming.next() => match socket {
+                    // 每条消息都走新连接，关掉多出的连接只会丢掉消息，协议本身容忍丢包
+                    Some(Ok(socket)) if inflows.len() >= self.max_inflows() => {
+                        self.rejected.fetch_add(1, Ordering::Relaxed);
+                        drop(socket);
+                    }
+                    Some(Ok(socket)) => {
+                        if let Err(e) = self.configure(&socket) {
+                            self.log(format_args!(
+                                "error: proxy #{} can't configure accepted connection: {}",
+                                self.local_id, e
+                            ));
+                            continue;
+                        }
+                        let name = format!("proxy-inflow-{}", self.local_id);
+                        let inflow = self.clone().serve_inflow(socket, tx.clone(), shutdown.clone());
+                        inflows.push(tokio::spawn(named(name, inflow)));
+                    }
+                    Some(Err(e)) => break Err(e),
+                    None => break Ok(()),
+                },
+                // 回收已经结束的连接
+                Some(_) = inflows.next() => {}
+                _ = shutdown.wait() => break Ok(()),
+            }
+        };
+        while inflows.next().await.is_some() {}
+        let _ = outflow.await;
+        result
+    }
+
+    // 从连接中读出一个完整的报文，一个连接上可以连续读多个
+    pub async fn read_incoming<R: AsyncRead + Unpin>(
+        &self,
+        socket: &mut R,
+    ) -> Result<(usize, Datagram), ProxyError> {
+        // 一个字节都没读到就关闭是正常结束，读到一半才关闭则是报文被截断
+        let mut header = [0u8; FRAME_HEADER_LEN];
+        let mut read = 0;
+        while read < FRAME_HEADER_LEN {
+            match socket.read(&mut header[read..]).await? {
+                0 if read == 0 => return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into()),
+                0 => return Err(ProxyError::TruncatedHeader(read)),
+                n => read += n,
+            }
+        }
+        let field = |at: usize| u64::from_be_bytes(header[at..at + 8].try_into().unwrap()) as usize;
+        let version = header[0];
+        if version != PROTOCOL_VERSION {
+            return Err(ProxyError::VersionMismatch(version));
+        }
+        let group = field(1);
+        if group != self.group {
+            return Err(ProxyError::WrongGroup(group));
+        }
+        let src = field(9);
+        if self.addr_of(src).is_none() {
+            return Err(ProxyError::UnknownPeer(src));
+        }
+        let len = field(17);
+        if len > MAX_FRAME_LEN {
+            return Err(ProxyError::FrameTooLarge(len));
+        }
+        // 发完报文头就停住的连接不能一直占着读任务
+        let mut buf = vec![0u8; len];
+        let payload = async {
+            let mut read = 0;
+            while read < len {
+                match socket.read(&mut buf[read..]).await? {
+                    0 => return Err(ProxyError::TruncatedPayload(read, len)),
+                    n => read += n,
+                }
+            }
+            Ok(())
+        };
+        tokio::time::timeout(self.read_deadline(), payload)
+            .await
+            .map_err(|_| ProxyError::Timeout)??;
+        let decoded = proposal::decode(&buf)?;
+        self.update_conn(src, |conn| {
+            conn.inbound = Some(ConnState::Connected);
+            conn.bytes_received += (FRAME_HEADER_LEN + len) as u64;
+        });
+        Ok((src, decoded))
+    }
+
+    // 每条消息都由发送方新建连接送达，连接中途断开只会丢掉这一条，
+    // 之后的消息走新连接，因此接收方不需要主动重连
+    async fn serve_inflow(
+        self: Arc<Self>,
+        socket: TcpStream,
+        tx: Tx<Incoming>,
+        shutdown: Shutdown,
+    ) {
+        self.active_inflows.fetch_add(1, Ordering::Relaxed);
+        // 带缓冲读取，多个报文挤在同一个 TCP 段里时也只需少量系统调用
+        let mut socket = BufReader::new(socket);
+        // 读到第一个报文才知道对方是谁
+        let mut peer = None;
+        let mut received = 0;
+        let reason = loop {
+            let (src, dgram) = tokio::select! {
+                read = self.read_incoming(&mut socket) => match read {
+                    Ok(read) => read,
+                    // 对方发完后正常关闭
+                    Err(ProxyError::Io(e)) if e.kind() == io::ErrorKind::UnexpectedEof => {
+                        break "eof".to_string()
+                    }
+                    // 报文出错不能悄悄丢掉，否则截断、版本不符之类的问题无从查起
+                    Err(e) => {
+                        self.protocol_errors.fetch_add(1, Ordering::Relaxed);
+                        if let Some(src) = peer {
+                            self.update_conn(src, |conn| conn.inbound = Some(ConnState::Failed));
+                        }
+                        let peer = peer.map_or("unknown peer".to_string(), |src| format!("#{}", src));
+                        self.log(format_args!(
+                            "error: proxy #{} dropped connection from {}: {}",
+                            self.local_id, peer, e
+                        ));
+                        break e.to_string();
+                    }
+                },
+                // 停机时读了一半的报文直接丢弃
+                _ = shutdown.wait() => break "shutdown".to_string(),
+            };
+            if peer.is_none() && self.log_connections() {
+                self.log(format_args!(
+                    "Proxy #{} opened connection from #{}",
+                    self.local_id, src
+                ));
+            }
+            peer = Some(src);
+            received += 1;
+            // 结点已经停机（例如正在迁移），丢弃剩余消息
+            if tx.unbounded_send(Incoming { src, dgram }).is_err() {
+                break "node stopped".to_string();
+            }
+        };
+        self.active_inflows.fetch_sub(1, Ordering::Relaxed);
+        if self.log_connections() {
+            let peer = peer.map_or("unknown peer".to_string(), |src| format!("#{}", src));
+            self.log(format_args!(
+                "Proxy #{} closed connection from {} after {} messages: {}",
+                self.local_id, peer, received, reason
+            ));
         }
+    }
+
+    pub async fn send(&self, dst: usize, dgram: &Datagram) -> Result<(), ProxyError> {
+        self.send_batch(dst, std::slice::from_ref(dgram)).await
+    }
+
+    // 多个报文拼在一起，用一个连接一次写出
+    pub async fn send_batch(&self, dst: usize, dgrams: &[Datagram]) -> Result<(), ProxyError> {
+        // 每次发送时查表，这样迁移后的结点也能收到消息
+        let addr = self.addr_of(dst).ok_or(ProxyError::UnknownPeer(dst))?;
+        self.send_batch_to(dst, addr, dgrams).await
+    }
+
+    // 不查地址表，直接发往 addr，例如按重定向给出的地址发送；连接仍记在 dst 名下
+    pub async fn send_to(
+        &self,
+        dst: usize,
+        addr: SocketAddr,
+        dgram: &Datagram,
+    ) -> Result<(), ProxyError> {
+        self.send_batch_to(dst, addr, std::slice::from_ref(dgram))
+            .await
+    }
+
+    async fn send_batch_to(
+        &self,
+        dst: usize,
+        addr: SocketAddr,
+        dgrams: &[Datagram],
+    ) -> Result<(), ProxyError> {
+        self.update_conn(dst, |conn| conn.outbound = Some(ConnState::Connecting));
+        let result = self.write_batch(addr, dgrams).await;
+        self.update_conn(dst, |conn| match result {
+            Ok(written) => {
+                conn.outbound = Some(ConnState::Connected);
+                conn.bytes_sent += written as u64;
+            }
+            Err(_) => conn.outbound = Some(ConnState::Failed),
+        });
+        result.map(|_| ())
+    }
+
+    // 返回写出的字节数
+    async fn write_batch(
+        &self,
+        addr: SocketAddr,
+        dgrams: &[Datagram],
+    ) -> Result<usize, ProxyError> {
+        let mut buf = Vec::new();
+        for dgram in dgrams {
+            buf.extend_from_slice(&dgram.encode(self.group, self.local_id));
+        }
+        let mut stream = self.connect(addr).await?;
+        stream.write_all(&buf).await?;
+        Ok(buf.len())
+    }
+
+    fn update_conn(&self, peer: usize, update: impl FnOnce(&mut PeerConn)) {
+        let mut conns = self.conns.lock().unwrap();
+        update(conns.entry(peer).or_default());
+    }
+
+    // 与各结点之间的连接表
+    pub fn conns(&self) -> HashMap<usize, PeerConn> {
+        self.conns.lock().unwrap().clone()
+    }
+
+    // 发往同一目的地的报文最多攒这么久再一起发出，0 表示只合并已经排队的报文
+    pub fn set_batch_window(&self, window_ms: u64) {
+        self.batch_window.store(window_ms, Ordering::Relaxed);
+    }
+
+    pub fn batch_window(&self) -> Duration {
+        Duration::from_millis(self.batch_window.load(Ordering::Relaxed))
+    }
+
+    pub fn set_read_deadline(&self, deadline_ms: u64) {
+        self.read_deadline.store(deadline_ms, Ordering::Relaxed);
+    }
+
+    pub fn read_deadline(&self) -> Duration {
+        Duration::from_millis(self.read_deadline.load(Ordering::Relaxed))
+    }
+
+    // 至少为 1，为 1 时同一目的地的批次按顺序到达。新的设置对已经在发送的批次不起作用
+    pub fn set_send_concurrency(&self, concurrency: usize) {
+        self.send_concurrency
+            .store(concurrency.max(1), Ordering::Relaxed);
+    }
+
+    pub fn send_concurrency(&self) -> usize {
+        self.send_concurrency.load(Ordering::Relaxed)
+    }
+
+    // 至少为 1，调低时已经建立的连接不受影响
+    pub fn set_max_inflows(&self, max: usize) {
+        self.max_inflows.store(max.max(1), Ordering::Relaxed);
+    }
+
+    pub fn max_inflows(&self) -> usize {
+        self.max_inflows.load(Ordering::Relaxed)
+    }
+
+    pub fn rejected(&self) -> u64 {
+        self.rejected.load(Ordering::Relaxed)
+    }
+
+    pub fn retries(&self) -> u64 {
+        self.retries.load(Ordering::Relaxed)
+    }
+
+    pub fn active_inflows(&self) -> usize {
+        self.active_inflows.load(Ordering::Relaxed)
+    }
+
+    // 每条消息都走新连接，打开后日志会很多，默认关闭
+    pub fn set_log_connections(&self, enabled: bool) {
+        self.log_connections.store(enabled, Ordering::Relaxed);
+    }
+
+    pub fn log_connections(&self) -> bool {
+        self.log_connections.load(Ordering::Relaxed)
+    }
+
+    // 因报文出错（截断、版本不符等）而断开的接入连接数，正常关闭的不算
+    pub fn protocol_errors(&self) -> u64 {
+        self.protocol_errors.load(Ordering::Relaxed)
+    }
+
+    // 发往其他结点、还在攒批次、排队或发送中的报文数。发给自己的直接投递，不算在内
+    pub fn queued(&self) -> usize {
+        self.queued.load(Ordering::Relaxed)
+    }
+
+    // 与结点日志一样，之后的日志都写到 sink
+    pub fn set_log_sink(&self, sink: Box<dyn std::io::Write + Send>) {
+        self.log_sink.lock().unwrap().set(sink);
+    }
+
+    // 追加写到 path，文件不存在时创建
+    pub fn set_log_file(&self, path: impl AsRef<std::path::Path>) -> std::io::Result<()> {
+        let file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)?;
+        self.set_log_sink(Box::new(file));
         Ok(())
     }
 
-    pub async fn read_incoming(
-        socket: &mut TcpStream,
-    ) -> Result<(usize, Datagram), tokio::io::Error> {
-        let mut buf = vec![0u8; 512];
-        let src = socket.read_u64().await?;
-        let src = src as usize;
-        let len = socket.read_u64().await? as usize;
-        socket.read_exact(&mut buf[..len]).await?;
-        let decoded: Datagram = bincode::deserialize(&buf[..len]).unwrap();
-        Ok((src, decoded))
+    pub fn set_quiet(&self, quiet: bool) {
+        self.quiet.store(quiet, Ordering::Relaxed);
     }
 
-    async fn serve_inflow(mut socket: TcpStream, tx: Tx<Incoming>) {
-        while let Ok((src, dgram)) = Self::read_incoming(&mut socket).await {
-            tx.unbounded_send(Incoming { src, dgram }).unwrap();
+    pub fn quiet(&self) -> bool {
+        self.quiet.load(Ordering::Relaxed)
+    }
+
+    fn log(&self, args: fmt::Arguments) {
+        if self.quiet() {
+            return;
+        }
+        self.log_sink.lock().unwrap().write_line(args);
+    }
+
+    // 连接、写入任何一步出错都退避后整批重发。对方可能已经收到了一部分，
+    // 重复的报文由 Paxos 协议本身容忍；最终失败则记录后丢弃
+    async fn send_with_retry(&self, dst: usize, dgrams: &[Datagram]) {
+        let mut backoff = SEND_BACKOFF;
+        for attempt in 1..=SEND_ATTEMPTS {
+            let e = match self.send_batch(dst, dgrams).await {
+                Ok(()) => return,
+                // 地址表里没有的目的地重试也没有用
+                Err(e @ ProxyError::UnknownPeer(_)) => e,
+                Err(_) if attempt < SEND_ATTEMPTS => {
+                    self.retries.fetch_add(1, Ordering::Relaxed);
+                    tokio::time::delay_for(backoff).await;
+                    backoff *= 2;
+                    continue;
+                }
+                Err(e) => e,
+            };
+            let kinds: Vec<&str> = dgrams.iter().map(Datagram::kind).collect();
+            self.log(format_args!(
+                "Proxy #{} dropped {:?} to #{}: {}",
+                self.local_id, kinds, dst, e
+            ));
+            return;
+        }
+    }
+
+    // 模拟丢包，per_mille 为 0 时关闭。重新设定时计数清零
+    pub fn set_drop_rate(&self, per_mille: u64, seed: u64) {
+        *self.drop_dice.lock().unwrap() = StdRng::seed_from_u64(seed);
+        self.dropped.lock().unwrap().clear();
+        self.drop_rate.store(per_mille, Ordering::Relaxed);
+    }
+
+    // 各目的地被丢弃的报文数
+    pub fn dropped(&self) -> HashMap<usize, u64> {
+        self.dropped.lock().unwrap().clone()
+    }
+
+    pub fn dropped_total(&self) -> u64 {
+        self.dropped.lock().unwrap().values().sum()
+    }
+
+    fn should_drop(&self, dst: usize) -> bool {
+        let rate = self.drop_rate.load(Ordering::Relaxed);
+        if rate == 0 || self.drop_dice.lock().unwrap().gen_range(0..1000) >= rate {
+            return false;
+        }
+        *self.dropped.lock().unwrap().entry(dst).or_default() += 1;
+        true
+    }
+
+    // 发给自己的报文直接交给 tx，不经过 TCP
+    async fn serve_outflow(
+        self: Arc<Self>,
+        mut rx: Rx<Outgoing>,
+        tx: Tx<Incoming>,
+        shutdown: Shutdown,
+    ) {
+        // 每个目的地一个发送队列，慢的结点只会拖住自己的队列
+        let mut queues: HashMap<usize, Tx<Vec<Datagram>>> = HashMap::new();
+        loop {
+            let first = tokio::select! {
+                outgoing = rx.next() => match outgoing {
+                    Some(outgoing) => outgoing,
+                    None => break,
+                },
+                _ = shutdown.wait() => break,
+            };
+            // 攒批次期间也算在排队，分组后再换成实际发往各目的地的报文数
+            self.queued.fetch_add(1, Ordering::Relaxed);
+            let mut batch = vec![first];
+            let window = self.batch_window();
+            if window > Duration::from_millis(0) {
+                let _ = tokio::time::timeout(window, async {
+                    while let Some(outgoing) = rx.next().await {
+                        self.queued.fetch_add(1, Ordering::Relaxed);
+                        batch.push(outgoing);
+                    }
+                })
+                .await;
+            }
+            while let Ok(Some(outgoing)) = rx.try_next() {
+                self.queued.fetch_add(1, Ordering::Relaxed);
+                batch.push(outgoing);
+            }
+            let collected = batch.len();
+
+            // 按目的地分组。同一批次里的报文按发送顺序写在一个连接上，
+            // 批次之间在 send_concurrency 大于 1 时并发发出，到达的先后不一定
+            let mut per_dst: HashMap<usize, Vec<Datagram>> = HashMap::new();
+            for Outgoing { dst, dgram } in batch {
+                for id in dst {
+                    if id == self.local_id {
+                        let src = self.local_id;
+                        let _ = tx.unbounded_send(Incoming {
+                            src,
+                            dgram: dgram.clone(),
+                        });
+                    } else if !self.should_drop(id) {
+                        per_dst.entry(id).or_default().push(dgram.clone());
+                    }
+                }
+            }
+            let sending: usize = per_dst.values().map(Vec::len).sum();
+            self.queued.fetch_add(sending, Ordering::Relaxed);
+            self.queued.fetch_sub(collected, Ordering::Relaxed);
+            for (id, dgrams) in per_dst {
+                let queue = queues.entry(id).or_insert_with(|| {
+                    let (tx, rx) = futures::channel::mpsc::unbounded();
+                    let name = format!("proxy-send-{}-to-{}", self.local_id, id);
+                    tokio::spawn(named(name, self.clone().serve_destination(id, rx)));
+                    tx
+                });
+                let _ = queue.unbounded_send(dgrams);
+            }
         }
     }
 
-    async fn serve_outflow(self: Arc<Self>, mut rx: Rx<Outgoing>) {
-        while let Some(Outgoing { dst, dgram }) = rx.next().await {
-            dst.iter().for_each(|id| {
-                let addr = self.id2addr[id];
-                let dgram = dgram.clone();
-                let local_id = self.local_id;
-                let send_task = async move {
-                    let mut stream = TcpStream::connect(addr).await.unwrap();
-                    let buf = dgram.encode_with_src(local_id);
-                    stream.write_all(&buf).await.unwrap();
-                };
-                tokio::spawn(send_task);
-            });
+    // 发出一个目的地的批次，同时在发送的批次不超过 send_concurrency。各批次走各自的连接，
+    // 并发时后发的可能先到，协议本身容忍乱序
+    async fn serve_destination(self: Arc<Self>, dst: usize, mut rx: Rx<Vec<Datagram>>) {
+        let mut sending = FuturesUnordered::new();
+        loop {
+            let has_room = sending.len() < self.send_concurrency();
+            tokio::select! {
+                dgrams = rx.next(), if has_room => match dgrams {
+                    Some(dgrams) => {
+                        let proxy = self.clone();
+                        // 重试后仍然失败相当于丢包，由 Paxos 协议本身容忍
+                        sending.push(async move {
+                            proxy.send_with_retry(dst, &dgrams).await;
+                            proxy.queued.fetch_sub(dgrams.len(), Ordering::Relaxed);
+                        });
+                    }
+                    None => break,
+                },
+                Some(_) = sending.next() => {}
+            }
         }
+        while sending.next().await.is_some() {}
     }
 }
@@ -0,0 +1,179 @@
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::fmt::Debug;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use super::node::{NodeControl, PRIORITY_BOOST_MS};
+use super::seq_num::SequenceNumber;
+
+// 提案序号的生成方式。floor 是结点见过的最高序号，实现可以据此保证新序号高于它
+pub trait BallotStrategy: Debug + Send + Sync {
+    fn next(&mut self, floor: Option<SequenceNumber>) -> SequenceNumber;
+
+    // 下一次 next 会给出的序号，没有副作用。依赖时钟的实现只能给出此刻的估计
+    fn peek(&self, floor: Option<SequenceNumber>) -> SequenceNumber;
+
+    // 下一个提案的优先级，只是调度提示，不关心的实现可以忽略
+    fn prioritize(&mut self, _priority: u8) {}
+}
+
+// 默认方式：当前时间戳加 server_id，遵从控制台设置的时钟偏移与平局规则。
+// 时钟可能往回跳（例如 NTP 校时，或者把偏移调小），时间戳因此不取时钟本身，
+// 而是至少比上一次用过的大 1，保证自己给出的序号严格递增
+#[derive(Debug)]
+pub struct TimestampBallots {
+    server_id: usize,
+    control: Arc<NodeControl>,
+    priority: u8,
+    last: Option<u128>, // 上一次用过的时间戳
+}
+
+impl TimestampBallots {
+    pub fn new(server_id: usize, control: Arc<NodeControl>) -> Self {
+        Self {
+            server_id,
+            control,
+            priority: 0,
+            last: None,
+        }
+    }
+}
+
+impl BallotStrategy for TimestampBallots {
+    fn next(&mut self, floor: Option<SequenceNumber>) -> SequenceNumber {
+        let seq = self.peek(floor);
+        self.priority = 0;
+        self.last = Some(seq.time_stamp());
+        seq
+    }
+
+    // 优先级只抬高时间戳，序号仍由 server_id 保证唯一，不影响安全性
+    fn peek(&self, _floor: Option<SequenceNumber>) -> SequenceNumber {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_millis() as i128;
+        // 时钟偏移可以模拟快慢不一的时钟
+        let time_stamp = (now + self.control.clock_skew() as i128).max(0) as u128;
+        let time_stamp = time_stamp + self.priority as u128 * PRIORITY_BOOST_MS;
+        let time_stamp = self
+            .last
+            .map_or(time_stamp, |last| time_stamp.max(last + 1));
+        if self.control.fair_tie_break() {
+            SequenceNumber::fair(self.server_id, time_stamp)
+        } else {
+            SequenceNumber::new(self.server_id, time_stamp)
+        }
+    }
+
+    fn prioritize(&mut self, priority: u8) {
+        self.priority = priority;
+    }
+}
+
+// 单调递增的计数器，与时钟无关，结果完全确定
+#[derive(Debug)]
+pub struct CounterBallots {
+    server_id: usize,
+    counter: u128,
+}
+
+impl CounterBallots {
+    pub fn new(server_id: usize) -> Self {
+        Self {
+            server_id,
+            counter: 0,
+        }
+    }
+}
+
+impl BallotStrategy for CounterBallots {
+    fn next(&mut self, floor: Option<SequenceNumber>) -> SequenceNumber {
+        let seq = self.peek(floor);
+        self.counter += 1;
+        seq
+    }
+
+    fn peek(&self, _floor: Option<SequenceNumber>) -> SequenceNumber {
+        SequenceNumber::new(self.server_id, self.counter + 1)
+    }
+}
+
+// 包装另一种方式，生成的序号不高于 floor 时直接跳到 floor 之上，竞争中不会白白浪费一轮。
+// 只高出一点时，按时钟重试的对手很快又会反超，两边来回抢占；
+// 跳得远一些（gap），再加上随机的抖动，重试的序号就在序号空间里散开了。
+// 跳过之后内层给出的序号仍从原处数起，因此还要高于上一次给出的序号，不能倒退
+#[derive(Debug)]
+pub struct FloorBallots<S> {
+    server_id: usize,
+    inner: S,
+    gap: u128,                    // 至少高出 floor 这么多
+    jitter: Option<StdRng>,       // 在 gap 之上再随机多跳 [0, gap)
+    last: Option<SequenceNumber>, // 上一次给出的序号
+}
+
+impl<S: BallotStrategy> FloorBallots<S> {
+    pub fn new(server_id: usize, inner: S) -> Self {
+        Self::with_gap(server_id, inner, 1)
+    }
+
+    // gap 至少为 1
+    pub fn with_gap(server_id: usize, inner: S, gap: u128) -> Self {
+        Self {
+            server_id,
+            inner,
+            gap: gap.max(1),
+            jitter: None,
+            last: None,
+        }
+    }
+
+    // 同 with_gap，并按 seed 抖动，种子相同时跳到的序号也相同
+    pub fn with_jitter(server_id: usize, inner: S, gap: u128, seed: u64) -> Self {
+        Self {
+            jitter: Some(StdRng::seed_from_u64(seed)),
+            ..Self::with_gap(server_id, inner, gap)
+        }
+    }
+
+    fn above(
+        &self,
+        floor: Option<SequenceNumber>,
+        seq: SequenceNumber,
+        jitter: Option<&mut StdRng>,
+    ) -> SequenceNumber {
+        let seq = match floor {
+            Some(floor) if floor >= seq => {
+                let extra = jitter.map_or(0, |dice| dice.gen_range(0..self.gap));
+                SequenceNumber::new(self.server_id, floor.time_stamp() + self.gap + extra)
+            }
+            _ => seq,
+        };
+        match self.last {
+            Some(last) if last >= seq => SequenceNumber::new(self.server_id, last.time_stamp() + 1),
+            _ => seq,
+        }
+    }
+}
+
+impl<S: BallotStrategy> BallotStrategy for FloorBallots<S> {
+    fn next(&mut self, floor: Option<SequenceNumber>) -> SequenceNumber {
+        let seq = self.inner.next(floor);
+        let mut jitter = self.jitter.take();
+        let seq = self.above(floor, seq, jitter.as_mut());
+        self.jitter = jitter;
+        self.last = Some(seq);
+        seq
+    }
+
+    // 在抖动的副本上计算，与 next 掷出相同的点数
+    fn peek(&self, floor: Option<SequenceNumber>) -> SequenceNumber {
+        let mut jitter = self.jitter.clone();
+        self.above(floor, self.inner.peek(floor), jitter.as_mut())
+    }
+
+    fn prioritize(&mut self, priority: u8) {
+        self.inner.prioritize(priority);
+    }
+}
@@ -0,0 +1,204 @@
+use futures::channel::mpsc;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::collections::{BTreeMap, HashSet, VecDeque};
+use std::sync::Arc;
+
+use super::ballot::{BallotStrategy, CounterBallots};
+use super::node::{Node, NodeControl};
+use super::proposal::{Datagram, Incoming, Outgoing};
+use super::{Rx, Tx};
+
+// 不经过网络和 run，同步驱动一个结点：喂给它消息，收集它发出的报文
+#[derive(Debug)]
+pub struct Harness {
+    node: Node,
+    outbox: Rx<Outgoing>,
+    _inbox: Tx<Incoming>, // 只为让结点的输入端保持打开
+}
+
+impl Harness {
+    pub fn new(self_id: usize, peers_id: HashSet<usize>) -> Self {
+        let (itx, irx) = mpsc::unbounded();
+        let (otx, orx) = mpsc::unbounded();
+        Self::wrap(Node::new(self_id, peers_id, otx, irx), itx, orx)
+    }
+
+    // 用确定的序号生成方式，测试可以直接写出期望的报文
+    pub fn with_ballots(
+        self_id: usize,
+        peers_id: HashSet<usize>,
+        ballots: Box<dyn BallotStrategy>,
+    ) -> Self {
+        let (itx, irx) = mpsc::unbounded();
+        let (otx, orx) = mpsc::unbounded();
+        let node = Node::with_ballots(self_id, peers_id, ballots, otx, irx);
+        Self::wrap(node, itx, orx)
+    }
+
+    // 序号与随机选择都由参数决定，同样的输入总是得到同样的输出
+    pub fn seeded(self_id: usize, peers_id: HashSet<usize>, seed: u64) -> Self {
+        let (itx, irx) = mpsc::unbounded();
+        let (otx, orx) = mpsc::unbounded();
+        let ballots = Box::new(CounterBallots::new(self_id));
+        let node = Node::with_ballots(self_id, peers_id, ballots, otx, irx).with_seed(seed);
+        Self::wrap(node, itx, orx)
+    }
+
+    fn wrap(node: Node, inbox: Tx<Incoming>, outbox: Rx<Outgoing>) -> Self {
+        Self {
+            node,
+            outbox,
+            _inbox: inbox,
+        }
+    }
+
+    // 依次处理 incoming，返回期间产生的所有报文
+    pub fn feed(&mut self, incoming: impl IntoIterator<Item = Incoming>) -> Vec<Outgoing> {
+        for incoming in incoming {
+            self.node.handle(incoming);
+        }
+        let mut outgoing = Vec::new();
+        while let Ok(Some(out)) = self.outbox.try_next() {
+            outgoing.push(out);
+        }
+        outgoing
+    }
+
+    pub fn node(&self) -> &Node {
+        &self.node
+    }
+
+    pub fn control(&self) -> Arc<NodeControl> {
+        self.node.control()
+    }
+}
+
+// 若干 Harness 组成的内存网络。报文先排队，每一步投递一个；
+// 给定种子时随机挑选下一个投递的报文，按种子可以复现任意的乱序
+#[derive(Debug)]
+pub struct Network {
+    nodes: BTreeMap<usize, Harness>,
+    pending: VecDeque<(usize, Incoming)>, // (目的地, 报文)
+    replies: Vec<(usize, Datagram)>,      // 发给网络之外（例如客户端 #0）的报文及其来源
+    dice: Option<StdRng>,
+    cut: HashSet<(usize, usize)>, // 断开的链路，经过它们的报文直接丢弃
+    trace: Vec<(usize, usize, Datagram)>, // 按投递顺序记下的 (来源, 目的地, 报文)
+}
+
+impl Network {
+    // 结点为 #1..=#n，互为成员
+    pub fn new(n: usize) -> Self {
+        let peers_id: HashSet<usize> = (1..=n).collect();
+        let nodes = peers_id
+            .iter()
+            .map(|&id| (id, Harness::new(id, peers_id.clone())))
+            .collect();
+        Self {
+            nodes,
+            pending: VecDeque::new(),
+            replies: Vec::new(),
+            dice: None,
+            cut: HashSet::new(),
+            trace: Vec::new(),
+        }
+    }
+
+    // 整个网络只取决于 seed：结点用计数序号，gossip 的抽取与投递顺序都从 seed 派生。
+    // 同一个种子跑两次，投递的报文序列完全相同
+    pub fn with_seed(n: usize, seed: u64) -> Self {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let peers_id: HashSet<usize> = (1..=n).collect();
+        let nodes = (1..=n)
+            .map(|id| (id, Harness::seeded(id, peers_id.clone(), rng.gen())))
+            .collect();
+        Self {
+            nodes,
+            dice: Some(StdRng::seed_from_u64(rng.gen())),
+            ..Self::new(0)
+        }
+    }
+
+    // None 时按发送顺序投递
+    pub fn set_reorder(&mut self, seed: Option<u64>) {
+        self.dice = seed.map(StdRng::seed_from_u64);
+    }
+
+    // 把 side 与其余结点隔开，两边之间的报文都会丢失
+    pub fn partition(&mut self, side: &[usize]) {
+        for &a in side {
+            for &b in self.nodes.keys().filter(|id| !side.contains(id)) {
+                self.cut.insert((a, b));
+                self.cut.insert((b, a));
+            }
+        }
+    }
+
+    pub fn heal(&mut self) {
+        self.cut.clear();
+    }
+
+    pub fn send(&mut self, dst: usize, incoming: Incoming) {
+        self.pending.push_back((dst, incoming));
+    }
+
+    // 投递一个报文，没有待投递的报文时返回 false
+    pub fn step(&mut self) -> bool {
+        let next = match &mut self.dice {
+            Some(dice) if !self.pending.is_empty() => {
+                let index = dice.gen_range(0..self.pending.len());
+                self.pending.remove(index)
+            }
+            _ => self.pending.pop_front(),
+        };
+        let (dst, incoming) = match next {
+            Some(next) => next,
+            None => return false,
+        };
+        self.trace.push((incoming.src, dst, incoming.dgram.clone()));
+        let outgoing = match self.nodes.get_mut(&dst) {
+            Some(node) => node.feed(std::iter::once(incoming)),
+            None => {
+                self.replies.push((incoming.src, incoming.dgram));
+                return true;
+            }
+        };
+        for Outgoing {
+            dst: targets,
+            dgram,
+        } in outgoing
+        {
+            // 目的地是 HashSet，排序后再入队，投递顺序才不受哈希种子影响
+            let mut targets: Vec<usize> = targets.into_iter().collect();
+            targets.sort_unstable();
+            for target in targets
+                .into_iter()
+                .filter(|&t| !self.cut.contains(&(dst, t)))
+            {
+                let incoming = Incoming {
+                    src: dst,
+                    dgram: dgram.clone(),
+                };
+                self.pending.push_back((target, incoming));
+            }
+        }
+        true
+    }
+
+    // 一直投递到没有报文为止，最多 max_steps 步，返回实际的步数
+    pub fn run(&mut self, max_steps: usize) -> usize {
+        (0..max_steps).take_while(|_| self.step()).count()
+    }
+
+    pub fn node(&self, id: usize) -> Option<&Harness> {
+        self.nodes.get(&id)
+    }
+
+    pub fn replies(&self) -> &[(usize, Datagram)] {
+        &self.replies
+    }
+
+    pub fn trace(&self) -> &[(usize, usize, Datagram)] {
+        &self.trace
+    }
+}
@@ -1,9 +1,18 @@
 use futures::channel::mpsc;
 
+pub mod ballot;
+pub mod harness;
 pub mod node;
 pub mod proposal;
 pub mod seq_num;
+pub mod sink;
+pub mod store;
 
 pub type ValueType = u32;
 pub type Tx<T> = mpsc::UnboundedSender<T>;
-pub type Rx<T> = mpsc::UnboundedReceiver<T>;
\ No newline at end of file
+pub type Rx<T> = mpsc::UnboundedReceiver<T>;
+
+// 多数派的大小：floor(n / 2) + 1
+pub fn majority(n: usize) -> usize {
+    n / 2 + 1
+}
@@ -1,35 +1,471 @@
-use std::collections::HashSet;
-use std::time::{SystemTime, UNIX_EPOCH};
+use futures::channel::mpsc;
+use rand::rngs::StdRng;
+use rand::seq::IteratorRandom;
+use rand::SeedableRng;
+use serde::Serialize;
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::fmt;
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicI64, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 use tokio::stream::StreamExt;
+use tokio::sync::Notify;
 
+use super::ballot::{BallotStrategy, TimestampBallots};
 use super::proposal::*;
 use super::seq_num::SequenceNumber;
-use super::ValueType;
+use super::sink::DecisionSink;
+use super::store::{MemoryStore, StateStore};
+use super::{majority, ValueType};
 use super::{Rx, Tx};
+use crate::shutdown::Shutdown;
 
+// 写到 control 指定的日志去向
 macro_rules! log {
-    ($($tokens: tt)*) => {
-        {
-            use std::io::Write;
-            let stdout = std::io::stdout();
-            let mut handle = stdout.lock();
-            writeln!(handle, $($tokens)*).unwrap();
-            handle.flush().unwrap();
-            // println!($($tokens)*);
+    ($control: expr, $($tokens: tt)*) => {
+        $control.log(format_args!($($tokens)*))
+    }
+}
+
+// 每一级提案优先级相当于把时钟拨快这么多毫秒
+pub const PRIORITY_BOOST_MS: u128 = 100;
+
+// 暂停的结点每隔这么久检查一次是否恢复
+const PAUSE_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+// read_index 读从收到起这么久还没回答就作废，回客户端 read_failed
+pub const READ_TIMEOUT: Duration = Duration::from_millis(500);
+
+// 结点作为提案者所处的阶段
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NodeState {
+    Idle,      // 没有进行中的提案
+    Preparing, // 已广播 prepare，等待多数派承诺
+    Accepting, // 已广播 accept，等待多数派接受
+    Decided,   // 已经学习到选定的值，不会再变
+}
+
+impl NodeState {
+    // 选定值之后不能再回到其他阶段；accept 只能跟在 prepare 之后
+    fn can_become(self, to: Self) -> bool {
+        use NodeState::*;
+        match (self, to) {
+            (Decided, _) => false,
+            (_, Decided) | (_, Preparing) => true,
+            // 停滞的提案被放弃
+            (Preparing | Accepting, Idle) => true,
+            (Preparing | Accepting, Accepting) => true,
+            _ => false,
+        }
+    }
+}
+
+// 结点启动以来的协议统计
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct NodeStats {
+    pub proposals: u64,                       // 收到并由自己处理的提案
+    pub rounds: u64,                          // 发起的 prepare 轮次
+    pub decided: u64,                         // 由自己推动到多数派接受的提案
+    pub messages: HashMap<&'static str, u64>, // 处理过的各类报文数
+    pub violations: u64,                      // 宽松模式下记录而没有崩溃的安全性违例
+    pub abandoned: u64,                       // 停滞太久而放弃的提案
+    pub rejected: u64,                        // 因承诺过更高的序号而拒绝的 prepare 与 accept
+}
+
+// 结点在协议中的关键动作，供可视化等观察者订阅
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub enum NodeEvent {
+    ProposalStarted {
+        node: usize,
+        seq: SequenceNumber,
+        value: ValueType,
+    },
+    PromiseReceived {
+        node: usize,
+        from: usize,
+        seq: SequenceNumber,
+    },
+    AcceptStarted {
+        node: usize,
+        seq: SequenceNumber,
+        value: ValueType,
+    },
+    Decided {
+        node: usize,
+        seq: SequenceNumber,
+        value: ValueType,
+    },
+}
+
+// 结点日志的去向。多个结点共用一个进程时，可以各写各的文件，互不交错
+#[derive(Default)]
+pub struct LogSink(Option<Box<dyn Write + Send>>);
+
+impl LogSink {
+    pub(crate) fn set(&mut self, sink: Box<dyn Write + Send>) {
+        self.0 = Some(sink);
+    }
+
+    // 写一行并立即刷出，调用者持锁，多个任务的日志不会交错
+    pub(crate) fn write_line(&mut self, args: fmt::Arguments) {
+        match self.0 {
+            // 写日志失败不影响协议，忽略
+            Some(ref mut sink) => {
+                let _ = writeln!(sink, "{}", args);
+                let _ = sink.flush();
+            }
+            None => {
+                let stdout = io::stdout();
+                let mut handle = stdout.lock();
+                writeln!(handle, "{}", args).unwrap();
+                handle.flush().unwrap();
+            }
+        }
+    }
+}
+
+impl fmt::Debug for LogSink {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            Some(_) => write!(f, "LogSink(custom)"),
+            None => write!(f, "LogSink(stdout)"),
+        }
+    }
+}
+
+// 控制台在运行时调整结点行为的开关，与结点共享
+#[derive(Debug, Default)]
+pub struct NodeControl {
+    clock_skew: AtomicI64,      // 叠加在 next_seq 时钟上的偏移（毫秒）
+    gossip_fanout: AtomicUsize, // 学习到值后转告的随机结点数，0 表示不转告
+    slowness: AtomicU64,        // 处理每条消息前的停顿（毫秒），模拟 GC 或负载过高
+    proposer: AtomicUsize,      // 非 0 时只做决策者，把客户端提案转交给该结点
+    paused: AtomicBool,         // 暂停处理消息，消息留在通道中排队
+    handled: AtomicU64,         // 已处理的消息总数，只供观察
+    state: AtomicUsize,         // 结点发布的 NodeState，只供观察
+    fair_tie_break: AtomicBool, // 时间戳相同时不再固定让 ID 大的提案胜出
+    commit_index: AtomicU64,    // 已选定的值的个数，只增不减
+    stats: Mutex<NodeStats>,    // 启动以来的协议统计，只供观察
+    redirect: AtomicBool,       // 让客户端直接去找提案者，而不是代为转交
+    proposer_addr: Mutex<Option<SocketAddr>>, // 提案者的地址，重定向时告诉客户端
+    lenient: AtomicBool,        // 安全性检查失败时只记录并继续，默认直接 panic
+    relearn_interval: AtomicU64, // 学习到值后定期向其他结点重播的间隔（毫秒），0 表示不重播
+    catch_up: AtomicBool,       // 启动时与其他结点交换提交位置，落后的一方立即补齐
+    stall_timeout: AtomicU64,   // 提案这么久（毫秒）没有任何进展就放弃，0 表示不放弃
+    metrics_interval: AtomicU64, // 每隔这么久（毫秒）输出一行统计，0 表示不输出
+    metrics_changed: Notify,    // 改了统计间隔后唤醒结点，从改的时候重新计时
+    connections: Mutex<ConnectionGauge>, // 统计行里的活跃连接数从这里读
+    subscribers: Mutex<Vec<Tx<NodeEvent>>>, // 事件的订阅者，接收端丢弃后自动退订
+    log_sink: Mutex<LogSink>,   // 日志去向，未设置时写到标准输出
+    quiet: AtomicBool,          // 不输出任何日志
+    #[cfg(feature = "debug")]
+    liar: AtomicBool, // 回应 prepare 时谎报接受过的值
+    #[cfg(feature = "debug")]
+    connected_quorum: Mutex<Option<HashSet<usize>>>, // 不安全：按连通的结点计算多数派
+}
+
+// 结点之外的连接数，由代理提供；未设置时报告为 0
+#[derive(Default)]
+pub struct ConnectionGauge(Option<Box<dyn Fn() -> usize + Send + Sync>>);
+
+impl fmt::Debug for ConnectionGauge {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            Some(_) => write!(f, "ConnectionGauge(set)"),
+            None => write!(f, "ConnectionGauge(unset)"),
+        }
+    }
+}
+
+impl NodeControl {
+    // 之后的日志都写到 sink，一行一次写入，持锁期间完成
+    pub fn set_log_sink(&self, sink: Box<dyn Write + Send>) {
+        self.log_sink.lock().unwrap().set(sink);
+    }
+
+    // 追加写到 path，文件不存在时创建
+    pub fn set_log_file(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        self.set_log_sink(Box::new(file));
+        Ok(())
+    }
+
+    pub fn set_quiet(&self, quiet: bool) {
+        self.quiet.store(quiet, Ordering::Relaxed);
+    }
+
+    pub fn quiet(&self) -> bool {
+        self.quiet.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn log(&self, args: fmt::Arguments) {
+        if self.quiet() {
+            return;
+        }
+        self.log_sink.lock().unwrap().write_line(args);
+    }
+
+    pub fn set_clock_skew(&self, delta_ms: i64) {
+        self.clock_skew.store(delta_ms, Ordering::Relaxed);
+    }
+
+    pub fn clock_skew(&self) -> i64 {
+        self.clock_skew.load(Ordering::Relaxed)
+    }
+
+    pub fn set_gossip_fanout(&self, fanout: usize) {
+        self.gossip_fanout.store(fanout, Ordering::Relaxed);
+    }
+
+    pub fn gossip_fanout(&self) -> usize {
+        self.gossip_fanout.load(Ordering::Relaxed)
+    }
+
+    pub fn set_slowness(&self, delay_ms: u64) {
+        self.slowness.store(delay_ms, Ordering::Relaxed);
+    }
+
+    pub fn slowness(&self) -> Duration {
+        Duration::from_millis(self.slowness.load(Ordering::Relaxed))
+    }
+
+    pub fn set_paused(&self, paused: bool) {
+        self.paused.store(paused, Ordering::Relaxed);
+    }
+
+    pub fn paused(&self) -> bool {
+        self.paused.load(Ordering::Relaxed)
+    }
+
+    pub fn set_fair_tie_break(&self, fair: bool) {
+        self.fair_tie_break.store(fair, Ordering::Relaxed);
+    }
+
+    pub fn fair_tie_break(&self) -> bool {
+        self.fair_tie_break.load(Ordering::Relaxed)
+    }
+
+    // 宽松模式用来保住可用性，违例记在 stats().violations 中
+    pub fn set_lenient(&self, lenient: bool) {
+        self.lenient.store(lenient, Ordering::Relaxed);
+    }
+
+    pub fn lenient(&self) -> bool {
+        self.lenient.load(Ordering::Relaxed)
+    }
+
+    // 反熵：错过 learn 又没人查询的结点也能最终学习到值
+    pub fn set_relearn_interval(&self, interval_ms: u64) {
+        self.relearn_interval.store(interval_ms, Ordering::Relaxed);
+    }
+
+    pub fn relearn_interval(&self) -> Duration {
+        Duration::from_millis(self.relearn_interval.load(Ordering::Relaxed))
+    }
+
+    // 反熵的另一半：定期重播照顾错过 learn 的结点，这里则在结点加入时立即补齐，
+    // 不必等到下一次重播。只在 run 开始时生效
+    pub fn set_catch_up(&self, catch_up: bool) {
+        self.catch_up.store(catch_up, Ordering::Relaxed);
+    }
+
+    pub fn catch_up(&self) -> bool {
+        self.catch_up.load(Ordering::Relaxed)
+    }
+
+    // 兜底的活性保障：回应全部丢失又没有重试时，提案会一直占着结点。
+    // 与提案重试不同，这里只是放弃，结点回到 Idle，等待下一个提案
+    pub fn set_stall_timeout(&self, timeout_ms: u64) {
+        self.stall_timeout.store(timeout_ms, Ordering::Relaxed);
+    }
+
+    pub fn stall_timeout(&self) -> Duration {
+        Duration::from_millis(self.stall_timeout.load(Ordering::Relaxed))
+    }
+
+    // 定期把主要计数写成一行日志，没有 Prometheus 时也可以 grep 出来
+    pub fn set_metrics_interval(&self, interval_ms: u64) {
+        self.metrics_interval.store(interval_ms, Ordering::Relaxed);
+        self.metrics_changed.notify();
+    }
+
+    pub fn metrics_interval(&self) -> Duration {
+        Duration::from_millis(self.metrics_interval.load(Ordering::Relaxed))
+    }
+
+    pub fn set_connection_gauge(&self, gauge: impl Fn() -> usize + Send + Sync + 'static) {
+        self.connections.lock().unwrap().0 = Some(Box::new(gauge));
+    }
+
+    pub fn connections(&self) -> usize {
+        self.connections
+            .lock()
+            .unwrap()
+            .0
+            .as_ref()
+            .map_or(0, |gauge| gauge())
+    }
+
+    // 订阅之后发生的事件
+    pub fn subscribe(&self) -> Rx<NodeEvent> {
+        let (tx, rx) = mpsc::unbounded();
+        self.subscribers.lock().unwrap().push(tx);
+        rx
+    }
+
+    fn emit(&self, event: NodeEvent) {
+        self.subscribers
+            .lock()
+            .unwrap()
+            .retain(|tx| tx.unbounded_send(event.clone()).is_ok());
+    }
+
+    pub fn handled(&self) -> u64 {
+        self.handled.load(Ordering::Relaxed)
+    }
+
+    // #0 是客户端，不可能是提案者，因此用 None 存为 0
+    pub fn set_proposer(&self, proposer: Option<usize>) {
+        self.proposer
+            .store(proposer.unwrap_or(0), Ordering::Relaxed);
+    }
+
+    pub fn proposer(&self) -> Option<usize> {
+        match self.proposer.load(Ordering::Relaxed) {
+            0 => None,
+            id => Some(id),
+        }
+    }
+
+    pub fn set_proposer_addr(&self, addr: Option<SocketAddr>) {
+        *self.proposer_addr.lock().unwrap() = addr;
+    }
+
+    pub fn set_redirect(&self, redirect: bool) {
+        self.redirect.store(redirect, Ordering::Relaxed);
+    }
+
+    // 开启重定向且知道提案者地址时返回该地址
+    pub fn redirect(&self) -> Option<SocketAddr> {
+        match self.redirect.load(Ordering::Relaxed) {
+            true => *self.proposer_addr.lock().unwrap(),
+            false => None,
+        }
+    }
+
+    // 经典 Paxos 只容忍宕机类故障，说谎的决策者可以让不同结点选定不同的值
+    #[cfg(feature = "debug")]
+    pub fn set_liar(&self, liar: bool) {
+        self.liar.store(liar, Ordering::Relaxed);
+    }
+
+    #[cfg(feature = "debug")]
+    pub fn liar(&self) -> bool {
+        self.liar.load(Ordering::Relaxed)
+    }
+
+    // 不安全，仅供演示：多数派按 connected 而不是全体成员计算。网络分区时两边
+    // 各自凑够"多数派"，可以选定不同的值。None 恢复按全体成员计算
+    #[cfg(feature = "debug")]
+    pub fn set_connected_quorum(&self, connected: Option<HashSet<usize>>) {
+        if let Some(connected) = &connected {
+            log!(
+                self,
+                "warning: UNSAFE quorum over connected peers {:?}, different sides of a partition may choose different values!",
+                connected
+            );
+        }
+        *self.connected_quorum.lock().unwrap() = connected;
+    }
+
+    #[cfg(feature = "debug")]
+    pub fn connected_quorum(&self) -> Option<HashSet<usize>> {
+        self.connected_quorum.lock().unwrap().clone()
+    }
+
+    // 选定值之前为 0，之后为 1。catch_up、read_index 和滞后统计按它比较结点之间谁更靠前
+    pub fn commit_index(&self) -> u64 {
+        self.commit_index.load(Ordering::Relaxed)
+    }
+
+    fn advance_commit_index(&self, index: u64) {
+        self.commit_index.fetch_max(index, Ordering::Relaxed);
+    }
+
+    pub fn stats(&self) -> NodeStats {
+        self.stats.lock().unwrap().clone()
+    }
+
+    fn record(&self, update: impl FnOnce(&mut NodeStats)) {
+        update(&mut self.stats.lock().unwrap());
+    }
+
+    fn set_state(&self, state: NodeState) {
+        self.state.store(state as usize, Ordering::Relaxed);
+    }
+
+    pub fn state(&self) -> NodeState {
+        match self.state.load(Ordering::Relaxed) {
+            0 => NodeState::Idle,
+            1 => NodeState::Preparing,
+            2 => NodeState::Accepting,
+            _ => NodeState::Decided,
         }
     }
 }
 
+// 结点内部状态的只读副本，供测试直接检查决策者的逻辑
+#[cfg(feature = "debug")]
+#[derive(Debug, Clone, PartialEq)]
+pub struct DebugState {
+    pub last_promised: Option<SequenceNumber>,
+    pub last_accepted_proposal: Option<AcceptedProposal>,
+    pub chosen: Option<AcceptedProposal>,
+}
+
+// 等待心跳确认的线性一致读
+#[derive(Debug)]
+struct PendingRead {
+    client: usize,
+    started: Instant, // 收到读请求的时刻，过了 READ_TIMEOUT 作废
+    index: u64,       // 读必须反映的提交位置，取自己与认可者中最靠前的
+    ahead: usize,     // 给出该位置的结点，自己落后时向它追赶
+    acks: HashSet<usize>,
+    nacks: HashSet<usize>,
+}
+
 #[derive(Debug)]
 pub struct Node {
     self_id: usize,
+    // 成员在启动时确定，之后不变
     peers_id: HashSet<usize>,
     proposal: Option<Proposal>,
+    progress: Instant,                 // 进行中的提案上次有进展的时刻
+    abandoned: Option<SequenceNumber>, // 最近放弃的提案，它迟到的回应直接忽略
+    state: NodeState,
 
     last_promised: Option<SequenceNumber>,
     last_accepted_proposal: Option<AcceptedProposal>,
 
-    chosen: Option<ValueType>,
+    // 选定的提案，提案序号中含有提案者。每个结点只决定这一个值，相当于日志里只有槽位 0，
+    // 选定之后不再改变，也不会过期
+    chosen: Option<AcceptedProposal>,
+    waiters: HashSet<usize>,          // 等待学习结果的查询者
+    decision: Option<DecisionRecord>, // 由自己选定时的决策记录，不持久化
+    decision_waiters: HashSet<usize>, // 等待决策记录的客户端
+    last_heard: Option<Instant>,      // 上次收到 learn 或心跳的时刻，定期重播也算
+    reads: HashMap<u64, PendingRead>, // 正在确认的 read_index 读
+    next_read: u64,
+    index_waiters: Vec<(usize, u64, Instant)>, // 已确认、等自己应用到读位置的 (客户端, 位置, 收到的时刻)
+    control: Arc<NodeControl>,
+    ballots: Box<dyn BallotStrategy>,    // 提案序号的生成方式
+    store: Box<dyn StateStore>,          // 承诺、接受与选定的值先写到这里再回应
+    sink: Option<Box<dyn DecisionSink>>, // 学习到的值同时交给下游
+    rng: StdRng,                         // 结点内所有的随机选择，给定种子后可以复现
     tx: Tx<Outgoing>,
     rx: Rx<Incoming>,
 }
@@ -40,38 +476,347 @@ impl Node {
         peers_id: HashSet<usize>,
         tx: Tx<Outgoing>,
         rx: Rx<Incoming>,
+    ) -> Self {
+        let control = Arc::<NodeControl>::default();
+        let ballots = TimestampBallots::new(self_id, control.clone());
+        let store = Box::<MemoryStore>::default();
+        Self::with_control(self_id, peers_id, Box::new(ballots), store, control, tx, rx)
+    }
+
+    // 写入 store 之后才回应；启动时从 store 读回上一次的状态
+    pub fn with_store(
+        self_id: usize,
+        peers_id: HashSet<usize>,
+        mut store: Box<dyn StateStore>,
+        tx: Tx<Outgoing>,
+        rx: Rx<Incoming>,
+    ) -> io::Result<Self> {
+        let snapshot = store.load()?;
+        let control = Arc::<NodeControl>::default();
+        let ballots = TimestampBallots::new(self_id, control.clone());
+        let mut node =
+            Self::with_control(self_id, peers_id, Box::new(ballots), store, control, tx, rx);
+        node.apply(snapshot);
+        Ok(node)
+    }
+
+    // 使用指定的序号生成方式，例如测试中用确定的计数器
+    pub fn with_ballots(
+        self_id: usize,
+        peers_id: HashSet<usize>,
+        ballots: Box<dyn BallotStrategy>,
+        tx: Tx<Outgoing>,
+        rx: Rx<Incoming>,
+    ) -> Self {
+        let store = Box::<MemoryStore>::default();
+        Self::with_control(self_id, peers_id, ballots, store, Arc::default(), tx, rx)
+    }
+
+    fn with_control(
+        self_id: usize,
+        mut peers_id: HashSet<usize>,
+        ballots: Box<dyn BallotStrategy>,
+        store: Box<dyn StateStore>,
+        control: Arc<NodeControl>,
+        tx: Tx<Outgoing>,
+        rx: Rx<Incoming>,
     ) -> Self {
         // log!("Paxos start with peers_num: {:?}", peers_id);
+        // 成员总是包含自己：自己也投票，发给自己的报文由代理经本地通道送达，不走网络。
+        // 漏掉自己的话，自己的承诺会被当成多出来的一票
+        if peers_id.insert(self_id) {
+            log!(control, "Server #{} isn't in its peers, add it", self_id);
+        }
         Self {
             self_id,
             last_promised: None,
             chosen: None,
+            waiters: HashSet::new(),
+            decision: None,
+            decision_waiters: HashSet::new(),
+            last_heard: None,
+            control,
+            ballots,
+            store,
+            sink: None,
+            reads: HashMap::new(),
+            next_read: 0,
+            index_waiters: Vec::new(),
+            rng: StdRng::from_entropy(),
             last_accepted_proposal: None,
             peers_id,
             proposal: None,
+            progress: Instant::now(),
+            abandoned: None,
+            state: NodeState::Idle,
             tx,
             rx,
         }
     }
 
-    pub async fn run(mut self) {
-        while let Some(incoming) = self.rx.next().await {
+    // 之后学习到的值都交给 sink，启动前已经学习到的值不会补交
+    pub fn with_sink(mut self, sink: Box<dyn DecisionSink>) -> Self {
+        self.sink = Some(sink);
+        self
+    }
+
+    // 从 seed 派生之后的随机选择，例如 gossip 转告给哪些结点
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.rng = StdRng::seed_from_u64(seed);
+        self
+    }
+
+    pub fn control(&self) -> Arc<NodeControl> {
+        self.control.clone()
+    }
+
+    // 运行直到通道关闭或收到停机信号，返回自身以便之后恢复
+    pub async fn run(mut self, shutdown: Shutdown) -> Self {
+        let mut last_relearn = Instant::now();
+        let mut last_metrics = Instant::now();
+        // 代理为每条消息新建连接，没有长连接可以在建立时握手，因此在结点加入时交换一次
+        if self.control.catch_up() {
+            let req = Request::CatchUp {
+                commit_index: self.control.commit_index(),
+            };
+            let dst: HashSet<usize> = self
+                .peers_id
+                .iter()
+                .filter(|&&id| id != self.self_id)
+                .copied()
+                .collect();
+            if !dst.is_empty() {
+                self.multicast(dst, Datagram::Request(req));
+            }
+        }
+        loop {
+            // 按截止时间等待，消息不断到达时重播也不会被一再推迟
+            let interval = self.control.relearn_interval();
+            let relearn = self.chosen.is_some() && interval > Duration::from_millis(0);
+            let metrics = self.control.metrics_interval();
+            let stall = self.control.stall_timeout();
+            let stalled = self.proposal.is_some()
+                && self.chosen.is_none()
+                && stall > Duration::from_millis(0);
+            let read_deadline = self.read_deadline();
+            tokio::select! {
+                incoming = self.rx.next() => match incoming {
+                    Some(incoming) => {
+                        // 等消息期间被暂停的话，这条消息也要等到恢复后才处理
+                        if !self.resumed(&shutdown).await {
+                            self.handle_incoming(incoming);
+                            self.drain();
+                            break;
+                        }
+                        let slowness = self.control.slowness();
+                        if slowness > Duration::from_millis(0) {
+                            tokio::time::delay_for(slowness).await;
+                        }
+                        self.handle_incoming(incoming);
+                    }
+                    None => break,
+                },
+                _ = tokio::time::delay_until((last_relearn + interval).into()), if relearn => {
+                    last_relearn = Instant::now();
+                    self.relearn();
+                }
+                _ = tokio::time::delay_until((self.progress + stall).into()), if stalled => {
+                    self.abandon();
+                }
+                _ = tokio::time::delay_until(read_deadline.unwrap_or_else(Instant::now).into()), if read_deadline.is_some() => {
+                    self.expire_reads();
+                }
+                _ = tokio::time::delay_until((last_metrics + metrics).into()), if metrics > Duration::from_millis(0) => {
+                    last_metrics = Instant::now();
+                    self.log_metrics();
+                }
+                // 新间隔从现在算起，不会因为结点早已启动而立即输出
+                _ = self.control.metrics_changed.notified() => {
+                    last_metrics = Instant::now();
+                }
+                _ = shutdown.wait() => {
+                    self.drain();
+                    break;
+                }
+            }
+        }
+        self
+    }
+
+    // 一行 key=value，方便 grep 与按列解析
+    fn log_metrics(&self) {
+        let stats = self.control.stats();
+        let handled = |kind| stats.messages.get(kind).copied().unwrap_or(0);
+        log!(
+            self.control,
+            "metrics: server #{} prepares={} accepts={} nacks={} decisions={} connections={}",
+            self.self_id,
+            handled("prepare"),
+            handled("accept"),
+            stats.rejected,
+            stats.decided,
+            self.control.connections()
+        );
+    }
+
+    // 放弃停滞的提案。此后它迟到的回应不再算作违例，新的提案照常开始
+    fn abandon(&mut self) {
+        if let Some(proposal) = self.proposal.take() {
+            log!(
+                self.control,
+                "Server #{} abandon stalled proposal {:?}",
+                self.self_id,
+                proposal.seq
+            );
+            self.abandoned = Some(proposal.seq);
+            self.control.record(|stats| stats.abandoned += 1);
+            self.transition(NodeState::Idle);
+        }
+    }
+
+    // 把选定的提案重播给其他结点，已经学习到的结点会忽略它
+    fn relearn(&self) {
+        if let Some(chosen) = self.chosen {
+            let dst: HashSet<usize> = self
+                .peers_id
+                .iter()
+                .filter(|&&id| id != self.self_id)
+                .copied()
+                .collect();
+            let req = Request::Learn {
+                seq: chosen.seq,
+                value: chosen.val,
+            };
+            // 单结点集群没有其他结点可告知
+            if !dst.is_empty() {
+                self.multicast(dst, Datagram::Request(req));
+            }
+        }
+    }
+
+    // 停机前先排空已经到达的消息
+    fn drain(&mut self) {
+        while let Ok(Some(incoming)) = self.rx.try_next() {
             self.handle_incoming(incoming);
         }
     }
 
-    fn next_seq(&mut self) -> SequenceNumber {
-        SequenceNumber::new(
+    // 暂停期间定期检查是否恢复；收到停机信号则返回 false
+    async fn resumed(&self, shutdown: &Shutdown) -> bool {
+        while self.control.paused() {
+            tokio::select! {
+                _ = tokio::time::delay_for(PAUSE_POLL_INTERVAL) => {}
+                _ = shutdown.wait() => return false,
+            }
+        }
+        true
+    }
+
+    // 用新的通道重启结点，只保留持久状态（承诺、接受的提案、选定值），进行中的提案作废
+    pub fn recover(self, tx: Tx<Outgoing>, rx: Rx<Incoming>) -> Self {
+        let snapshot = self.snapshot();
+        let mut node = Self {
+            proposal: None,
+            waiters: HashSet::new(),
+            tx,
+            rx,
+            ..self
+        };
+        node.apply(snapshot);
+        node
+    }
+
+    pub fn snapshot(&self) -> NodeSnapshot {
+        NodeSnapshot {
+            last_promised: self.last_promised,
+            last_accepted: self.last_accepted_proposal,
+            chosen: self.chosen,
+        }
+    }
+
+    // 从快照重建结点，运行时开关恢复为默认值
+    pub fn restore(
+        self_id: usize,
+        peers_id: HashSet<usize>,
+        snapshot: NodeSnapshot,
+        tx: Tx<Outgoing>,
+        rx: Rx<Incoming>,
+    ) -> Self {
+        let mut node = Self::new(self_id, peers_id, tx, rx);
+        node.apply(snapshot);
+        node
+    }
+
+    fn apply(&mut self, snapshot: NodeSnapshot) {
+        self.last_promised = snapshot.last_promised;
+        self.last_accepted_proposal = snapshot.last_accepted;
+        self.chosen = snapshot.chosen;
+        self.state = match self.chosen {
+            Some(_) => NodeState::Decided,
+            None => NodeState::Idle,
+        };
+        self.control.set_state(self.state);
+        self.control
+            .advance_commit_index(self.chosen.is_some() as u64);
+    }
+
+    pub fn state(&self) -> NodeState {
+        self.state
+    }
+
+    #[cfg(feature = "debug")]
+    pub fn debug_state(&self) -> DebugState {
+        DebugState {
+            last_promised: self.last_promised,
+            last_accepted_proposal: self.last_accepted_proposal,
+            chosen: self.chosen,
+        }
+    }
+
+    // 不经过 run 直接处理一条消息，产生的报文照常写入 tx
+    pub fn handle(&mut self, incoming: Incoming) {
+        self.handle_incoming(incoming);
+    }
+
+    fn transition(&mut self, to: NodeState) {
+        if self.state == to {
+            return;
+        }
+        if !self.state.can_become(to) {
+            self.violation(format!(
+                "Server #{} illegal transition {:?} -> {:?}",
+                self.self_id, self.state, to
+            ));
+            return;
+        }
+        log!(
+            self.control,
+            "Server #{} {:?} -> {:?}",
             self.self_id,
-            SystemTime::now()
-                .duration_since(UNIX_EPOCH)
-                .unwrap()
-                .as_millis(),
-        )
+            self.state,
+            to
+        );
+        self.state = to;
+        self.control.set_state(to);
+    }
+
+    // 新序号交给 ballots 生成，floor 是见过的最高序号
+    fn next_seq(&mut self, priority: u8) -> SequenceNumber {
+        let floor = self.seq_floor();
+        self.ballots.prioritize(priority);
+        self.ballots.next(floor)
+    }
+
+    fn seq_floor(&self) -> Option<SequenceNumber> {
+        self.last_promised
+            .max(self.proposal.as_ref().map(|p| p.seq))
     }
 
     fn handle_incoming(&mut self, incoming: Incoming) {
         let Incoming { src, dgram } = incoming;
+        self.control.handled.fetch_add(1, Ordering::Relaxed);
+        self.control
+            .record(|stats| *stats.messages.entry(dgram.kind()).or_default() += 1);
         match dgram {
             Datagram::Request(req) => self.handle_request(src, req),
             Datagram::Response(resp) => self.handle_response(src, resp),
@@ -79,23 +824,48 @@ impl Node {
     }
 
     fn handle_request(&mut self, src: usize, req: Request) {
-        log!(
-            "Server #{} handle req  from #{}: {:?}",
-            self.self_id,
-            src,
-            req
-        );
+        // 周期性重播的 learn 对已经学习到值的结点没有新信息，不再记日志
+        let known =
+            matches!(req, Request::Learn { value, .. } if self.chosen_value() == Some(value));
+        if !known {
+            log!(
+                self.control,
+                "Server #{} handle req  from #{}: {:?}",
+                self.self_id,
+                src,
+                req
+            );
+        }
         match req {
             Request::Prepare { seq } => {
                 // 如果是没有给过承诺，或者新 prepare 请求 ID 更大
-                if self.last_promised.is_none() || self.last_promised.unwrap() <= seq {
+                if self.last_promised.is_none_or(|promised| promised <= seq) {
+                    if let Err(e) = self.store.persist_promise(seq) {
+                        log!(
+                            self.control,
+                            "error: server #{} can't persist promise {:?}: {}",
+                            self.self_id,
+                            seq,
+                            e
+                        );
+                        return;
+                    }
                     self.last_promised = Some(seq);
                     // 将最后接受的值返回给它。
-                    let resp = Response::Prepare(self.last_accepted_proposal);
+                    #[allow(unused_mut)]
+                    let mut accepted = self.last_accepted_proposal;
+                    #[cfg(feature = "debug")]
+                    if self.control.liar() {
+                        accepted =
+                            accepted.map(|p| AcceptedProposal::new(p.seq, p.val.wrapping_add(1)));
+                    }
+                    let resp = Response::Prepare { seq, accepted };
                     self.unicast(src, Datagram::Response(resp));
                 } else {
                     // 否则的话忽略请求
+                    self.control.record(|stats| stats.rejected += 1);
                     log!(
+                        self.control,
                         "Server#{} ignore low-seq req `{:?}` from #{}",
                         self.self_id,
                         req,
@@ -104,13 +874,26 @@ impl Node {
                 }
             }
             Request::Accept { seq, value } => {
-                if self.last_promised.is_none() || self.last_promised.unwrap() <= seq {
-                    self.last_accepted_proposal = Some(AcceptedProposal::new(seq, value));
+                if self.last_promised.is_none_or(|promised| promised <= seq) {
+                    let accepted = AcceptedProposal::new(seq, value);
+                    if let Err(e) = self.store.persist_accept(accepted) {
+                        log!(
+                            self.control,
+                            "error: server #{} can't persist accept {:?}: {}",
+                            self.self_id,
+                            seq,
+                            e
+                        );
+                        return;
+                    }
+                    self.last_accepted_proposal = Some(accepted);
                     // 回应已接受（accepted）
                     let resp = Response::Accepted { seq };
                     self.unicast(src, Datagram::Response(resp));
                 } else {
+                    self.control.record(|stats| stats.rejected += 1);
                     log!(
+                        self.control,
                         "Server#{} ignore req `{:?}` from #{}",
                         self.self_id,
                         req,
@@ -119,138 +902,650 @@ impl Node {
                 }
             }
             // 请求本结点学习 value
-            Request::Learn { value } => {
-                // 若已经学习过，那么两者必须要一致
-                if let Some(chosen_value) = self.chosen {
-                    assert!(chosen_value == value);
-                } else {
-                    // 否则开始学习
-                    self.chosen = Some(value);
+            Request::Learn { seq, value } => {
+                self.last_heard = Some(Instant::now());
+                if self.learn(AcceptedProposal::new(seq, value)) {
+                    // gossip 模式下转告随机几个结点，即使提案者中途宕机也能传开。
+                    // 只在第一次学习时转告，已知的值不再转发，因此不会无限广播
+                    let fanout = self.control.gossip_fanout();
+                    if fanout > 0 {
+                        // 先排好序再抽取，同一个种子总是选中同样的结点
+                        let candidates: BTreeSet<usize> = self
+                            .peers_id
+                            .iter()
+                            .filter(|&&id| id != self.self_id && id != src)
+                            .copied()
+                            .collect();
+                        let dst = candidates
+                            .into_iter()
+                            .choose_multiple(&mut self.rng, fanout);
+                        let req = Request::Learn { seq, value };
+                        // 除了自己和来源之外没有别的结点时无需转告
+                        if !dst.is_empty() {
+                            self.multicast(dst.into_iter().collect(), Datagram::Request(req));
+                        }
+                    }
+                    log!(
+                        self.control,
+                        "Server #{} learned {:?}",
+                        self.self_id,
+                        self.chosen.unwrap()
+                    );
                 }
-                log!("Server #{} learned {}", self.self_id, self.chosen.unwrap());
             }
-            Request::Propose { value } => {
-                let seq = self.next_seq();
-                if self.chosen.is_none() {
-                    // 构造一个提案
-                    self.proposal = Some(Proposal {
-                        seq,
-                        value: None,
-                        want_value: value,
-                        prepared: HashSet::new(),
-                        accepted: HashSet::new(),
-                    });
-
-                    // 准备好 prepare 请求，并广播它
-                    let req = Request::Prepare { seq };
-                    self.boardcast(Datagram::Request(req));
-                } else {
-                    // 系统已经认定值了，不用再 Propose 了
-                    if Some(value) != self.chosen {
+            Request::Propose {
+                value,
+                priority,
+                seq,
+            } => {
+                // 只做决策者时，自己从不发起提案，转交给指定的提案者
+                if let Some(proposer) = self.control.proposer() {
+                    if proposer != self.self_id {
+                        // 来自客户端的提案可以让客户端自己去找提案者
+                        match self.control.redirect() {
+                            Some(addr) if !self.peers_id.contains(&src) => {
+                                let resp = Response::Redirect {
+                                    leader: proposer,
+                                    addr,
+                                };
+                                self.unicast(src, Datagram::Response(resp));
+                            }
+                            _ => self.unicast(proposer, Datagram::Request(req)),
+                        }
+                        return;
+                    }
+                }
+                self.control.record(|stats| stats.proposals += 1);
+                // 系统已经认定值了，不用再 Propose 了。先于序号的检查与生成，
+                // 不会白白用掉序号，带着旧序号的提案也能拿到 AlreadyChosen
+                if let Some(chosen) = self.chosen_value() {
+                    if value != chosen {
                         log!(
+                            self.control,
                             "proposal value `{}` fail, `{}` is chosen.",
                             value,
-                            self.chosen.unwrap()
+                            chosen
                         );
                     } else {
-                        log!("proposal value `{}` is existed", value);
+                        // 告诉提案者它的值已经选定，等待结果的客户端不必再查询
+                        log!(self.control, "proposal value `{}` is existed", value);
+                        let resp = Response::AlreadyChosen { value };
+                        self.unicast(src, Datagram::Response(resp));
                     }
+                    return;
                 }
+                let seq = match seq {
+                    // 指定的序号必须属于本结点，且高于见过的所有序号，否则拒绝
+                    Some(seq) => {
+                        let seen = self
+                            .last_promised
+                            .max(self.proposal.as_ref().map(|p| p.seq));
+                        if seq.server_id() != self.self_id || seen.is_some_and(|seen| seen >= seq) {
+                            log!(
+                                self.control,
+                                "Server #{} reject proposal with seq {:?}, seen {:?}",
+                                self.self_id,
+                                seq,
+                                seen
+                            );
+                            return;
+                        }
+                        seq
+                    }
+                    None => self.next_seq(priority),
+                };
+                // 构造一个提案，取代尚未选定的旧提案时接着累计轮数
+                let mut proposal = Proposal::new(seq, value);
+                if let Some(old) = &self.proposal {
+                    proposal.rounds = old.rounds + 1;
+                }
+                self.proposal = Some(proposal);
+                self.progress = Instant::now();
+
+                // 准备好 prepare 请求，并广播它。选定之后的提案直接拿到选定值，走不到这里，
+                // 也就没有可以省掉 prepare 的后续提案
+                self.transition(NodeState::Preparing);
+                self.control.record(|stats| stats.rounds += 1);
+                self.control.emit(NodeEvent::ProposalStarted {
+                    node: self.self_id,
+                    seq,
+                    value,
+                });
+                let req = Request::Prepare { seq };
+                self.boardcast(Datagram::Request(req));
             }
             Request::Query => {
-                let resp = Response::Query { val: self.chosen };
+                let resp = Response::Query {
+                    val: self.chosen_value(),
+                };
+                self.unicast(src, Datagram::Response(resp));
+            }
+            // 从不在多数派中确认，只报告自己多久没收到 learn 或心跳了，是否接受由客户端决定
+            Request::BoundedQuery => {
+                let resp = Response::BoundedQuery {
+                    val: self.chosen_value(),
+                    staleness_ms: self
+                        .last_heard
+                        .map(|heard| heard.elapsed().as_millis() as u64),
+                };
                 self.unicast(src, Datagram::Response(resp));
             }
+            Request::Ping { nonce } => {
+                self.unicast(src, Datagram::Response(Response::Pong { nonce }));
+            }
+            // 不写日志的线性一致读。领导者模式下只有提案者写入，它的提交位置涵盖了所有
+            // 已完成的写，心跳轮确认期间没有别人用更高的序号取而代之；其他结点转给提案者
+            Request::ReadIndex => {
+                if let Some(proposer) = self.control.proposer() {
+                    if proposer != self.self_id {
+                        match self.control.redirect() {
+                            Some(addr) => {
+                                let resp = Response::Redirect {
+                                    leader: proposer,
+                                    addr,
+                                };
+                                self.unicast(src, Datagram::Response(resp));
+                            }
+                            None => log!(
+                                self.control,
+                                "warning: server #{} isn't the proposer, read index dropped",
+                                self.self_id
+                            ),
+                        }
+                        return;
+                    }
+                }
+                let read_id = self.next_read;
+                self.next_read += 1;
+                self.reads.insert(
+                    read_id,
+                    PendingRead {
+                        client: src,
+                        started: Instant::now(),
+                        index: self.control.commit_index(),
+                        ahead: self.self_id,
+                        acks: HashSet::new(),
+                        nacks: HashSet::new(),
+                    },
+                );
+                let req = Request::Heartbeat {
+                    read_id,
+                    seq: self.last_promised,
+                };
+                self.boardcast(Datagram::Request(req));
+            }
+            // 只读：没承诺过比对方更高的序号就认可它
+            Request::Heartbeat { read_id, seq } => {
+                self.last_heard = Some(Instant::now());
+                let resp = Response::Heartbeat {
+                    read_id,
+                    ok: self.last_promised <= seq,
+                    commit_index: self.control.commit_index(),
+                };
+                self.unicast(src, Datagram::Response(resp));
+            }
+            Request::Probe => {
+                let resp = Response::Probe(AcceptorState {
+                    last_promised: self.last_promised,
+                    last_accepted: self.last_accepted_proposal,
+                });
+                self.unicast(src, Datagram::Response(resp));
+            }
+            Request::WaitQuery => {
+                // 还没有学习到值就先挂起，学习后再回应
+                if self.chosen.is_some() {
+                    let resp = Response::Query {
+                        val: self.chosen_value(),
+                    };
+                    self.unicast(src, Datagram::Response(resp));
+                } else {
+                    self.waiters.insert(src);
+                }
+            }
+            // 对方落后就把选定的提案推过去；自己落后就告知自己的位置，由对方来推。
+            // 位置相同时不回应，因此不会来回不停
+            Request::CatchUp { commit_index } => {
+                let mine = self.control.commit_index();
+                match (self.chosen, mine.cmp(&commit_index)) {
+                    (Some(chosen), std::cmp::Ordering::Greater) => {
+                        let req = Request::Learn {
+                            seq: chosen.seq,
+                            value: chosen.val,
+                        };
+                        self.unicast(src, Datagram::Request(req));
+                    }
+                    (_, std::cmp::Ordering::Less) => {
+                        let req = Request::CatchUp { commit_index: mine };
+                        self.unicast(src, Datagram::Request(req));
+                    }
+                    _ => {}
+                }
+            }
+            Request::WaitDecision => match self.chosen {
+                Some(chosen) => {
+                    let resp = Response::Decision {
+                        value: chosen.val,
+                        record: self.decision.clone(),
+                    };
+                    self.unicast(src, Datagram::Response(resp));
+                }
+                None => {
+                    self.decision_waiters.insert(src);
+                }
+            },
+            Request::Who => {
+                let resp = Response::Who(self.chosen);
+                self.unicast(src, Datagram::Response(resp));
+            }
+            // 只读：不消耗序号，也不改变优先级
+            Request::PeekSeq => {
+                let seq = self.ballots.peek(self.seq_floor());
+                self.unicast(src, Datagram::Response(Response::PeekSeq { seq }));
+            }
         }
     }
 
     fn handle_response(&mut self, src: usize, resp: Response) {
         log!(
+            self.control,
             "Server #{} handle resp from #{}: {:?}",
             self.self_id,
             src,
             resp
         );
         match resp {
-            Response::Prepare(accepted_proposal) => {
+            Response::Prepare {
+                seq,
+                accepted: accepted_proposal,
+            } => {
+                // 已经学习到值，进行中的提案没有必要再推进
+                if self.state == NodeState::Decided {
+                    return;
+                }
+                let quorum = self.quorum();
                 // 将自身的提案取出
                 if let Some(ref mut my_proposal) = self.proposal {
-                    // 如果已经有被选定的提案
-                    if let Some(AcceptedProposal { seq, val }) = accepted_proposal {
-                        assert!(my_proposal.seq >= seq);
+                    // 重新提案后，旧提案迟到的承诺不能算数
+                    if seq != my_proposal.seq {
+                        return;
+                    }
+                    // 决策者接受过更高的提案，说明本提案已被抢先，这个承诺不算数
+                    if let Some(accepted) = accepted_proposal.filter(|p| p.seq > seq) {
+                        log!(
+                            self.control,
+                            "Server #{} preempted: #{} accepted {:?} above {:?}",
+                            self.self_id,
+                            src,
+                            accepted.seq,
+                            seq
+                        );
+                        return;
+                    }
+                    // 同一决策者的重复承诺不重复计数
+                    if !my_proposal.prepared.insert(src) {
+                        return;
+                    }
+                    self.progress = Instant::now();
+                    self.control.emit(NodeEvent::PromiseReceived {
+                        node: self.self_id,
+                        from: src,
+                        seq,
+                    });
+                    // 记下承诺中序号最大的已接受提案，之前的提案者可能已经让多数派接受了它
+                    if let Some(accepted) = accepted_proposal {
+                        if my_proposal
+                            .highest_accepted
+                            .is_none_or(|highest| highest.seq < accepted.seq)
+                        {
+                            my_proposal.highest_accepted = Some(accepted);
+                        }
+                    }
 
-                        // 我也声明要提出这个值
+                    // Prepare 被大多数允许
+                    if my_proposal.prepared.len() == quorum {
+                        // 有人接受过值就必须沿用它，否则才能提出自己想要的值
+                        let value = match my_proposal.highest_accepted {
+                            Some(highest) => highest.val,
+                            None => my_proposal.want_value,
+                        };
+                        if value != my_proposal.want_value {
+                            log!(
+                                self.control,
+                                "Server #{} adopt value `{}` instead of `{}`",
+                                self.self_id,
+                                value,
+                                my_proposal.want_value
+                            );
+                        }
+                        if let Err(set) = my_proposal.set_value(value) {
+                            let msg = format!(
+                                "Server #{} changed value of {:?} from `{}` to `{}`",
+                                self.self_id, seq, set, value
+                            );
+                            self.violation(msg);
+                            return;
+                        }
+                        // 那就继续提出 Accept 请求
                         let req = Request::Accept {
                             seq: my_proposal.seq,
-                            value: val,
+                            value,
                         };
-                        // 并广播之，当然，这会导致一个 node 收到多个 accept
+                        self.transition(NodeState::Accepting);
+                        self.control.emit(NodeEvent::AcceptStarted {
+                            node: self.self_id,
+                            seq,
+                            value,
+                        });
                         self.boardcast(Datagram::Request(req));
-                    } else {
-                        // 否则就是还没有被选定提案
-                        my_proposal.prepared.insert(src);
-
-                        // Prepare 被大多数允许
-                        if my_proposal.prepared.len() >= self.peers_id.len() / 2 + 1 {
-                            // 那就继续提出 Accept 请求
-                            let req = Request::Accept {
-                                seq: my_proposal.seq,
-                                value: *my_proposal.value.get_or_insert(my_proposal.want_value),
-                            };
-                            self.boardcast(Datagram::Request(req));
-                        }
                     }
+                } else if self.abandoned.is_some_and(|abandoned| seq <= abandoned) {
+                    log!(
+                        self.control,
+                        "Server #{} ignore promise {:?} of abandoned proposal from #{}",
+                        self.self_id,
+                        seq,
+                        src
+                    );
                 } else {
                     // 不可能还没有设定提案吧
-                    panic!("Why there is no proposal for me?");
+                    self.violation(format!(
+                        "Server #{} got a promise from #{}, but there is no proposal for me",
+                        self.self_id, src
+                    ));
                 }
             }
             Response::Accepted { seq } => {
+                let quorum = self.quorum();
                 // 将自身提案取出，并且比较响应的序列号是否等于自身
                 if let Some(ref mut my_proposal) = self.proposal {
-                    assert!(seq == my_proposal.seq);
+                    // 重新提案后，旧提案迟到的回应不再有意义
+                    if seq != my_proposal.seq {
+                        log!(
+                            self.control,
+                            "Server #{} ignore stale accepted {:?} from #{}",
+                            self.self_id,
+                            seq,
+                            src
+                        );
+                        return;
+                    }
 
                     // 提案已被接受
-                    my_proposal.accepted.insert(src);
+                    if my_proposal.accepted.insert(src) {
+                        self.progress = Instant::now();
+                    }
 
                     // 如果过半数接受
-                    if my_proposal.accepted.len() == self.peers_id.len() / 2 + 1 {
-                        my_proposal.value = Some(my_proposal.want_value);
+                    if my_proposal.accepted.len() == quorum {
+                        // 多数派接受的是 Accept 中的值，可能是沿用的别人的值
                         let value = my_proposal.value.unwrap();
-                        log!("value accepted by majority: {}", value);
+                        log!(self.control, "value accepted by majority: {}", value);
+                        self.control.record(|stats| stats.decided += 1);
+                        self.decision = Some(DecisionRecord {
+                            value,
+                            ballot: seq,
+                            deciding_quorum: my_proposal.accepted.clone(),
+                            rounds: my_proposal.rounds,
+                        });
 
-                        let req = Request::Learn { value };
+                        // 自己先学习，不依赖广播绕回自己
+                        self.learn(AcceptedProposal::new(seq, value));
+                        let req = Request::Learn { seq, value };
                         self.boardcast(Datagram::Request(req));
                     }
+                } else if self.abandoned.is_some_and(|abandoned| seq <= abandoned) {
+                    log!(
+                        self.control,
+                        "Server #{} ignore accepted {:?} of abandoned proposal from #{}",
+                        self.self_id,
+                        seq,
+                        src
+                    );
                 } else {
-                    panic!("recv an accepted response, but not my proposal !!!");
+                    self.violation(format!(
+                        "Server #{} recv an accepted response from #{}, but not my proposal",
+                        self.self_id, src
+                    ));
                 }
             }
+            // 只有客户端会发出 probe、who、peek_seq、ping 与 wait_decision，也只有客户端会被重定向
+            Response::Probe(_)
+            | Response::Who(_)
+            | Response::Redirect { .. }
+            | Response::PeekSeq { .. }
+            | Response::Pong { .. }
+            | Response::Decision { .. }
+            | Response::BoundedQuery { .. }
+            | Response::AlreadyChosen { .. }
+            | Response::ReadIndex { .. }
+            | Response::ReadFailed => {}
+            Response::Heartbeat {
+                read_id,
+                ok,
+                commit_index,
+            } => self.confirm_read(src, read_id, ok, commit_index),
             Response::Query { val } => {
                 if let Some(val) = val {
-                    log!("Server #{} Answer: {}.", src, val);
+                    log!(self.control, "Server #{} Answer: {}.", src, val);
                 } else {
-                    log!("Server #{} Answer: not value learned yet.", src);
+                    log!(
+                        self.control,
+                        "Server #{} Answer: not value learned yet.",
+                        src
+                    );
+                }
+            }
+        }
+    }
+
+    // 凑成多数派需要的回应数
+    fn quorum(&self) -> usize {
+        #[cfg(feature = "debug")]
+        if let Some(connected) = self.control.connected_quorum() {
+            return majority(connected.len());
+        }
+        majority(self.peers_id.len())
+    }
+
+    fn chosen_value(&self) -> Option<ValueType> {
+        self.chosen.map(|chosen| chosen.val)
+    }
+
+    // 学习选定的值并回应所有等待中的查询，返回是否是第一次学习。
+    // 同一个值可能被更高的提案再次选定，只记录第一次学习到的提案
+    fn learn(&mut self, proposal: AcceptedProposal) -> bool {
+        // 若已经学习过，那么两者必须要一致
+        if let Some(chosen_value) = self.chosen_value() {
+            if chosen_value != proposal.val {
+                self.violation(format!(
+                    "Server #{} learned `{}` but `{}` was chosen",
+                    self.self_id, proposal.val, chosen_value
+                ));
+            }
+            return false;
+        }
+        // 自己接受过别的值而它没有选中，学到的值覆盖它。选定的值在更高的提案里不会变，
+        // 所以选定它的提案必须比自己接受的那个更高
+        if let Some(accepted) = self.last_accepted_proposal {
+            if accepted.val != proposal.val {
+                log!(
+                    self.control,
+                    "Server #{} learned `{}` at {:?}, had accepted `{}` at {:?}",
+                    self.self_id,
+                    proposal.val,
+                    proposal.seq,
+                    accepted.val,
+                    accepted.seq
+                );
+                if proposal.seq <= accepted.seq {
+                    self.violation(format!(
+                        "Server #{} learned `{}` at {:?} but accepted `{}` at higher {:?}",
+                        self.self_id, proposal.val, proposal.seq, accepted.val, accepted.seq
+                    ));
                 }
             }
         }
+        // 选定值可以从其他结点重新学到，写不进去也照常学习
+        if let Err(e) = self.store.persist_chosen(proposal) {
+            log!(
+                self.control,
+                "error: server #{} can't persist chosen {:?}: {}",
+                self.self_id,
+                proposal,
+                e
+            );
+        }
+        self.chosen = Some(proposal);
+        if let Some(sink) = &mut self.sink {
+            if let Err(e) = sink.on_decided(0, proposal.val) {
+                log!(
+                    self.control,
+                    "error: server #{} can't export chosen {:?}: {}",
+                    self.self_id,
+                    proposal,
+                    e
+                );
+            }
+        }
+        self.transition(NodeState::Decided);
+        self.control.emit(NodeEvent::Decided {
+            node: self.self_id,
+            seq: proposal.seq,
+            value: proposal.val,
+        });
+        self.control.advance_commit_index(1);
+        for waiter in std::mem::take(&mut self.waiters) {
+            let resp = Response::Query {
+                val: self.chosen_value(),
+            };
+            self.unicast(waiter, Datagram::Response(resp));
+        }
+        let applied = self.control.commit_index();
+        let (ready, waiting) = std::mem::take(&mut self.index_waiters)
+            .into_iter()
+            .partition(|&(_, index, _)| index <= applied);
+        self.index_waiters = waiting;
+        for (client, index, _) in ready {
+            self.answer_read(client, index);
+        }
+        for waiter in std::mem::take(&mut self.decision_waiters) {
+            let resp = Response::Decision {
+                value: proposal.val,
+                record: self.decision.clone(),
+            };
+            self.unicast(waiter, Datagram::Response(resp));
+        }
+        true
+    }
+
+    // 收够多数派的认可后，应用到读位置就回答；被多数派否认则放弃，告诉客户端读失败
+    fn confirm_read(&mut self, src: usize, read_id: u64, ok: bool, commit_index: u64) {
+        let quorum = self.quorum();
+        let rivals = self.peers_id.len() + 1 - quorum;
+        let read = match self.reads.get_mut(&read_id) {
+            Some(read) => read,
+            None => return,
+        };
+        if ok {
+            read.acks.insert(src);
+            if commit_index > read.index {
+                read.index = commit_index;
+                read.ahead = src;
+            }
+        } else {
+            read.nacks.insert(src);
+        }
+        if read.nacks.len() >= rivals {
+            log!(
+                self.control,
+                "warning: server #{} lost leadership, read {} abandoned",
+                self.self_id,
+                read_id
+            );
+            let read = self.reads.remove(&read_id).unwrap();
+            self.unicast(read.client, Datagram::Response(Response::ReadFailed));
+        } else if read.acks.len() >= quorum {
+            let read = self.reads.remove(&read_id).unwrap();
+            let mine = self.control.commit_index();
+            if mine >= read.index {
+                self.answer_read(read.client, read.index);
+            } else {
+                // 认可者更靠前，向它追上之后再回答
+                self.index_waiters
+                    .push((read.client, read.index, read.started));
+                let req = Request::CatchUp { commit_index: mine };
+                self.unicast(read.ahead, Datagram::Request(req));
+            }
+        }
+    }
+
+    // 最早作废的 read_index 读的期限，没有等着的读时为空
+    fn read_deadline(&self) -> Option<Instant> {
+        let confirming = self.reads.values().map(|read| read.started);
+        let applying = self.index_waiters.iter().map(|&(_, _, started)| started);
+        confirming
+            .chain(applying)
+            .min()
+            .map(|started| started + READ_TIMEOUT)
+    }
+
+    // 心跳轮的回应丢失，或者一直追不上读位置，都不能让读永远挂着
+    fn expire_reads(&mut self) {
+        let now = Instant::now();
+        let mut clients = Vec::new();
+        let mut keep = |client, started| {
+            let live = now < started + READ_TIMEOUT;
+            if !live {
+                clients.push(client);
+            }
+            live
+        };
+        self.reads.retain(|_, read| keep(read.client, read.started));
+        self.index_waiters
+            .retain(|&(client, _, started)| keep(client, started));
+        for client in clients {
+            log!(
+                self.control,
+                "warning: server #{} read from #{} timed out",
+                self.self_id,
+                client
+            );
+            self.unicast(client, Datagram::Response(Response::ReadFailed));
+        }
+    }
+
+    fn answer_read(&mut self, client: usize, index: u64) {
+        let resp = Response::ReadIndex {
+            val: self.chosen_value(),
+            index,
+        };
+        self.unicast(client, Datagram::Response(resp));
+    }
+
+    // 安全性检查失败：严格模式下 panic，宽松模式下记录后继续，保留原有状态
+    fn violation(&self, msg: String) {
+        if !self.control.lenient() {
+            panic!("{}", msg);
+        }
+        log!(self.control, "error: safety violation: {}", msg);
+        self.control.record(|stats| stats.violations += 1);
     }
 
     pub(crate) fn boardcast(&self, msg: Datagram) {
-        self.tx
-            .unbounded_send(Outgoing {
-                dst: self.peers_id.clone(),
-                dgram: msg,
-            })
-            .unwrap();
+        self.send(self.peers_id.clone(), msg);
+    }
+
+    pub(crate) fn multicast(&self, dst: HashSet<usize>, msg: Datagram) {
+        self.send(dst, msg);
     }
 
     pub(crate) fn unicast(&self, src: usize, msg: Datagram) {
-        self.tx
-            .unbounded_send(Outgoing {
-                dst: (src..src + 1).collect(),
-                dgram: msg,
-            })
-            .unwrap();
+        self.send(std::iter::once(src).collect(), msg);
+    }
+
+    // 调用者保证 dst 不为空，没有目的地的报文代理会直接丢掉
+    fn send(&self, dst: HashSet<usize>, dgram: Datagram) {
+        debug_assert!(!dst.is_empty(), "{} has no destination", dgram.kind());
+        self.tx.unbounded_send(Outgoing { dst, dgram }).unwrap();
     }
 }
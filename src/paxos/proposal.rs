@@ -1,6 +1,8 @@
+use bincode::Options;
 use bytes::{BufMut, Bytes, BytesMut};
 use serde::{Deserialize, Serialize};
 use std::collections::HashSet;
+use std::net::SocketAddr;
 
 use super::{seq_num::SequenceNumber, ValueType};
 
@@ -9,21 +11,80 @@ pub struct Proposal {
     pub(crate) seq: SequenceNumber,
     pub(crate) value: Option<ValueType>, // 我已经 accept 过的值
     pub(crate) want_value: ValueType,    // 我想要设定的值
-    // pub(crate) highest_seq: Option<SequenceNumber>,
+    pub(crate) highest_accepted: Option<AcceptedProposal>, // 承诺中序号最大的已接受提案
     pub(crate) prepared: HashSet<usize>,
     pub(crate) accepted: HashSet<usize>,
+    pub(crate) rounds: u32, // 连同被取代的提案在内，为选定一个值发起的 prepare 轮数
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+impl Proposal {
+    pub fn new(seq: SequenceNumber, want_value: ValueType) -> Self {
+        Self {
+            seq,
+            value: None,
+            want_value,
+            highest_accepted: None,
+            prepared: HashSet::new(),
+            accepted: HashSet::new(),
+            rounds: 1,
+        }
+    }
+
+    pub fn value(&self) -> Option<ValueType> {
+        self.value
+    }
+
+    // 同一个提案的值一经设定就不能再变，否则多数派可能接受了两个不同的值。
+    // 试图改成别的值时保持原值不动，返回已设定的值
+    pub fn set_value(&mut self, value: ValueType) -> Result<(), ValueType> {
+        match self.value {
+            Some(set) if set != value => Err(set),
+            _ => {
+                self.value = Some(value);
+                Ok(())
+            }
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Copy)]
 pub struct AcceptedProposal {
-    pub(crate) seq: SequenceNumber,
-    pub(crate) val: ValueType,
+    pub seq: SequenceNumber,
+    pub val: ValueType,
 }
 
 impl AcceptedProposal {
     pub fn new(seq: SequenceNumber, val: ValueType) -> Self {
         Self { seq, val }
     }
+
+    pub fn proposer(&self) -> usize {
+        self.seq.server_id()
+    }
+}
+
+// 提案者选定一个值时的完整记录，客户端据此核对是哪些决策结点组成了多数派
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct DecisionRecord {
+    pub value: ValueType,
+    pub ballot: SequenceNumber,
+    pub deciding_quorum: HashSet<usize>, // 回应 accepted 凑成多数派的结点
+    pub rounds: u32,
+}
+
+// 决策结点当前的承诺与接受情况，只读
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Copy)]
+pub struct AcceptorState {
+    pub last_promised: Option<SequenceNumber>,
+    pub last_accepted: Option<AcceptedProposal>,
+}
+
+// 结点全部的持久状态，用于热备份以及迁移后恢复
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+pub struct NodeSnapshot {
+    pub last_promised: Option<SequenceNumber>,
+    pub last_accepted: Option<AcceptedProposal>,
+    pub chosen: Option<AcceptedProposal>,
 }
 
 #[derive(Debug)]
@@ -39,19 +100,92 @@ pub struct Outgoing {
 }
 
 // 报文数据分为两类
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub enum Datagram {
     Request(Request),   // 请求类
     Response(Response), // 响应类
 }
 
+// 报文格式版本，版本不同的结点之间无法通信
+pub const PROTOCOL_VERSION: u8 = 14;
+
+// 解码时最多读取的字节数，即使报文内声明了巨大的长度也不会超出
+pub const DECODE_LIMIT: u64 = 512;
+
+// 变长整数编码：序号中的 u128 时间戳和各种 usize 通常都很小
+fn codec() -> impl Options {
+    bincode::DefaultOptions::new().with_varint_encoding()
+}
+
+// 解码一个报文的数据部分。超过上限的先整个拒绝，其余的必须恰好是一个报文，
+// 解码完还有多出的字节同样算出错
+pub fn decode(data: &[u8]) -> Result<Datagram, bincode::Error> {
+    if data.len() as u64 > DECODE_LIMIT {
+        return Err(Box::new(bincode::ErrorKind::SizeLimit));
+    }
+    codec()
+        .with_limit(DECODE_LIMIT)
+        .reject_trailing_bytes()
+        .deserialize(data)
+}
+
+// 只启动一组服务器时使用的组号
+pub const DEFAULT_GROUP: usize = 0;
+
 impl Datagram {
+    // 报文的种类，用于统计
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::Request(req) => match req {
+                Request::Propose { .. } => "propose",
+                Request::Prepare { .. } => "prepare",
+                Request::Accept { .. } => "accept",
+                Request::Learn { .. } => "learn",
+                Request::Query => "query",
+                Request::WaitQuery => "wait_query",
+                Request::Probe => "probe",
+                Request::Who => "who",
+                Request::PeekSeq => "peek_seq",
+                Request::Ping { .. } => "ping",
+                Request::WaitDecision => "wait_decision",
+                Request::CatchUp { .. } => "catch_up",
+                Request::ReadIndex => "read_index",
+                Request::Heartbeat { .. } => "heartbeat",
+                Request::BoundedQuery => "bounded_query",
+            },
+            Self::Response(resp) => match resp {
+                Response::Prepare { .. } => "promise",
+                Response::Accepted { .. } => "accepted",
+                Response::Query { .. } => "answer",
+                Response::Probe(_) => "probe_state",
+                Response::Who(_) => "chosen",
+                Response::Redirect { .. } => "redirect",
+                Response::PeekSeq { .. } => "peeked_seq",
+                Response::Pong { .. } => "pong",
+                Response::Decision { .. } => "decision",
+                Response::BoundedQuery { .. } => "bounded_answer",
+                Response::AlreadyChosen { .. } => "already_chosen",
+                Response::Heartbeat { .. } => "heartbeat_ack",
+                Response::ReadIndex { .. } => "read_answer",
+                Response::ReadFailed => "read_failed",
+            },
+        }
+    }
+
+    // 以默认组的身份编码
     pub fn encode_with_src(&self, src: usize) -> Bytes {
+        self.encode(DEFAULT_GROUP, src)
+    }
+
+    // 编码为 [版本 | 组号 | 来源 | 长度 | 数据]
+    pub fn encode(&self, group: usize, src: usize) -> Bytes {
         const N: usize = std::mem::size_of::<usize>();
 
-        let data = bincode::serialize(&self).unwrap();
-        let mut buf = BytesMut::with_capacity(2 * N + data.len());
+        let data = codec().serialize(&self).unwrap();
+        let mut buf = BytesMut::with_capacity(1 + 3 * N + data.len());
 
+        buf.put_u8(PROTOCOL_VERSION);
+        buf.put_uint_be(group as u64, N);
         buf.put_uint_be(src as u64, N);
         buf.put_uint_be(data.len() as u64, N);
         buf.put(data);
@@ -63,15 +197,28 @@ impl Datagram {
 请求有四种请求：
     1. propose: 提出设定值
     2. prepare: 询问众人，查询是否已被设定值
-    3. accept: 请求众人将值设定为 value
+    3. accept: 请求众人将值设定为 value，一次只携带一个值
     4. learn: 请求学习设定好的值
+    5. query: 查询已学习的值，wait_query 则等到学习到值后才回应
+    6. probe: 查询决策结点的承诺与接受情况，不做任何承诺
+    7. who: 查询选定值是由哪个提案选定的
+    8. peek_seq: 查询结点下一个提案会用的序号，不产生副作用
+    9. ping: 立即原样回应 nonce，用于测量往返时延
+    10. wait_decision: 同 wait_query，但由选定该值的提案者附上决策记录
+    11. catch_up: 告知自己的提交位置，更靠前的一方把对方缺少的决议用 learn 推过去
+    12. bounded_query: 同 query，另外带回距上次收到 learn 或心跳过了多久，由客户端判断是否太旧
+    13. read_index: 线性一致读，结点先用一轮心跳确认多数派没有承诺过更高的序号，再回答
+    14. heartbeat: read_index 的确认轮，带上发起者承诺过的最高序号
 
 */
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub enum Request {
     Propose {
         value: ValueType,
+        priority: u8, // 越大越容易在竞争中胜出，只是调度提示
+        // 由调用者指定提案序号，此时忽略 priority
+        seq: Option<SequenceNumber>,
     },
     Prepare {
         seq: SequenceNumber,
@@ -81,20 +228,89 @@ pub enum Request {
         value: ValueType,
     },
     Learn {
+        seq: SequenceNumber, // 选定值所在的提案，用于追溯提案者
         value: ValueType,
     },
     Query,
+    WaitQuery,
+    Probe,
+    Who,
+    PeekSeq,
+    Ping {
+        nonce: u64,
+    },
+    WaitDecision,
+    CatchUp {
+        commit_index: u64,
+    },
+    BoundedQuery,
+    ReadIndex,
+    Heartbeat {
+        read_id: u64,
+        seq: Option<SequenceNumber>,
+    },
 }
 
 /*
-响应有三种：
-    1. prepare: 没有设定值，或者已经有设定值
+响应有以下几种：
+    1. prepare: 对某个序号的承诺，附带已接受过的提案（若有）
     2. accept: 接受值成功
     3. query: 查询响应，要么没有值，要么有设定值
+    4. probe: 决策结点的当前状态
+    5. who: 选定的提案，还没有学习到值时为空
+    6. redirect: 只有提案者才提案，客户端应改向 leader 提案
+    7. peek_seq: 下一个提案会用的序号
+    8. pong: 对 ping 的回应，带回同一个 nonce
+    9. decision: 学习到的值，本结点不是选定它的提案者时没有记录
+    10. bounded_query: 学习到的值，以及距上次收到 learn 或心跳的毫秒数，从未收到时为空
+    11. already_chosen: 提出的值正是已选定的值，提案立即完成
+    12. heartbeat: 是否认可对方的序号（没有承诺过更高的），以及自己的提交位置
+    13. read_index: 线性一致读的结果，以及读所依据的提交位置
+    14. read_failed: read_index 被多数派否认或者超时作废，不会再有回答
  */
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub enum Response {
-    Prepare(Option<AcceptedProposal>),
-    Accepted { seq: SequenceNumber },
-    Query { val: Option<ValueType> },
+    Prepare {
+        seq: SequenceNumber, // 所承诺的提案序号，提案者据此丢弃旧提案的承诺
+        accepted: Option<AcceptedProposal>,
+    },
+    Accepted {
+        seq: SequenceNumber,
+    },
+    Query {
+        val: Option<ValueType>,
+    },
+    Probe(AcceptorState),
+    Who(Option<AcceptedProposal>),
+    Redirect {
+        leader: usize,
+        addr: SocketAddr,
+    },
+    PeekSeq {
+        seq: SequenceNumber,
+    },
+    Pong {
+        nonce: u64,
+    },
+    Decision {
+        value: ValueType,
+        record: Option<DecisionRecord>,
+    },
+    BoundedQuery {
+        val: Option<ValueType>,
+        staleness_ms: Option<u64>,
+    },
+    AlreadyChosen {
+        value: ValueType,
+    },
+    Heartbeat {
+        read_id: u64,
+        ok: bool,
+        commit_index: u64,
+    },
+    ReadIndex {
+        val: Option<ValueType>,
+        index: u64,
+    },
+    ReadFailed,
 }
@@ -5,34 +5,62 @@ use serde::{Deserialize, Serialize};
 #[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
 pub struct SequenceNumber {
     time_stamp: u128,
+    // 时间戳相同时先比较它，为 0 时退化为比较 server_id
+    #[serde(default)]
+    tie_break: u64,
     server_id: usize,
 }
 
 impl PartialOrd for SequenceNumber {
     fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        if self.time_stamp == other.time_stamp {
-            Some(self.server_id.cmp(&other.server_id))
-        } else {
-            Some(self.time_stamp.cmp(&other.time_stamp))
-        }
+        Some(self.cmp(other))
     }
 }
 
+// 最后总是比较 server_id，因此不同服务器的序号不会相等
 impl Ord for SequenceNumber {
     fn cmp(&self, other: &Self) -> Ordering {
-        if self.time_stamp == other.time_stamp {
-            self.server_id.cmp(&other.server_id)
-        } else {
-            self.time_stamp.cmp(&other.time_stamp)
-        }
+        (self.time_stamp, self.tie_break, self.server_id).cmp(&(
+            other.time_stamp,
+            other.tie_break,
+            other.server_id,
+        ))
     }
 }
 
+// splitmix64 的一步，把相邻的输入打散
+fn mix(x: u64) -> u64 {
+    let x = x.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    let x = (x ^ (x >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    let x = (x ^ (x >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    x ^ (x >> 31)
+}
+
 impl SequenceNumber {
-    pub(crate) fn new(server_id: usize, time_stamp: u128) -> Self {
+    pub fn new(server_id: usize, time_stamp: u128) -> Self {
+        Self {
+            time_stamp,
+            tie_break: 0,
+            server_id,
+        }
+    }
+
+    // 时钟同步时 new 总让 ID 最大的服务器胜出。这里由时间戳和 ID 算出打破平局的值，
+    // 每个时间戳下的胜者各不相同，结果是确定的，所有结点的比较结论一致
+    pub fn fair(server_id: usize, time_stamp: u128) -> Self {
         Self {
             time_stamp,
+            tie_break: mix(time_stamp as u64 ^ mix(server_id as u64)),
             server_id,
         }
     }
+
+    // 提出该提案的服务器
+    pub fn server_id(&self) -> usize {
+        self.server_id
+    }
+
+    pub fn time_stamp(&self) -> u128 {
+        self.time_stamp
+    }
 }
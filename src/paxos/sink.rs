@@ -0,0 +1,39 @@
+use std::fmt::Debug;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::path::Path;
+
+use super::ValueType;
+
+/*
+选定值的下游出口。结点第一次学习到某个槽位的值时调用 on_decided，
+同一个结点对同一个槽位只调用一次，槽位按学习的先后依次到达。
+写出失败时结点照常学习，只记下错误：选定值可以随时从其他结点重新读到。
+
+目前只有写文件一种实现。发布到 Kafka 这类外部系统只需再实现这个 trait，
+放在单独的 feature 后面；现有依赖中没有 Kafka 客户端，因此暂时没有提供。
+*/
+pub trait DecisionSink: Debug + Send + Sync {
+    fn on_decided(&mut self, slot: usize, value: ValueType) -> io::Result<()>;
+}
+
+// 每个决议追加一行 "<槽位> <值>"，写完后 fsync，返回时已经落盘
+#[derive(Debug)]
+pub struct FileSink {
+    file: File,
+}
+
+impl FileSink {
+    // 文件已存在时接着追加
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Self { file })
+    }
+}
+
+impl DecisionSink for FileSink {
+    fn on_decided(&mut self, slot: usize, value: ValueType) -> io::Result<()> {
+        writeln!(self.file, "{} {}", slot, value)?;
+        self.file.sync_data()
+    }
+}
@@ -0,0 +1,148 @@
+use std::fmt::Debug;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use super::proposal::{AcceptedProposal, NodeSnapshot};
+use super::seq_num::SequenceNumber;
+
+/*
+决策结点的持久状态。承诺与接受必须先落盘再回应，否则重启后可能违背自己说过的话：
+    persist_promise: 承诺了 seq，之后不再接受更低的提案
+    persist_accept:  接受了某个提案
+    persist_chosen:  学习到选定的提案
+    load:            启动时读回上一次的状态，从未写过时为空
+写入失败时结点不回应，对方视同丢包。
+*/
+pub trait StateStore: Debug + Send + Sync {
+    fn persist_promise(&mut self, seq: SequenceNumber) -> io::Result<()>;
+    fn persist_accept(&mut self, accepted: AcceptedProposal) -> io::Result<()>;
+    fn persist_chosen(&mut self, chosen: AcceptedProposal) -> io::Result<()>;
+    fn load(&mut self) -> io::Result<NodeSnapshot>;
+}
+
+#[derive(Debug, Default)]
+struct Memory {
+    snapshot: NodeSnapshot,
+    delay: Duration, // 每次写入前的停顿，模拟慢盘
+    failing: bool,   // 写入一律失败
+    writes: u64,     // 成功写入的次数
+}
+
+// 存在内存里，供测试使用。克隆出的句柄共享同一份状态，
+// 交给结点之后仍可以注入延迟与失败，或者检查写入了什么
+#[derive(Debug, Default, Clone)]
+pub struct MemoryStore(Arc<Mutex<Memory>>);
+
+impl MemoryStore {
+    pub fn set_delay(&self, delay: Duration) {
+        self.0.lock().unwrap().delay = delay;
+    }
+
+    pub fn set_failing(&self, failing: bool) {
+        self.0.lock().unwrap().failing = failing;
+    }
+
+    pub fn snapshot(&self) -> NodeSnapshot {
+        self.0.lock().unwrap().snapshot.clone()
+    }
+
+    pub fn writes(&self) -> u64 {
+        self.0.lock().unwrap().writes
+    }
+
+    fn write(&self, update: impl FnOnce(&mut NodeSnapshot)) -> io::Result<()> {
+        let delay = self.0.lock().unwrap().delay;
+        // 停顿时不持锁，测试可以同时观察
+        if !delay.is_zero() {
+            std::thread::sleep(delay);
+        }
+        let mut memory = self.0.lock().unwrap();
+        if memory.failing {
+            return Err(io::Error::other("injected write failure"));
+        }
+        update(&mut memory.snapshot);
+        memory.writes += 1;
+        Ok(())
+    }
+}
+
+impl StateStore for MemoryStore {
+    fn persist_promise(&mut self, seq: SequenceNumber) -> io::Result<()> {
+        self.write(|snapshot| snapshot.last_promised = Some(seq))
+    }
+
+    fn persist_accept(&mut self, accepted: AcceptedProposal) -> io::Result<()> {
+        self.write(|snapshot| snapshot.last_accepted = Some(accepted))
+    }
+
+    fn persist_chosen(&mut self, chosen: AcceptedProposal) -> io::Result<()> {
+        self.write(|snapshot| snapshot.chosen = Some(chosen))
+    }
+
+    fn load(&mut self) -> io::Result<NodeSnapshot> {
+        Ok(self.snapshot())
+    }
+}
+
+// 每次写入都把整个快照写到临时文件、fsync 后改名覆盖，崩溃时要么是旧状态要么是新状态
+#[derive(Debug)]
+pub struct FileStore {
+    path: PathBuf,
+    snapshot: NodeSnapshot,
+}
+
+impl FileStore {
+    // 文件已存在时沿用其中的状态
+    pub fn open(path: impl Into<PathBuf>) -> io::Result<Self> {
+        let mut store = Self {
+            path: path.into(),
+            snapshot: NodeSnapshot::default(),
+        };
+        store.snapshot = store.read()?;
+        Ok(store)
+    }
+
+    fn read(&self) -> io::Result<NodeSnapshot> {
+        match fs::read(&self.path) {
+            Ok(data) => bincode::deserialize(&data)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(NodeSnapshot::default()),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn write(&mut self, update: impl FnOnce(&mut NodeSnapshot)) -> io::Result<()> {
+        let mut snapshot = self.snapshot.clone();
+        update(&mut snapshot);
+        let data = bincode::serialize(&snapshot).map_err(io::Error::other)?;
+        let tmp = self.path.with_extension("tmp");
+        let mut file = File::create(&tmp)?;
+        file.write_all(&data)?;
+        file.sync_all()?;
+        fs::rename(&tmp, &self.path)?;
+        // 写成功后才更新内存中的副本
+        self.snapshot = snapshot;
+        Ok(())
+    }
+}
+
+impl StateStore for FileStore {
+    fn persist_promise(&mut self, seq: SequenceNumber) -> io::Result<()> {
+        self.write(|snapshot| snapshot.last_promised = Some(seq))
+    }
+
+    fn persist_accept(&mut self, accepted: AcceptedProposal) -> io::Result<()> {
+        self.write(|snapshot| snapshot.last_accepted = Some(accepted))
+    }
+
+    fn persist_chosen(&mut self, chosen: AcceptedProposal) -> io::Result<()> {
+        self.write(|snapshot| snapshot.chosen = Some(chosen))
+    }
+
+    fn load(&mut self) -> io::Result<NodeSnapshot> {
+        self.read()
+    }
+}
@@ -0,0 +1,20 @@
+// 作为库使用时最常用的类型，`use paxos::prelude::*;` 即可启动集群、提案与查询，
+// 不必关心它们各自位于哪个模块
+
+pub use crate::client::{ClientSession, SessionError};
+pub use crate::net_proxy::{AddrTable, ConnState, PeerConn, Proxy, ProxyError, SocketOptions};
+pub use crate::paxos::ballot::{BallotStrategy, CounterBallots, FloorBallots, TimestampBallots};
+pub use crate::paxos::node::{Node, NodeControl, NodeEvent, NodeState, NodeStats};
+pub use crate::paxos::proposal::{
+    AcceptedProposal, AcceptorState, Datagram, DecisionRecord, Incoming, NodeSnapshot, Outgoing,
+    Request, Response, DEFAULT_GROUP,
+};
+pub use crate::paxos::seq_num::SequenceNumber;
+pub use crate::paxos::sink::{DecisionSink, FileSink};
+pub use crate::paxos::store::{FileStore, MemoryStore, StateStore};
+pub use crate::paxos::{majority, Rx, Tx, ValueType};
+pub use crate::shell::{
+    ClusterDump, ClusterStats, Command, Console, LagReport, ParseCommandError, ProbeReport,
+    ServerDump, StressReport, Verbosity,
+};
+pub use crate::shutdown::Shutdown;
@@ -1,16 +1,40 @@
-use futures::channel::mpsc;
-use std::collections::HashMap;
-use std::io::BufRead;
+use futures::channel::{mpsc, oneshot};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::io::{self, BufRead};
 use std::net::SocketAddr;
 use std::str::FromStr;
-use std::sync::Arc;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
 use tokio::net::TcpStream;
 use tokio::prelude::*;
+use tokio::stream::StreamExt;
+use tokio::task::JoinHandle;
 
-use crate::net_proxy::Proxy;
-use crate::paxos::node::Node;
-use crate::paxos::proposal::{Datagram, Request};
-use crate::paxos::ValueType;
+use crate::client::{self, ClientSession};
+use crate::net_proxy::{AddrTable, ConnState, PeerConn, Proxy};
+use crate::paxos::node::{Node, NodeControl, NodeState, READ_TIMEOUT};
+use crate::paxos::proposal::{
+    AcceptedProposal, AcceptorState, Datagram, Incoming, Outgoing, Request, Response, DEFAULT_GROUP,
+};
+use crate::paxos::seq_num::SequenceNumber;
+use crate::paxos::{majority, Rx, Tx, ValueType};
+use crate::shutdown::Shutdown;
+use crate::task_name::named;
+
+// 查询等待服务器应答的最长时间
+const QUERY_TIMEOUT: Duration = Duration::from_millis(500);
+// query --wait 等待值被选定的最长时间
+const WAIT_TIMEOUT: Duration = Duration::from_secs(5);
+// 提案时最多跟随的重定向次数，防止结点之间互相指向
+const MAX_REDIRECTS: usize = 3;
+// ping 每台服务器的次数
+const PING_COUNT: u32 = 5;
+// 判断集群是否安静的轮询间隔
+const QUIESCENT_POLL_INTERVAL: Duration = Duration::from_millis(20);
 
 macro_rules! print_flushed {
     ($($tokens: tt)*) => {
@@ -39,44 +63,556 @@ macro_rules! println_flushed {
 #[derive(Debug, PartialEq)]
 pub enum Command {
     Start(usize),
-    Propose(usize, ValueType),
+    Propose(usize, ValueType, u8),
+    ProposeWait(usize, ValueType, u8),
+    ForcePropose(usize, ValueType),
     Query(usize),
+    QueryWait(usize),
+    QueryWithin(usize, u64),
+    QueryReadIndex(usize),
+    QueryAll,
+    Migrate(usize, SocketAddr),
+    Skew(usize, i64),
+    Probe,
+    ProbeServer(usize),
+    Gossip(usize),
+    TieBreak(bool),
+    Redirect(bool),
+    Slow(usize, u64),
+    Drop(usize, u64, u64),
+    Proposer(usize),
+    Group(usize),
+    Who(usize),
+    Pause(usize),
+    Barrier,
+    Stats,
+    Metrics(u64),
+    Lag,
+    Ping(usize),
+    PingAll,
+    Dump(Option<String>),
+    Conns(usize),
+    Stress(u64, usize),
+    Resume(usize),
+    #[cfg(feature = "http")]
+    Http(SocketAddr, usize),
+    #[cfg(feature = "ws")]
+    Ws(SocketAddr),
+    #[cfg(feature = "debug")]
+    Inject(usize, usize, Datagram),
+    #[cfg(feature = "debug")]
+    Lie(usize, bool),
+    #[cfg(feature = "debug")]
+    Seq(usize),
     Exit,
 }
 
 #[derive(Debug, PartialEq)]
-pub struct ParseCommandError;
+pub enum ParseCommandError {
+    Empty,
+    UnknownCommand(String),
+    TooFewArgs(&'static str),  // 附带该命令的用法
+    TooManyArgs(&'static str), // 同上
+    InvalidArgs(&'static str), // 个数对，但不是可选的取值之一，例如 redirect maybe
+    ExpectedNumber(String),
+    InvalidAddr(String),
+    #[cfg(feature = "debug")]
+    InvalidDatagram(String),
+}
+
+impl fmt::Display for ParseCommandError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Empty => write!(f, "empty command"),
+            Self::UnknownCommand(name) => write!(f, "unknown command `{}`", name),
+            Self::TooFewArgs(usage) => write!(f, "too few arguments, usage: {}", usage),
+            Self::TooManyArgs(usage) => write!(f, "too many arguments, usage: {}", usage),
+            Self::InvalidArgs(usage) => write!(f, "invalid arguments, usage: {}", usage),
+            Self::ExpectedNumber(token) => write!(f, "expected a number, got `{}`", token),
+            Self::InvalidAddr(token) => {
+                write!(
+                    f,
+                    "expected an address like 127.0.0.1:9527, got `{}`",
+                    token
+                )
+            }
+            #[cfg(feature = "debug")]
+            Self::InvalidDatagram(e) => write!(f, "invalid datagram: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for ParseCommandError {}
+
+#[cfg(feature = "debug")]
+impl From<crate::json::Error> for ParseCommandError {
+    fn from(e: crate::json::Error) -> Self {
+        Self::InvalidDatagram(e.to_string())
+    }
+}
+
+// 各命令的名字、参数个数范围与用法，解析失败时据此给出提示。
+// 新增命令时两处都要改，test_usages 检查每条命令都能在这里找到
+const USAGES: &[(&[&str], usize, usize, &str)] = &[
+    (&["s", "start"], 1, 1, "start <num>"),
+    (
+        &["p", "propose"],
+        2,
+        4,
+        "propose [--wait] <id> <value> [priority]",
+    ),
+    (&["forcepropose"], 2, 2, "forcepropose <id> <value>"),
+    (
+        &["q", "query"],
+        1,
+        3,
+        "query [--wait | --within <ms> | --read-index] <id>",
+    ),
+    (&["qa", "queryall"], 0, 0, "queryall"),
+    (&["m", "migrate"], 2, 2, "migrate <id> <addr>"),
+    (&["skew"], 2, 2, "skew <id> <delta_ms>"),
+    (&["probe"], 0, 1, "probe [id]"),
+    (&["gossip"], 1, 1, "gossip <fanout>"),
+    (&["redirect"], 1, 1, "redirect on|off"),
+    (&["tiebreak"], 1, 1, "tiebreak fair|id"),
+    (&["slow"], 2, 2, "slow <id> <ms>"),
+    (&["drop"], 3, 3, "drop <id> <per_mille> <seed>"),
+    (&["proposer"], 1, 1, "proposer <id>"),
+    (&["g", "group"], 1, 1, "group <group>"),
+    (&["who"], 1, 1, "who <id>"),
+    (&["pause"], 1, 1, "pause <id>"),
+    (&["barrier"], 0, 0, "barrier"),
+    (&["stats"], 0, 0, "stats"),
+    (&["metrics"], 1, 1, "metrics <interval_ms>"),
+    (&["lag"], 0, 0, "lag"),
+    (&["ping"], 1, 1, "ping <id>"),
+    (&["pingall"], 0, 0, "pingall"),
+    (&["dump"], 0, 1, "dump [file]"),
+    (&["conns"], 1, 1, "conns <id>"),
+    (&["stress"], 2, 2, "stress <seconds> <concurrency>"),
+    (&["resume"], 1, 1, "resume <id>"),
+    #[cfg(feature = "debug")]
+    (&["lie"], 1, 2, "lie <id> [off]"),
+    #[cfg(feature = "debug")]
+    (&["seq"], 1, 1, "seq <id>"),
+    #[cfg(feature = "debug")]
+    (&["inject"], 3, usize::MAX, "inject <from> <to> <json>"),
+    #[cfg(feature = "http")]
+    (&["http"], 2, 2, "http <addr> <id>"),
+    #[cfg(feature = "ws")]
+    (&["ws"], 1, 1, "ws <addr>"),
+    (&["x", "exit"], 0, 0, "exit"),
+];
+
+// 没有匹配上任何一种写法时，说明具体错在哪里
+fn usage_error(tokens: &[&str]) -> ParseCommandError {
+    let (name, args) = match tokens.split_first() {
+        Some(split) => split,
+        None => return ParseCommandError::Empty,
+    };
+    match USAGES.iter().find(|(names, ..)| names.contains(name)) {
+        Some(&(_, min, _, usage)) if args.len() < min => ParseCommandError::TooFewArgs(usage),
+        Some(&(_, _, max, usage)) if args.len() > max => ParseCommandError::TooManyArgs(usage),
+        Some(&(.., usage)) => ParseCommandError::InvalidArgs(usage),
+        None => ParseCommandError::UnknownCommand(name.to_string()),
+    }
+}
+
+fn num<T: FromStr>(token: &str) -> Result<T, ParseCommandError> {
+    token
+        .parse()
+        .map_err(|_| ParseCommandError::ExpectedNumber(token.to_string()))
+}
+
+fn addr(token: &str) -> Result<SocketAddr, ParseCommandError> {
+    token
+        .parse()
+        .map_err(|_| ParseCommandError::InvalidAddr(token.to_string()))
+}
 
 impl FromStr for Command {
     type Err = ParseCommandError;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
+        // inject 的报文是大小写敏感的 JSON，需要在转小写之前单独解析
+        #[cfg(feature = "debug")]
+        if let ("inject", rest) = split_token(s) {
+            let (from, rest) = split_token(rest);
+            let (to, json) = split_token(rest);
+            if json.trim().is_empty() {
+                let tokens: Vec<&str> = s.split_whitespace().collect();
+                return Err(usage_error(&tokens));
+            }
+            let (from, to) = (num(from)?, num(to)?);
+            return Ok(Self::Inject(from, to, crate::json::from_str(json)?));
+        }
+
         let lower = s.to_lowercase();
         let tokens: Vec<&str> = lower.split_whitespace().collect();
+        // 文件路径大小写敏感，从原始输入里取
+        let original: Vec<&str> = s.split_whitespace().collect();
         Ok(match tokens[..] {
-            ["s" | "start", num] => Self::Start(num.parse().unwrap()),
-            ["p" | "propose", id, val] => Self::Propose(id.parse().unwrap(), val.parse().unwrap()),
-            ["q" | "query", id] => Self::Query(id.parse().unwrap()),
+            ["s" | "start", n] => Self::Start(num(n)?),
+            ["p" | "propose", "--wait", id, val] => Self::ProposeWait(num(id)?, num(val)?, 0),
+            ["p" | "propose", "--wait", id, val, priority] => {
+                Self::ProposeWait(num(id)?, num(val)?, num(priority)?)
+            }
+            ["p" | "propose", id, val] => Self::Propose(num(id)?, num(val)?, 0),
+            ["p" | "propose", id, val, priority] => {
+                Self::Propose(num(id)?, num(val)?, num(priority)?)
+            }
+            ["forcepropose", id, val] => Self::ForcePropose(num(id)?, num(val)?),
+            ["q" | "query", "--wait", id] => Self::QueryWait(num(id)?),
+            ["q" | "query", "--within", ms, id] => Self::QueryWithin(num(id)?, num(ms)?),
+            ["q" | "query", "--read-index", id] => Self::QueryReadIndex(num(id)?),
+            ["q" | "query", id] => Self::Query(num(id)?),
+            ["qa" | "queryall"] => Self::QueryAll,
+            ["m" | "migrate", id, a] => Self::Migrate(num(id)?, addr(a)?),
+            ["skew", id, delta] => Self::Skew(num(id)?, num(delta)?),
+            ["probe"] => Self::Probe,
+            ["probe", id] => Self::ProbeServer(num(id)?),
+            ["gossip", fanout] => Self::Gossip(num(fanout)?),
+            ["redirect", "on"] => Self::Redirect(true),
+            ["redirect", "off"] => Self::Redirect(false),
+            ["tiebreak", "fair"] => Self::TieBreak(true),
+            ["tiebreak", "id"] => Self::TieBreak(false),
+            ["slow", id, ms] => Self::Slow(num(id)?, num(ms)?),
+            ["drop", id, per_mille, seed] => Self::Drop(num(id)?, num(per_mille)?, num(seed)?),
+            ["proposer", id] => Self::Proposer(num(id)?),
+            ["g" | "group", group] => Self::Group(num(group)?),
+            ["who", id] => Self::Who(num(id)?),
+            ["pause", id] => Self::Pause(num(id)?),
+            ["barrier"] => Self::Barrier,
+            ["stats"] => Self::Stats,
+            ["metrics", ms] => Self::Metrics(num(ms)?),
+            ["lag"] => Self::Lag,
+            ["ping", id] => Self::Ping(num(id)?),
+            ["pingall"] => Self::PingAll,
+            ["dump"] => Self::Dump(None),
+            ["dump", _] => Self::Dump(Some(original[1].to_string())),
+            ["conns", id] => Self::Conns(num(id)?),
+            ["stress", secs, concurrency] => Self::Stress(num(secs)?, num(concurrency)?),
+            ["resume", id] => Self::Resume(num(id)?),
+            #[cfg(feature = "debug")]
+            ["lie", id] => Self::Lie(num(id)?, true),
+            #[cfg(feature = "debug")]
+            ["lie", id, "off"] => Self::Lie(num(id)?, false),
+            #[cfg(feature = "debug")]
+            ["seq", id] => Self::Seq(num(id)?),
+            #[cfg(feature = "http")]
+            ["http", a, id] => Self::Http(addr(a)?, num(id)?),
+            #[cfg(feature = "ws")]
+            ["ws", a] => Self::Ws(addr(a)?),
             ["x" | "exit"] => Self::Exit,
 
-            _ => return Err(ParseCommandError),
+            _ => return Err(usage_error(&tokens)),
         })
     }
 }
 
+// probe 的汇总结果：各决策结点的状态
+#[derive(Debug, Default)]
+pub struct ProbeReport {
+    pub states: HashMap<usize, AcceptorState>,
+    pub acceptors: usize, // 本组决策结点的总数，用来判断多数派
+}
+
+impl ProbeReport {
+    // 目前最高的承诺，即进行中的最高提案序号
+    pub fn highest_promised(&self) -> Option<SequenceNumber> {
+        self.states.values().filter_map(|s| s.last_promised).max()
+    }
+
+    pub fn highest_accepted(&self) -> Option<AcceptedProposal> {
+        self.states
+            .values()
+            .filter_map(|s| s.last_accepted)
+            .max_by_key(|p| p.seq)
+    }
+
+    // 已被多数派在同一个提案中接受的值即已选定，之后不会再变
+    pub fn locked_in(&self) -> Option<AcceptedProposal> {
+        let mut votes: BTreeMap<SequenceNumber, (AcceptedProposal, usize)> = BTreeMap::new();
+        for accepted in self.states.values().filter_map(|s| s.last_accepted) {
+            votes.entry(accepted.seq).or_insert((accepted, 0)).1 += 1;
+        }
+        votes
+            .into_values()
+            .find(|&(_, n)| n >= majority(self.acceptors))
+            .map(|(accepted, _)| accepted)
+    }
+
+    // value 是否还有机会被选定：只要没有别的值锁定，再提案就不是徒劳的。
+    // 只看到部分决策结点时，锁定可能发生在没有回应的结点上，因此结论偏乐观
+    pub fn can_still_choose(&self, value: ValueType) -> bool {
+        self.locked_in().is_none_or(|chosen| chosen.val == value)
+    }
+}
+
+// stats 的汇总结果：本组所有服务器的统计之和
+#[derive(Debug, Default)]
+pub struct ClusterStats {
+    pub proposals: u64,
+    pub rounds: u64,
+    pub decided: u64,
+    pub messages: BTreeMap<&'static str, u64>,
+    pub dropped: u64,
+    pub violations: u64,
+    pub connections: usize, // 此刻正在服务的接入连接数
+}
+
+impl ClusterStats {
+    // 平均每次决议需要几轮 prepare，还没有决议时为 None
+    pub fn rounds_per_decision(&self) -> Option<f64> {
+        match self.decided {
+            0 => None,
+            decided => Some(self.rounds as f64 / decided as f64),
+        }
+    }
+}
+
+// lag 的结果：本组最高的提交位置，以及各服务器的提交位置
+#[derive(Debug, Default)]
+pub struct LagReport {
+    pub highest: u64,
+    pub commit_index: BTreeMap<usize, u64>,
+}
+
+impl LagReport {
+    pub fn lag(&self, server_id: usize) -> Option<u64> {
+        self.commit_index
+            .get(&server_id)
+            .map(|&index| self.highest - index)
+    }
+}
+
+// dump 导出的单台服务器状态，字段都是自有类型，工具可以原样读回
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ServerDump {
+    pub id: usize,
+    pub addr: Option<SocketAddr>,
+    pub role: String,  // 指定了提案者时为 proposer 或 acceptor，否则为 peer
+    pub state: String, // NodeState 的名字
+    pub last_promised: Option<SequenceNumber>,
+    pub last_accepted: Option<AcceptedProposal>,
+    pub chosen: Option<AcceptedProposal>,
+    pub commit_index: u64,
+    pub metrics: BTreeMap<String, u64>, // 协议统计、代理计数以及 msg.<kind> 形式的各类报文数
+}
+
+// dump 导出的整个组，服务器按 ID 排列
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+pub struct ClusterDump {
+    pub group: usize,
+    pub servers: Vec<ServerDump>,
+}
+
+// stress 的结果。整个运行至多达成一次共识，之后的提案都直接拿到
+// 已选定的值，因此回答数、吞吐与时延衡量的是回答提案，不是达成共识
+#[derive(Debug, Default)]
+pub struct StressReport {
+    pub elapsed: Duration,
+    pub decisions: u64,           // 运行期间新达成的共识数，至多为 1
+    pub latencies: Vec<Duration>, // 拿到结果的提案从发出到拿到选定值的耗时，升序
+    pub failures: u64,            // 超时、连不上或重定向过多的提案
+    pub violations: u64,          // 结点记录的违例，加上客户端与服务器之间读到的不一致
+}
+
+impl StressReport {
+    // 拿到结果的提案数，包括选定之后直接得到已选定值的
+    pub fn answered(&self) -> usize {
+        self.latencies.len()
+    }
+
+    // 每秒拿到结果的提案数
+    pub fn throughput(&self) -> f64 {
+        match self.elapsed.as_secs_f64() {
+            secs if secs > 0.0 => self.answered() as f64 / secs,
+            _ => 0.0,
+        }
+    }
+
+    // p 取 0 到 100，还没有成功的提案时为 None
+    pub fn percentile(&self, p: f64) -> Option<Duration> {
+        let last = self.latencies.len().checked_sub(1)?;
+        let index = (last as f64 * p.clamp(0.0, 100.0) / 100.0).round() as usize;
+        Some(self.latencies[index])
+    }
+}
+
+// ping 的结果：若干次往返时延的最小、平均与最大值
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RttReport {
+    pub min: Duration,
+    pub avg: Duration,
+    pub max: Duration,
+}
+
+// queryall 的结果：回应了的服务器及其学习到的值，以及没有回应的服务器
+#[derive(Debug, Default)]
+pub struct QueryAllReport {
+    pub answers: HashMap<usize, Option<ValueType>>,
+    pub missing: Vec<usize>,
+}
+
+// start_servers 启动后打印的摘要，#0 是客户端，#1..=#n 是决策结点
+pub fn start_banner(server_num: usize, base_port: usize) -> String {
+    format!(
+        "paxos v{}: started {} acceptors on ports {}..{}, quorum={}, transport=TCP, client #0 on port {}.",
+        env!("CARGO_PKG_VERSION"),
+        server_num,
+        base_port + 1,
+        base_port + server_num,
+        majority(server_num),
+        base_port
+    )
+}
+
+// 以 group 组客户端 #0 的身份发送一个请求
+async fn send_request(group: usize, addr: SocketAddr, req: Request) {
+    if let Ok(mut stream) = TcpStream::connect(addr).await {
+        let dgram = Datagram::Request(req);
+        stream.write_all(&dgram.encode(group, 0)).await.unwrap();
+    }
+}
+
+// 一台服务器对应的代理与结点任务
+struct Server {
+    shutdown: oneshot::Sender<()>, // 代理与结点的所有任务共用一个停机信号
+    proxy: JoinHandle<Result<(), tokio::io::Error>>,
+    node: JoinHandle<Node>,
+    control: Arc<NodeControl>,
+    transport: Arc<Proxy>, // 用于调整代理的丢包等设置，迁移后换成新的代理
+    inbox: Tx<Incoming>,   // 结点的收件箱，控制台可以不经过网络直接送入消息
+}
+
+type ServerTasks = (JoinHandle<Result<(), tokio::io::Error>>, JoinHandle<Node>);
+
+// 客户端 #0 只有代理，收到的响应交给控制台处理
+struct Client {
+    _proxy_shutdown: oneshot::Sender<()>,
+    inbox: Rx<Incoming>,
+}
+
+// 一组独立的服务器，有自己的成员、地址表与选定的值
+#[derive(Default)]
+struct Group {
+    addr_table: Option<AddrTable>,
+    servers: HashMap<usize, Server>,
+    client: Option<Client>,
+    #[cfg(feature = "http")]
+    gateway: Option<oneshot::Sender<()>>,
+    #[cfg(feature = "ws")]
+    events: Option<oneshot::Sender<()>>,
+}
+
+// 切出第一个词，返回它和剩余部分
+#[cfg(feature = "debug")]
+fn split_token(s: &str) -> (&str, &str) {
+    let s = s.trim_start();
+    match s.find(char::is_whitespace) {
+        Some(i) => (&s[..i], &s[i..]),
+        None => (s, ""),
+    }
+}
+
+fn apply_verbosity(server: &Server, verbosity: Verbosity) {
+    server.control.set_quiet(verbosity == Verbosity::Quiet);
+    server.transport.set_quiet(verbosity == Verbosity::Quiet);
+    server
+        .transport
+        .set_log_connections(verbosity == Verbosity::Verbose);
+}
+
+// 服务器输出的详细程度，控制台自己的输出不受影响
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Verbosity {
+    Quiet, // 不输出结点处理每条消息的日志
+    #[default]
+    Normal,
+    Verbose, // 另外记录每个接入连接的建立与关闭
+}
+
 pub struct Console {
     rt: tokio::runtime::Runtime,
-    addr_table: Option<Arc<HashMap<usize, SocketAddr>>>,
+    groups: HashMap<usize, Group>,
+    current: usize,       // 命令作用的组
+    verbosity: Verbosity, // 对所有组生效，之后启动的服务器也一样
+    dedicated: bool,      // 之后启动的服务器各自跑在独立的线程与单线程运行时上
+    rng: StdRng,          // 控制台与结点的随机选择都从这里派生，设定种子后整个运行可以复现
+}
+
+impl Default for Console {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Console {
     pub fn new() -> Self {
         Self {
             rt: tokio::runtime::Runtime::new().unwrap(),
-            addr_table: None,
+            groups: std::iter::once((DEFAULT_GROUP, Group::default())).collect(),
+            current: DEFAULT_GROUP,
+            verbosity: Verbosity::default(),
+            dedicated: false,
+            rng: StdRng::from_entropy(),
+        }
+    }
+
+    // 之后启动的结点、ping 的随机数与 stress 挑选的服务器和值都由 seed 决定。
+    // 丢包由 drop 命令自带的种子决定
+    pub fn set_seed(&mut self, seed: u64) {
+        self.rng = StdRng::seed_from_u64(seed);
+    }
+
+    // 供调用者从同一个随机源取数，例如打乱要提出的值
+    pub fn rng(&mut self) -> &mut StdRng {
+        &mut self.rng
+    }
+
+    // 打开后每个结点连同它的代理独占一个线程，某个结点阻塞时不会拖慢其他结点。
+    // 只影响之后启动的服务器
+    pub fn set_dedicated_runtimes(&mut self, dedicated: bool) {
+        self.dedicated = dedicated;
+    }
+
+    pub fn set_verbosity(&mut self, verbosity: Verbosity) {
+        self.verbosity = verbosity;
+        for server in self
+            .groups
+            .values()
+            .flat_map(|group| group.servers.values())
+        {
+            apply_verbosity(server, verbosity);
         }
     }
 
+    fn group(&self) -> &Group {
+        &self.groups[&self.current]
+    }
+
+    fn group_mut(&mut self) -> &mut Group {
+        self.groups.get_mut(&self.current).unwrap()
+    }
+
+    // 同时借出运行时与当前组
+    fn split(&mut self) -> (&mut tokio::runtime::Runtime, &mut Group) {
+        let Self {
+            rt,
+            groups,
+            current,
+            ..
+        } = self;
+        (rt, groups.get_mut(current).unwrap())
+    }
+
+    // 之后的命令都作用于 group 组，组不存在时新建一个空组
+    pub fn select_group(&mut self, group: usize) {
+        self.groups.entry(group).or_default();
+        self.current = group;
+    }
+
     pub fn run(mut self) {
         let stdin = std::io::stdin();
         let handle = stdin.lock();
@@ -84,18 +620,134 @@ impl Console {
         for line in handle.lines() {
             // 解析命令
             if let Ok(line) = line {
-                if let Ok(cmd) = line.parse() {
-                    match cmd {
+                match line.parse() {
+                    Ok(cmd) => match cmd {
                         // 启动 num 个服务器
-                        Command::Start(num) => self.start_servers(num, 9527),
+                        // 每组占用不同的端口段
+                        Command::Start(num) => self.start_servers(num, 9527 + 100 * self.current),
                         // server_id 号服务器提交值 val
-                        Command::Propose(server_id, val) => self.propose(server_id, val),
+                        Command::Propose(server_id, val, priority) => {
+                            self.propose_with_priority(server_id, val, priority)
+                        }
+                        // 同上，等到选定后报告耗时
+                        Command::ProposeWait(server_id, val, priority) => {
+                            self.propose_timed(server_id, val, priority, WAIT_TIMEOUT);
+                        }
+                        // 让 server_id 号服务器自己发起提案，不经过客户端 #0
+                        Command::ForcePropose(server_id, val) => self.force_propose(server_id, val),
                         // 查询 server_id 号服务器
-                        Command::Query(server_id) => self.query(server_id),
+                        Command::Query(server_id) => {
+                            self.query(server_id);
+                        }
+                        // 查询所有服务器，跳过没有及时回应的
+                        Command::QueryAll => {
+                            self.query_all(QUERY_TIMEOUT, WAIT_TIMEOUT);
+                        }
+                        // 阻塞直到 server_id 号服务器学习到值
+                        Command::QueryWait(server_id) => {
+                            self.query_wait(server_id, WAIT_TIMEOUT);
+                        }
+                        // 直接读 server_id 号服务器，还没学习到值时，距它上次收到 learn 或心跳超过 ms 毫秒就不接受
+                        Command::QueryWithin(server_id, ms) => {
+                            self.query_within(server_id, Duration::from_millis(ms));
+                        }
+                        // 线性一致读：server_id 号服务器先确认多数派仍认可它，再回答
+                        Command::QueryReadIndex(server_id) => {
+                            self.query_read_index(server_id);
+                        }
+                        // 将 server_id 号服务器迁移到新地址
+                        Command::Migrate(server_id, addr) => self.migrate(server_id, addr),
+                        // 让 server_id 号服务器的时钟偏移 delta 毫秒
+                        Command::Skew(server_id, delta) => self.skew(server_id, delta),
+                        // 查看半数以上决策结点的承诺与接受情况
+                        Command::Probe => {
+                            self.probe();
+                        }
+                        // 只探查 server_id 号服务器
+                        Command::ProbeServer(server_id) => {
+                            self.probe_server(server_id);
+                        }
+                        // 学习到值后转告 fanout 个随机结点，0 表示关闭
+                        Command::Gossip(fanout) => self.set_gossip_fanout(fanout),
+                        // 时间戳相同时由 fair 轮流决定胜者，id 则总是 ID 大的胜出
+                        Command::TieBreak(fair) => self.set_fair_tie_break(fair),
+                        Command::Redirect(redirect) => self.set_redirect(redirect),
+                        // server_id 号服务器处理每条消息前停顿 ms 毫秒
+                        Command::Slow(server_id, ms) => self.slow(server_id, ms),
+                        // server_id 号服务器发出的报文按千分比 per_mille 丢弃，seed 决定丢哪些
+                        Command::Drop(server_id, per_mille, seed) => {
+                            self.set_drop_rate(server_id, per_mille, seed)
+                        }
+                        // 只让 server_id 号服务器提案，其余只做决策者；0 表示取消
+                        Command::Proposer(0) => self.set_proposer(None),
+                        Command::Proposer(server_id) => self.set_proposer(Some(server_id)),
+                        // 查看 server_id 号服务器选定的值由谁提出
+                        Command::Who(server_id) => {
+                            self.who(server_id);
+                        }
+                        // 在 addr 上开启 HTTP 网关，请求都转给 server_id 号服务器
+                        #[cfg(feature = "http")]
+                        Command::Http(addr, server_id) => self.start_http(addr, server_id),
+                        // 在 addr 上推送本组所有结点的事件
+                        #[cfg(feature = "ws")]
+                        Command::Ws(addr) => self.start_ws(addr),
+                        // 暂停或恢复 server_id 号服务器处理消息，期间消息排队等候
+                        Command::Pause(server_id) => self.pause(server_id, true),
+                        Command::Resume(server_id) => self.pause(server_id, false),
+                        // 等到没有进行中的提案和消息
+                        Command::Barrier => {
+                            if self.await_quiescent(WAIT_TIMEOUT) {
+                                println_flushed!("Cluster is quiescent.");
+                            }
+                        }
+                        // 汇总本组所有服务器启动以来的协议统计
+                        Command::Stats => {
+                            self.stats();
+                        }
+                        // 每台服务器每隔 ms 毫秒输出一行统计，0 表示关闭
+                        Command::Metrics(ms) => self.set_metrics_interval(ms),
+                        // 各服务器落后于最新提交位置多少个槽位
+                        Command::Lag => {
+                            self.lag();
+                        }
+                        // 测量到 server_id 号服务器或所有服务器的往返时延
+                        Command::Ping(server_id) => {
+                            self.ping(server_id);
+                        }
+                        Command::PingAll => {
+                            self.ping_all();
+                        }
+                        // 把整个组的状态以 JSON 写到标准输出或文件
+                        Command::Dump(path) => self.dump_to(path.as_deref()),
+                        // server_id 号服务器与各结点之间的连接状态与收发字节数
+                        Command::Conns(server_id) => {
+                            self.conns(server_id);
+                        }
+                        // 持续 secs 秒、保持 concurrency 个提案在途，结束时报告吞吐、时延与违例
+                        Command::Stress(secs, concurrency) => {
+                            self.stress(Duration::from_secs(secs), concurrency);
+                        }
+                        // 切换到 group 组，之后的命令都作用于该组
+                        Command::Group(group) => {
+                            self.select_group(group);
+                            println_flushed!("Switched to group {}.", group);
+                        }
+                        // 以 from 的身份直接向 to 发送任意报文
+                        #[cfg(feature = "debug")]
+                        Command::Inject(from, to, dgram) => {
+                            self.inject(from, to, dgram);
+                        }
+                        // 让 server_id 号服务器在回应 prepare 时谎报接受过的值
+                        #[cfg(feature = "debug")]
+                        Command::Lie(server_id, liar) => self.lie(server_id, liar),
+                        // 查看 server_id 号服务器下一个提案会用的序号
+                        #[cfg(feature = "debug")]
+                        Command::Seq(server_id) => {
+                            self.peek_seq(server_id);
+                        }
                         Command::Exit => break,
-                    }
-                } else {
-                    println_flushed!("Unknown command.");
+                    },
+                    Err(e) => println_flushed!("error: {}.", e),
                 }
             }
             // A slight pause waiting for servers' output.
@@ -107,68 +759,1309 @@ impl Console {
 
     // 从端口 base_port 启动 server_num 个服务器
     pub fn start_servers(&mut self, server_num: usize, base_port: usize) {
+        // 没有决策结点就不可能形成多数派
+        if server_num == 0 {
+            println_flushed!("error: need at least 1 server.");
+            return;
+        }
         let server_num = server_num + 1; // #0 转为客户端 client.
 
         // 将 ID 和 addr 绑定起来，建立一种映射关系
-        let addr_table: Arc<HashMap<usize, SocketAddr>> = Arc::new(
+        let addr_table: AddrTable = Arc::new(RwLock::new(
             ((base_port)..(base_port + server_num))
                 .enumerate()
                 .map(|(id, port)| (id, format!("127.0.0.1:{}", port).parse().unwrap()))
                 .collect(),
-        );
+        ));
+        self.group_mut().addr_table = Some(addr_table.clone());
+
+        // 客户端 #0 只启动代理，不需要向外发送
+        let (itx, irx) = mpsc::unbounded();
+        let (_, orx) = mpsc::unbounded();
+        let (shutdown, shutdown_rx) = Shutdown::new();
+        let proxy = Proxy::with_group(self.current, 0, addr_table);
+        let listener = match self.rt.block_on(proxy.bind()) {
+            Ok(listener) => listener,
+            Err(e) => {
+                println_flushed!("error: client can't listen: {}", e);
+                return;
+            }
+        };
+        self.rt.spawn(named(
+            "proxy-0".to_string(),
+            proxy.run(listener, itx, orx, shutdown_rx),
+        ));
+        self.group_mut().client = Some(Client {
+            _proxy_shutdown: shutdown,
+            inbox: irx,
+        });
 
         // 为每一个 ID 都建立一条到其他 id 的连接，包括自己到自己
-        let start_server = |id: usize| {
+        for id in 1..server_num {
             let (itx, irx) = mpsc::unbounded();
             let (otx, orx) = mpsc::unbounded();
             // 跳过客户端 #0
-            let node = Node::new(id, (1..server_num).collect(), otx, irx);
-            let proxy = Proxy::new(id, (*addr_table).clone());
-            self.rt.spawn(proxy.run(itx, orx));
-            self.rt.spawn(node.run());
+            let node = Node::new(id, (1..server_num).collect(), otx, irx).with_seed(self.rng.gen());
+            self.spawn_server(id, node, itx, orx);
+        }
+        println_flushed!("{}", start_banner(server_num - 1, base_port));
+    }
+
+    // 先绑定好监听地址再启动任务，保证返回后即可连接
+    fn spawn_server(&mut self, id: usize, node: Node, itx: Tx<Incoming>, orx: Rx<Outgoing>) {
+        let addr_table = self.group().addr_table.clone().unwrap();
+        let proxy = Proxy::with_group(self.current, id, addr_table);
+        let (shutdown, shutdown_rx) = Shutdown::new();
+        let control = node.control();
+        let gauge = proxy.clone();
+        control.set_connection_gauge(move || gauge.active_inflows());
+        let tasks = if self.dedicated {
+            self.spawn_dedicated(id, node, proxy.clone(), itx.clone(), orx, shutdown_rx)
+        } else {
+            self.spawn_shared(id, node, proxy.clone(), itx.clone(), orx, shutdown_rx)
         };
-        (0..server_num).for_each(|id| {
-            start_server(id);
-        });
-        self.addr_table = Some(addr_table.clone());
+        let (proxy_task, node_task) = match tasks {
+            Ok(tasks) => tasks,
+            Err(e) => {
+                println_flushed!("error: server #{} can't listen: {}", id, e);
+                return;
+            }
+        };
+        let server = Server {
+            shutdown,
+            proxy: proxy_task,
+            node: node_task,
+            control,
+            transport: proxy,
+            inbox: itx,
+        };
+        apply_verbosity(&server, self.verbosity);
+        self.group_mut().servers.insert(id, server);
     }
 
-    pub fn propose(&mut self, server_id: usize, val: ValueType) {
-        if let Some(addr_table) = &self.addr_table {
-            if let Some(addr) = addr_table.get(&server_id) {
-                let addr = addr.clone();
-                let task = async move {
-                    if let Ok(mut stream) = TcpStream::connect(addr).await {
-                        let dgram = Datagram::Request(Request::Propose { value: val });
-                        stream.write_all(&dgram.encode_with_src(0)).await.unwrap();
-                    }
+    fn spawn_shared(
+        &mut self,
+        id: usize,
+        node: Node,
+        proxy: Arc<Proxy>,
+        itx: Tx<Incoming>,
+        orx: Rx<Outgoing>,
+        shutdown_rx: Shutdown,
+    ) -> io::Result<ServerTasks> {
+        let listener = self.rt.block_on(proxy.bind())?;
+        let proxy_task = self.rt.spawn(named(
+            format!("proxy-{}", id),
+            proxy.run(listener, itx, orx, shutdown_rx.clone()),
+        ));
+        let node_task = self
+            .rt
+            .spawn(named(format!("node-{}", id), node.run(shutdown_rx)));
+        Ok((proxy_task, node_task))
+    }
+
+    // 在新线程上建单线程运行时，监听也在那边建立，直到两个任务都结束线程才退出。
+    // 结果经 oneshot 交回共享运行时上的任务，migrate 与退出时照常等待
+    fn spawn_dedicated(
+        &mut self,
+        id: usize,
+        node: Node,
+        proxy: Arc<Proxy>,
+        itx: Tx<Incoming>,
+        orx: Rx<Outgoing>,
+        shutdown_rx: Shutdown,
+    ) -> io::Result<ServerTasks> {
+        let (bound_tx, bound_rx) = std::sync::mpsc::channel();
+        let (proxy_tx, proxy_rx) = oneshot::channel();
+        let (node_tx, node_rx) = oneshot::channel();
+        std::thread::Builder::new()
+            .name(format!("node-{}", id))
+            .spawn(move || {
+                let mut rt = match tokio::runtime::Builder::new()
+                    .basic_scheduler()
+                    .enable_all()
+                    .build()
+                {
+                    Ok(rt) => rt,
+                    Err(e) => return bound_tx.send(Err(e)).unwrap_or(()),
                 };
-                self.rt.block_on(task);
-            } else {
+                rt.block_on(async move {
+                    let listener = match proxy.bind().await {
+                        Ok(listener) => listener,
+                        Err(e) => return bound_tx.send(Err(e)).unwrap_or(()),
+                    };
+                    let _ = bound_tx.send(Ok(()));
+                    let (proxy_result, node) = futures::join!(
+                        named(
+                            format!("proxy-{}", id),
+                            proxy.run(listener, itx, orx, shutdown_rx.clone()),
+                        ),
+                        named(format!("node-{}", id), node.run(shutdown_rx)),
+                    );
+                    let _ = proxy_tx.send(proxy_result);
+                    let _ = node_tx.send(node);
+                });
+            })?;
+        // 线程在报告监听结果之前就退出的话，视同监听失败
+        bound_rx
+            .recv()
+            .unwrap_or_else(|_| Err(io::Error::other("runtime thread exited")))?;
+        // 线程 panic 时 oneshot 被丢弃，这里跟着 panic，等待方看到的是 JoinError
+        let proxy_task = self
+            .rt
+            .spawn(async move { proxy_rx.await.expect("dedicated runtime thread panicked") });
+        let node_task = self
+            .rt
+            .spawn(async move { node_rx.await.expect("dedicated runtime thread panicked") });
+        Ok((proxy_task, node_task))
+    }
+
+    // 停掉 server_id 号服务器的监听，排空后带着恢复的状态在新地址重启
+    pub fn migrate(&mut self, server_id: usize, addr: SocketAddr) {
+        let addr_table = match &self.group().addr_table {
+            Some(addr_table) => addr_table.clone(),
+            None => {
+                println_flushed!("error: servers haven't started.");
+                return;
+            }
+        };
+        let server = match self.group_mut().servers.remove(&server_id) {
+            Some(server) => server,
+            None => {
+                println_flushed!("error: server id dosen't exist.");
+                return;
+            }
+        };
+
+        let Server {
+            shutdown,
+            proxy,
+            node,
+            ..
+        } = server;
+        let _ = shutdown.send(());
+        let node = self.rt.block_on(async move {
+            let _ = proxy.await;
+            node.await
+        });
+        let node = match node {
+            Ok(node) => node,
+            Err(_) => {
+                println_flushed!("error: server #{} has crashed.", server_id);
+                return;
+            }
+        };
+
+        // 所有代理共享地址表，更新后广播自然会发往新地址
+        addr_table.write().unwrap().insert(server_id, addr);
+        // 重定向时告诉客户端的是结点里记下的提案者地址，不查地址表，要一起更新
+        for server in self.group().servers.values() {
+            if server.control.proposer() == Some(server_id) {
+                server.control.set_proposer_addr(Some(addr));
+            }
+        }
+        let (itx, irx) = mpsc::unbounded();
+        let (otx, orx) = mpsc::unbounded();
+        self.spawn_server(server_id, node.recover(otx, irx), itx, orx);
+        println_flushed!("Server #{} migrated to {}.", server_id, addr);
+    }
+
+    pub fn skew(&mut self, server_id: usize, delta_ms: i64) {
+        if let Some(server) = self.group().servers.get(&server_id) {
+            server.control.set_clock_skew(delta_ms);
+        } else {
+            println_flushed!("error: server id dosen't exist.");
+        }
+    }
+
+    pub fn slow(&mut self, server_id: usize, delay_ms: u64) {
+        if let Some(server) = self.group().servers.get(&server_id) {
+            server.control.set_slowness(delay_ms);
+        } else {
+            println_flushed!("error: server id dosen't exist.");
+        }
+    }
+
+    // 依次 ping server_id 号服务器 PING_COUNT 次，时延包括双向建立连接。
+    // 慢结点处理每条消息前的停顿也算在内
+    pub fn ping(&mut self, server_id: usize) -> Option<RttReport> {
+        let mut rtts = Vec::with_capacity(PING_COUNT as usize);
+        for _ in 0..PING_COUNT {
+            let nonce: u64 = self.rng.gen();
+            let start = std::time::Instant::now();
+            self.call(
+                server_id,
+                Request::Ping { nonce },
+                QUERY_TIMEOUT,
+                |resp| match resp {
+                    Response::Pong { nonce: echoed } if echoed == nonce => Some(()),
+                    _ => None,
+                },
+            )?;
+            rtts.push(start.elapsed());
+        }
+        let report = RttReport {
+            min: *rtts.iter().min().unwrap(),
+            avg: rtts.iter().sum::<Duration>() / PING_COUNT,
+            max: *rtts.iter().max().unwrap(),
+        };
+        println_flushed!(
+            "Server #{} rtt min/avg/max = {:.2?}/{:.2?}/{:.2?}.",
+            server_id,
+            report.min,
+            report.avg,
+            report.max
+        );
+        Some(report)
+    }
+
+    // 没有回应的服务器不出现在结果中
+    pub fn ping_all(&mut self) -> BTreeMap<usize, RttReport> {
+        let mut ids: Vec<usize> = self.group().servers.keys().copied().collect();
+        ids.sort_unstable();
+        ids.into_iter()
+            .filter_map(|id| Some((id, self.ping(id)?)))
+            .collect()
+    }
+
+    // 查看 server_id 号服务器下一个提案会用的序号，不影响之后的提案
+    #[cfg(feature = "debug")]
+    pub fn peek_seq(&mut self, server_id: usize) -> Option<SequenceNumber> {
+        let seq = self.call(
+            server_id,
+            Request::PeekSeq,
+            QUERY_TIMEOUT,
+            |resp| match resp {
+                Response::PeekSeq { seq } => Some(seq),
+                _ => None,
+            },
+        )?;
+        println_flushed!("Server #{} Answer: next ballot {:?}.", server_id, seq);
+        Some(seq)
+    }
+
+    #[cfg(feature = "debug")]
+    pub fn lie(&mut self, server_id: usize, liar: bool) {
+        if let Some(server) = self.group().servers.get(&server_id) {
+            server.control.set_liar(liar);
+        } else {
+            println_flushed!("error: server id dosen't exist.");
+        }
+    }
+
+    pub fn set_drop_rate(&mut self, server_id: usize, per_mille: u64, seed: u64) {
+        if let Some(server) = self.group().servers.get(&server_id) {
+            server.transport.set_drop_rate(per_mille, seed);
+        } else {
+            println_flushed!("error: server id dosen't exist.");
+        }
+    }
+
+    // server_id 号服务器的代理与各结点之间的连接表，按结点 ID 排序
+    pub fn conns(&self, server_id: usize) -> Option<BTreeMap<usize, PeerConn>> {
+        let server = match self.group().servers.get(&server_id) {
+            Some(server) => server,
+            None => {
                 println_flushed!("error: server id dosen't exist.");
+                return None;
             }
+        };
+        let conns: BTreeMap<usize, PeerConn> = server.transport.conns().into_iter().collect();
+        if conns.is_empty() {
+            println_flushed!("Server #{} has no connections yet.", server_id);
+        }
+        let state = |state: Option<ConnState>| state.map_or("idle".to_string(), |s| s.to_string());
+        for (peer, conn) in &conns {
+            println_flushed!(
+                "#{} -> #{}: {}, sent {} bytes; #{} -> #{}: {}, received {} bytes",
+                server_id,
+                peer,
+                state(conn.outbound),
+                conn.bytes_sent,
+                peer,
+                server_id,
+                state(conn.inbound),
+                conn.bytes_received
+            );
+        }
+        Some(conns)
+    }
+
+    // server_id 号服务器发往各目的地时丢弃的报文数
+    pub fn dropped(&self, server_id: usize) -> Option<HashMap<usize, u64>> {
+        let server = self.group().servers.get(&server_id)?;
+        Some(server.transport.dropped())
+    }
+
+    // 暂停的结点仍在监听，收到的消息留到恢复后处理
+    pub fn pause(&mut self, server_id: usize, paused: bool) {
+        if let Some(server) = self.group().servers.get(&server_id) {
+            server.control.set_paused(paused);
         } else {
-            println_flushed!("error: servers haven't started.");
+            println_flushed!("error: server id dosen't exist.");
         }
     }
 
-    pub fn query(&mut self, server_id: usize) {
-        if let Some(peers_addr) = &self.addr_table {
-            if let Some(addr) = peers_addr.get(&server_id) {
-                let addr = addr.clone();
-                let task = async move {
-                    if let Ok(mut stream) = TcpStream::connect(addr).await {
-                        let dgram = Datagram::Request(Request::Query);
-                        stream.write_all(&dgram.encode_with_src(0)).await.unwrap();
-                    }
-                };
-                self.rt.block_on(task);
-            } else {
+    pub fn set_proposer(&mut self, proposer: Option<usize>) {
+        if let Some(id) = proposer {
+            if !self.group().servers.contains_key(&id) {
                 println_flushed!("error: server id dosen't exist.");
+                return;
+            }
+        }
+        let addr = proposer.and_then(|id| self.server_addr(id));
+        for server in self.group().servers.values() {
+            server.control.set_proposer(proposer);
+            server.control.set_proposer_addr(addr);
+        }
+    }
+
+    // 开启后，非提案者收到客户端的提案时让客户端改向提案者提案，而不是代为转交
+    pub fn set_redirect(&mut self, redirect: bool) {
+        for server in self.group().servers.values() {
+            server.control.set_redirect(redirect);
+        }
+    }
+
+    pub fn set_gossip_fanout(&mut self, fanout: usize) {
+        for server in self.group().servers.values() {
+            server.control.set_gossip_fanout(fanout);
+        }
+    }
+
+    pub fn set_fair_tie_break(&mut self, fair: bool) {
+        for server in self.group().servers.values() {
+            server.control.set_fair_tie_break(fair);
+        }
+    }
+
+    // 每隔 interval_ms 把选定值重播给其他结点，0 表示关闭
+    pub fn set_relearn_interval(&mut self, interval_ms: u64) {
+        for server in self.group().servers.values() {
+            server.control.set_relearn_interval(interval_ms);
+        }
+    }
+
+    // 提案停滞 timeout_ms 毫秒后放弃，0 表示关闭
+    pub fn set_stall_timeout(&mut self, timeout_ms: u64) {
+        for server in self.group().servers.values() {
+            server.control.set_stall_timeout(timeout_ms);
+        }
+    }
+
+    // 默认严格：安全性检查失败时结点直接 panic，方便测试尽早暴露问题
+    pub fn set_lenient(&mut self, lenient: bool) {
+        for server in self.group().servers.values() {
+            server.control.set_lenient(lenient);
+        }
+    }
+
+    // 只作用于已经启动的服务器
+    pub fn set_metrics_interval(&mut self, interval_ms: u64) {
+        for server in self.group().servers.values() {
+            server.control.set_metrics_interval(interval_ms);
+        }
+    }
+
+    // 之后每台服务器的日志各自追加到 dir/node-{id}.log，代理的日志也写在一起，不再写到标准输出
+    pub fn set_log_dir(&mut self, dir: impl AsRef<std::path::Path>) -> std::io::Result<()> {
+        let dir = dir.as_ref();
+        std::fs::create_dir_all(dir)?;
+        for (id, server) in self.group().servers.iter() {
+            let path = dir.join(format!("node-{}.log", id));
+            server.control.set_log_file(&path)?;
+            server.transport.set_log_file(&path)?;
+        }
+        Ok(())
+    }
+
+    fn server_addr(&self, server_id: usize) -> Option<SocketAddr> {
+        if let Some(addr_table) = &self.group().addr_table {
+            if let Some(addr) = addr_table.read().unwrap().get(&server_id) {
+                return Some(*addr);
             }
+            println_flushed!("error: server id dosen't exist.");
         } else {
             println_flushed!("error: servers haven't started.");
         }
+        None
+    }
+
+    pub fn propose(&mut self, server_id: usize, val: ValueType) {
+        self.propose_with_priority(server_id, val, 0)
+    }
+
+    // 优先级越高，提案序号越大，竞争时越容易胜出。服务器回应重定向时改向提案者重新提案
+    pub fn propose_with_priority(&mut self, server_id: usize, val: ValueType, priority: u8) {
+        self.send_proposal(server_id, val, priority);
+    }
+
+    // 发出提案并跟随重定向，返回最终收下提案的服务器与它的地址。
+    // 会重定向的服务器一定回应，等到重定向再改发；其余的服务器不回应，发出就返回
+    fn send_proposal(
+        &mut self,
+        server_id: usize,
+        val: ValueType,
+        priority: u8,
+    ) -> Option<(usize, SocketAddr)> {
+        let (mut server_id, mut addr) = (server_id, self.server_addr(server_id)?);
+        for _ in 0..=MAX_REDIRECTS {
+            let req = Request::Propose {
+                value: val,
+                priority,
+                seq: None,
+            };
+            if !self.redirects(server_id) {
+                self.rt.block_on(send_request(self.current, addr, req));
+                return Some((server_id, addr));
+            }
+            let (leader, leader_addr) =
+                self.call_at(server_id, addr, req, QUERY_TIMEOUT, |resp| match resp {
+                    Response::Redirect { leader, addr } => Some((leader, addr)),
+                    _ => None,
+                })?;
+            // 按回应中的地址重新提案，提案者可能不在本地的地址表中
+            println_flushed!(
+                "Server #{} redirects to server #{} at {}.",
+                server_id,
+                leader,
+                leader_addr
+            );
+            server_id = leader;
+            addr = leader_addr;
+        }
+        println_flushed!("error: too many redirects.");
+        None
+    }
+
+    // server_id 号服务器是否会把客户端的提案重定向给提案者
+    fn redirects(&self, server_id: usize) -> bool {
+        self.group().servers.get(&server_id).is_some_and(|server| {
+            server.control.redirect().is_some()
+                && server.control.proposer().is_some_and(|id| id != server_id)
+        })
+    }
+
+    // 提案并等到收下提案的服务器学习到值，返回选定的值与从发出提案到选定的耗时
+    pub fn propose_timed(
+        &mut self,
+        server_id: usize,
+        val: ValueType,
+        priority: u8,
+        timeout: Duration,
+    ) -> Option<(ValueType, Duration)> {
+        let start = std::time::Instant::now();
+        let (server_id, addr) = self.send_proposal(server_id, val, priority)?;
+        let chosen = self.call_at(
+            server_id,
+            addr,
+            Request::WaitQuery,
+            timeout,
+            |resp| match resp {
+                Response::Query { val: Some(val) } => Some(val),
+                _ => None,
+            },
+        )?;
+        let elapsed = start.elapsed();
+        println_flushed!(
+            "Server #{} Answer: {} (decided in {}ms).",
+            server_id,
+            chosen,
+            elapsed.as_millis()
+        );
+        Some((chosen, elapsed))
+    }
+
+    // 把提案直接放进 server_id 号服务器的收件箱，来源是它自己，
+    // 就像它自己决定要提案一样。只做决策者的结点仍会转交给提案者
+    pub fn force_propose(&mut self, server_id: usize, val: ValueType) {
+        let server = match self.group().servers.get(&server_id) {
+            Some(server) => server,
+            None => {
+                println_flushed!("error: server id dosen't exist.");
+                return;
+            }
+        };
+        let req = Request::Propose {
+            value: val,
+            priority: 0,
+            seq: None,
+        };
+        let incoming = Incoming {
+            src: server_id,
+            dgram: Datagram::Request(req),
+        };
+        if server.inbox.unbounded_send(incoming).is_err() {
+            println_flushed!("error: server #{} has stopped.", server_id);
+        }
+    }
+
+    // 浸泡测试：concurrency 个客户端各自不停地向随机的服务器提出随机的值，持续 duration。
+    // 期间结点改为宽松模式，违例只计数不 panic，结束后恢复原来的设置。
+    // 每个提案拿到的值必须相同，结束时每台服务器学习到的也必须是这个值。
+    // 真正走完 prepare/accept 的只有最早的那批提案，所以它检验的是竞争下的安全性，
+    // 报告中的吞吐不能当作共识的吞吐
+    pub fn stress(&mut self, duration: Duration, concurrency: usize) -> StressReport {
+        let mut report = StressReport::default();
+        let mut ids: Vec<usize> = self.group().servers.keys().copied().collect();
+        ids.sort_unstable();
+        let session = match (ids.is_empty(), self.session()) {
+            (false, Some(session)) => session,
+            _ => return report,
+        };
+        let saved: Vec<(Arc<NodeControl>, bool)> = self
+            .group()
+            .servers
+            .values()
+            .map(|server| (server.control.clone(), server.control.lenient()))
+            .collect();
+        let mut violations_before = 0;
+        let mut decided_before = 0;
+        for (control, _) in &saved {
+            violations_before += control.stats().violations;
+            decided_before += control.stats().decided;
+            control.set_lenient(true);
+        }
+
+        // 每个并发的提案者一个种子，各自挑选服务器与值
+        let seeds: Vec<u64> = (0..concurrency).map(|_| self.rng.gen()).collect();
+        let start = std::time::Instant::now();
+        let deadline = start + duration;
+        let outcomes = self
+            .rt
+            .block_on(futures::future::join_all(seeds.into_iter().map(|seed| {
+                let (session, ids) = (&session, &ids);
+                async move {
+                    let mut rng = StdRng::seed_from_u64(seed);
+                    let mut outcomes = Vec::new();
+                    while std::time::Instant::now() < deadline {
+                        let server_id = ids[rng.gen_range(0..ids.len())];
+                        let value = rng.gen();
+                        let sent = std::time::Instant::now();
+                        let result = session.propose(server_id, value, WAIT_TIMEOUT).await;
+                        outcomes.push(result.map(|chosen| (chosen, sent.elapsed())));
+                    }
+                    outcomes
+                }
+            })));
+        report.elapsed = start.elapsed();
+
+        let mut chosen = None;
+        for outcome in outcomes.into_iter().flatten() {
+            match outcome {
+                Ok((value, latency)) => {
+                    if *chosen.get_or_insert(value) != value {
+                        report.violations += 1;
+                    }
+                    report.latencies.push(latency);
+                }
+                Err(_) => report.failures += 1,
+            }
+        }
+        report.latencies.sort_unstable();
+        let answers = self.query_all(QUERY_TIMEOUT, WAIT_TIMEOUT).answers;
+        // 还没有学习到值的服务器不算违例
+        report.violations += answers
+            .values()
+            .filter(|answer| matches!(answer, Some(value) if Some(*value) != chosen))
+            .count() as u64;
+        let mut decided_after = 0;
+        for (control, before) in &saved {
+            report.violations += control.stats().violations;
+            decided_after += control.stats().decided;
+            control.set_lenient(*before);
+        }
+        report.violations -= violations_before;
+        // 竞争的提案者可能各自把同一个值推到多数派，但都是同一个槽位
+        report.decisions = (decided_after > decided_before) as u64;
+
+        let ms = |latency: Option<Duration>| latency.unwrap_or_default().as_secs_f64() * 1000.0;
+        println_flushed!(
+            "Stress: {} decisions, {} proposals answered in {:.1}s ({:.1}/s), {} failures, answer latency p50 {:.2}ms, p99 {:.2}ms, max {:.2}ms, {} violations.",
+            report.decisions,
+            report.answered(),
+            report.elapsed.as_secs_f64(),
+            report.throughput(),
+            report.failures,
+            ms(report.percentile(50.0)),
+            ms(report.percentile(99.0)),
+            ms(report.percentile(100.0)),
+            report.violations
+        );
+        report
+    }
+
+    // 读自己所写：提案并等到选定，再等 server_id 自己也学习到值后从它读回。
+    // 提案可能经重定向由提案者选定，server_id 不一定已经学习到，所以读时要等；
+    // 选定值之后不会再变，读到的一定不旧于这次提案的结果。落选时返回胜出的值
+    pub fn propose_read(&mut self, server_id: usize, val: ValueType) -> Option<ValueType> {
+        let (chosen, _) = self.propose_timed(server_id, val, 0, WAIT_TIMEOUT)?;
+        let read = self.query_wait(server_id, WAIT_TIMEOUT)?;
+        if read != chosen {
+            println_flushed!(
+                "error: server #{} read {} after {} was chosen.",
+                server_id,
+                read,
+                chosen
+            );
+            return None;
+        }
+        Some(read)
+    }
+
+    // 查询 server_id 号服务器已学习到的值
+    pub fn query(&mut self, server_id: usize) -> Option<ValueType> {
+        self.ask(server_id, Request::Query, QUERY_TIMEOUT)
+    }
+
+    // 同 query，但服务器会等到学习到值才回应，最多等待 timeout
+    pub fn query_wait(&mut self, server_id: usize, timeout: Duration) -> Option<ValueType> {
+        self.ask(server_id, Request::WaitQuery, timeout)
+    }
+
+    // 有界陈旧读：不经过多数派，直接采用 server_id 的回答。选定的值不会再变，学习到的值
+    // 无论多旧都接受；只有“还没有学习到”可能已经过时，要求它在 bound 之内收到过 learn
+    // 或心跳，否则不接受。接受时返回 Some，其中为空表示确实还没有选定值
+    pub fn query_within(&mut self, server_id: usize, bound: Duration) -> Option<Option<ValueType>> {
+        let (val, staleness) = self.call(
+            server_id,
+            Request::BoundedQuery,
+            QUERY_TIMEOUT,
+            |resp| match resp {
+                Response::BoundedQuery { val, staleness_ms } => Some((val, staleness_ms)),
+                _ => None,
+            },
+        )?;
+        match (val, staleness.map(Duration::from_millis)) {
+            (Some(val), _) => {
+                println_flushed!("Server #{} Answer: {}.", server_id, val);
+                Some(Some(val))
+            }
+            (None, Some(staleness)) if staleness <= bound => {
+                println_flushed!(
+                    "Server #{} Answer: not value learned yet ({}ms stale).",
+                    server_id,
+                    staleness.as_millis()
+                );
+                Some(None)
+            }
+            (None, Some(staleness)) => {
+                println_flushed!(
+                    "error: server #{} is {}ms stale, over the {}ms bound.",
+                    server_id,
+                    staleness.as_millis(),
+                    bound.as_millis()
+                );
+                None
+            }
+            (None, None) => {
+                println_flushed!(
+                    "error: server #{} has never heard a learn or heartbeat.",
+                    server_id
+                );
+                None
+            }
+        }
+    }
+
+    // read-index 读，不写入任何值也能读到此前完成的写。问的不是提案者时按重定向再问一次。
+    // 结点在 READ_TIMEOUT 内没能回答会回 read_failed，因此多等一个查询超时
+    pub fn query_read_index(&mut self, server_id: usize) -> Option<ValueType> {
+        let extract = |resp| match resp {
+            Response::ReadIndex { val, index } => Some(Ok((val, index))),
+            Response::Redirect { leader, addr } => Some(Err(Some((leader, addr)))),
+            Response::ReadFailed => Some(Err(None)),
+            _ => None,
+        };
+        let timeout = READ_TIMEOUT + QUERY_TIMEOUT;
+        let answer = match self.call(server_id, Request::ReadIndex, timeout, extract)? {
+            Err(Some((leader, addr))) => (
+                leader,
+                self.call_at(leader, addr, Request::ReadIndex, timeout, extract)?,
+            ),
+            answer => (server_id, answer),
+        };
+        let (server_id, (val, index)) = match answer {
+            (server_id, Ok(answer)) => (server_id, answer),
+            (server_id, Err(_)) => {
+                println_flushed!("error: server #{} couldn't confirm the read.", server_id);
+                return None;
+            }
+        };
+        match val {
+            Some(val) => println_flushed!(
+                "Server #{} Answer: {} (read index {}).",
+                server_id,
+                val,
+                index
+            ),
+            None => println_flushed!(
+                "Server #{} Answer: not value learned yet (read index {}).",
+                server_id,
+                index
+            ),
+        }
+        val
+    }
+
+    fn ask(&mut self, server_id: usize, req: Request, timeout: Duration) -> Option<ValueType> {
+        let answer = self.call(server_id, req, timeout, |resp| match resp {
+            Response::Query { val } => Some(val),
+            _ => None,
+        })?;
+        match answer {
+            Some(val) => println_flushed!("Server #{} Answer: {}.", server_id, val),
+            None => println_flushed!("Server #{} Answer: not value learned yet.", server_id),
+        }
+        answer
+    }
+
+    // 查询 server_id 号服务器选定的值由哪台服务器在哪个提案中提出
+    pub fn who(&mut self, server_id: usize) -> Option<AcceptedProposal> {
+        let chosen = self.call(server_id, Request::Who, QUERY_TIMEOUT, |resp| match resp {
+            Response::Who(chosen) => Some(chosen),
+            _ => None,
+        })?;
+        match chosen {
+            Some(chosen) => println_flushed!(
+                "Server #{} Answer: value {} was proposed by server #{} at ballot {:?}.",
+                server_id,
+                chosen.val,
+                chosen.proposer(),
+                chosen.seq
+            ),
+            None => println_flushed!("Server #{} Answer: not value learned yet.", server_id),
+        }
+        chosen
+    }
+
+    // 发出请求并等待 server_id 号服务器的回应，extract 挑出需要的那种回应
+    fn call<T>(
+        &mut self,
+        server_id: usize,
+        req: Request,
+        timeout: Duration,
+        extract: impl Fn(Response) -> Option<T>,
+    ) -> Option<T> {
+        if server_id == 0 || !self.group().servers.contains_key(&server_id) {
+            println_flushed!("error: server id dosen't exist.");
+            return None;
+        }
+        let addr = self.server_addr(server_id)?;
+        self.call_at(server_id, addr, req, timeout, extract)
+    }
+
+    // 同 call，但直接向 addr 发出请求
+    fn call_at<T>(
+        &mut self,
+        server_id: usize,
+        addr: SocketAddr,
+        req: Request,
+        timeout: Duration,
+        extract: impl Fn(Response) -> Option<T>,
+    ) -> Option<T> {
+        let current = self.current;
+        let (rt, group) = self.split();
+        let inbox = &mut group.client.as_mut()?.inbox;
+        let task = async move {
+            send_request(current, addr, req).await;
+            tokio::time::timeout(timeout, async {
+                while let Some(Incoming { src, dgram }) = inbox.next().await {
+                    if src != server_id {
+                        continue;
+                    }
+                    if let Datagram::Response(resp) = dgram {
+                        if let Some(answer) = extract(resp) {
+                            return Some(answer);
+                        }
+                    }
+                }
+                None
+            })
+            .await
+        };
+        match rt.block_on(task) {
+            Ok(Some(answer)) => Some(answer),
+            _ => {
+                println_flushed!("error: server #{} didn't answer.", server_id);
+                None
+            }
+        }
+    }
+
+    // 轮询各服务器，直到半数以上都学习到 value；超时返回 false
+    pub fn wait_for_value(&mut self, value: ValueType, timeout: Duration) -> bool {
+        let deadline = std::time::Instant::now() + timeout;
+        let ids: Vec<usize> = self.group().servers.keys().copied().collect();
+        let quorum = majority(ids.len());
+        loop {
+            let chosen = ids
+                .iter()
+                .filter(|&&id| self.learned(id) == Some(value))
+                .count();
+            if !ids.is_empty() && chosen >= quorum {
+                println_flushed!(
+                    "Value {} is chosen by {}/{} servers.",
+                    value,
+                    chosen,
+                    ids.len()
+                );
+                return true;
+            }
+            if std::time::Instant::now() >= deadline {
+                println_flushed!("error: value {} isn't chosen by a majority.", value);
+                return false;
+            }
+            std::thread::sleep(QUIESCENT_POLL_INTERVAL);
+        }
+    }
+
+    // 同 query，但不打印回答
+    fn learned(&mut self, server_id: usize) -> Option<ValueType> {
+        self.call(
+            server_id,
+            Request::Query,
+            QUERY_TIMEOUT,
+            |resp| match resp {
+                Response::Query { val } => Some(val),
+                _ => None,
+            },
+        )?
+    }
+
+    // 等到集群安静下来：没有结点在提案中，各代理没有待发的报文，且一个轮询间隔内
+    // 没有结点处理过消息。已经写进连接、对方还没读出的报文看不到，只能靠最后一条兜底，
+    // 因此最好把它当作启发式的判断。超时仍未安静则返回 false
+    pub fn await_quiescent(&mut self, timeout: Duration) -> bool {
+        let deadline = std::time::Instant::now() + timeout;
+        let mut last = None;
+        loop {
+            let servers = &self.group().servers;
+            let active = servers.values().any(|server| {
+                matches!(
+                    server.control.state(),
+                    NodeState::Preparing | NodeState::Accepting
+                ) || server.transport.queued() > 0
+            });
+            let handled: HashMap<usize, u64> = servers
+                .iter()
+                .map(|(&id, server)| (id, server.control.handled()))
+                .collect();
+            if !active && last.as_ref() == Some(&handled) {
+                return true;
+            }
+            if std::time::Instant::now() >= deadline {
+                println_flushed!("error: cluster isn't quiescent yet.");
+                return false;
+            }
+            last = Some(handled);
+            std::thread::sleep(QUIESCENT_POLL_INTERVAL);
+        }
+    }
+
+    // 同时查询所有服务器。每台服务器最多等 per_node，整条命令最多等 deadline，
+    // 宕机的服务器只会出现在 missing 中，不会拖住其余的回应
+    pub fn query_all(&mut self, per_node: Duration, deadline: Duration) -> QueryAllReport {
+        let mut report = QueryAllReport::default();
+        let addr_table = match &self.group().addr_table {
+            Some(addr_table) => addr_table.read().unwrap().clone(),
+            None => {
+                println_flushed!("error: servers haven't started.");
+                return report;
+            }
+        };
+        let mut ids: Vec<usize> = self.group().servers.keys().copied().collect();
+        ids.sort_unstable();
+        let current = self.current;
+        let (rt, group) = self.split();
+        let inbox = match group.client.as_mut() {
+            Some(client) => &mut client.inbox,
+            None => return report,
+        };
+        // 上一次没有及时回应的服务器可能后来才回应，迟到的回应还留在收件箱里，先丢掉
+        while let Ok(Some(_)) = inbox.try_next() {}
+        let answers = &mut report.answers;
+        rt.block_on(async {
+            let start = tokio::time::Instant::now();
+            // 连接迟迟建立不了的服务器也只占用 per_node
+            let sends = ids.iter().map(|id| {
+                let send = send_request(current, addr_table[id], Request::Query);
+                tokio::time::timeout(per_node, send)
+            });
+            let _ = tokio::time::timeout(deadline, futures::future::join_all(sends)).await;
+            let wait = per_node.min(deadline.checked_sub(start.elapsed()).unwrap_or_default());
+            let _ = tokio::time::timeout(wait, async {
+                while let Some(Incoming { src, dgram }) = inbox.next().await {
+                    if let Datagram::Response(Response::Query { val }) = dgram {
+                        answers.insert(src, val);
+                        if answers.len() == ids.len() {
+                            break;
+                        }
+                    }
+                }
+            })
+            .await;
+        });
+        report.missing = ids
+            .into_iter()
+            .filter(|id| !report.answers.contains_key(id))
+            .collect();
+
+        let mut answered: Vec<_> = report.answers.iter().collect();
+        answered.sort_unstable();
+        for (id, val) in answered {
+            match val {
+                Some(val) => println_flushed!("Server #{} Answer: {}.", id, val),
+                None => println_flushed!("Server #{} Answer: not value learned yet.", id),
+            }
+        }
+        if !report.missing.is_empty() {
+            println_flushed!("error: servers {:?} didn't answer.", report.missing);
+        }
+        report
+    }
+
+    pub fn stats(&self) -> ClusterStats {
+        let mut total = ClusterStats::default();
+        for server in self.group().servers.values() {
+            let stats = server.control.stats();
+            total.proposals += stats.proposals;
+            total.rounds += stats.rounds;
+            total.decided += stats.decided;
+            for (kind, count) in stats.messages {
+                *total.messages.entry(kind).or_default() += count;
+            }
+            total.dropped += server.transport.dropped_total();
+            total.violations += stats.violations;
+            total.connections += server.transport.active_inflows();
+        }
+        let rounds_per_decision = match total.rounds_per_decision() {
+            Some(rounds) => format!("{:.2}", rounds),
+            None => "-".to_string(),
+        };
+        println_flushed!(
+            "Proposals: {}, rounds: {}, decided: {}, rounds per decision: {}, dropped: {}, violations: {}, connections: {}.",
+            total.proposals,
+            total.rounds,
+            total.decided,
+            rounds_per_decision,
+            total.dropped,
+            total.violations,
+            total.connections
+        );
+        println_flushed!("Messages: {:?}.", total.messages);
+        total
+    }
+
+    // 各服务器的提交位置与本组最高提交位置之差
+    pub fn lag(&self) -> LagReport {
+        let commit_index: BTreeMap<usize, u64> = self
+            .group()
+            .servers
+            .iter()
+            .map(|(&id, server)| (id, server.control.commit_index()))
+            .collect();
+        let report = LagReport {
+            highest: commit_index.values().copied().max().unwrap_or(0),
+            commit_index,
+        };
+        for &id in report.commit_index.keys() {
+            println_flushed!(
+                "Server #{} commit index: {}, lag: {}.",
+                id,
+                report.commit_index[&id],
+                report.lag(id).unwrap()
+            );
+        }
+        report
+    }
+
+    // 不做承诺地探查各决策结点，收到半数以上的回应即汇总
+    pub fn probe(&mut self) -> ProbeReport {
+        let mut report = ProbeReport {
+            acceptors: self.group().servers.len(),
+            ..Default::default()
+        };
+        let addr_table = match &self.group().addr_table {
+            Some(addr_table) => addr_table.read().unwrap().clone(),
+            None => {
+                println_flushed!("error: servers haven't started.");
+                return report;
+            }
+        };
+        let addrs: Vec<SocketAddr> = self
+            .group()
+            .servers
+            .keys()
+            .map(|id| addr_table[id])
+            .collect();
+        let quorum = majority(addrs.len());
+        let current = self.current;
+        let (rt, group) = self.split();
+        let inbox = match group.client.as_mut() {
+            Some(client) => &mut client.inbox,
+            None => return report,
+        };
+        // 上一次 probe 收够多数派就返回了，其余结点迟到的回应还留在收件箱里，先丢掉
+        while let Ok(Some(_)) = inbox.try_next() {}
+        let states = &mut report.states;
+        rt.block_on(async {
+            for addr in addrs {
+                send_request(current, addr, Request::Probe).await;
+            }
+            let _ = tokio::time::timeout(QUERY_TIMEOUT, async {
+                while let Some(Incoming { src, dgram }) = inbox.next().await {
+                    if let Datagram::Response(Response::Probe(state)) = dgram {
+                        states.insert(src, state);
+                        if states.len() >= quorum {
+                            break;
+                        }
+                    }
+                }
+            })
+            .await;
+        });
+
+        if report.states.len() < quorum {
+            println_flushed!("error: only {} servers answered.", report.states.len());
+        }
+        println_flushed!(
+            "Probed {:?}, highest promised: {:?}, highest accepted: {:?}",
+            report.states.keys().collect::<Vec<_>>(),
+            report.highest_promised(),
+            report.highest_accepted()
+        );
+        report
+    }
+
+    // 不做承诺地探查一台决策结点，看它自己承诺与接受过什么
+    pub fn probe_server(&mut self, server_id: usize) -> Option<AcceptorState> {
+        let state = self.call(
+            server_id,
+            Request::Probe,
+            QUERY_TIMEOUT,
+            |resp| match resp {
+                Response::Probe(state) => Some(state),
+                _ => None,
+            },
+        )?;
+        println_flushed!(
+            "Server #{} promised: {:?}, accepted: {:?}",
+            server_id,
+            state.last_promised,
+            state.last_accepted
+        );
+        Some(state)
+    }
+
+    // 收集当前组每台服务器的完整状态。承诺、接受与选定的提案向服务器询问，
+    // 没有回应的服务器这几项为空；其余的直接读取本地的计数
+    pub fn dump(&mut self) -> ClusterDump {
+        let mut ids: Vec<usize> = self.group().servers.keys().copied().collect();
+        ids.sort_unstable();
+        let mut dump = ClusterDump {
+            group: self.current,
+            servers: Vec::new(),
+        };
+        for id in ids {
+            let acceptor = self.call(id, Request::Probe, QUERY_TIMEOUT, |resp| match resp {
+                Response::Probe(state) => Some(state),
+                _ => None,
+            });
+            let chosen = self.call(id, Request::Who, QUERY_TIMEOUT, |resp| match resp {
+                Response::Who(chosen) => Some(chosen),
+                _ => None,
+            });
+            let addr = self
+                .group()
+                .addr_table
+                .as_ref()
+                .and_then(|addr_table| addr_table.read().unwrap().get(&id).copied());
+            let server = &self.group().servers[&id];
+            let control = &server.control;
+            let role = match control.proposer() {
+                Some(proposer) if proposer == id => "proposer",
+                Some(_) => "acceptor",
+                None => "peer",
+            };
+            let stats = control.stats();
+            let mut metrics: BTreeMap<String, u64> = [
+                ("proposals", stats.proposals),
+                ("rounds", stats.rounds),
+                ("decided", stats.decided),
+                ("violations", stats.violations),
+                ("handled", control.handled()),
+                ("dropped", server.transport.dropped_total()),
+                ("connections", server.transport.active_inflows() as u64),
+            ]
+            .into_iter()
+            .map(|(name, value)| (name.to_string(), value))
+            .collect();
+            for (kind, count) in stats.messages {
+                metrics.insert(format!("msg.{}", kind), count);
+            }
+            dump.servers.push(ServerDump {
+                id,
+                addr,
+                role: role.to_string(),
+                state: format!("{:?}", control.state()),
+                last_promised: acceptor.and_then(|state| state.last_promised),
+                last_accepted: acceptor.and_then(|state| state.last_accepted),
+                chosen: chosen.flatten(),
+                commit_index: control.commit_index(),
+                metrics,
+            });
+        }
+        dump
+    }
+
+    // path 为空时写到标准输出
+    pub fn dump_to(&mut self, path: Option<&str>) {
+        let json = match crate::json::to_string(&self.dump()) {
+            Ok(json) => json,
+            Err(e) => {
+                println_flushed!("error: can't encode the dump: {}", e);
+                return;
+            }
+        };
+        match path {
+            None => println_flushed!("{}", json),
+            Some(path) => match std::fs::write(path, json + "\n") {
+                Ok(()) => println_flushed!("Dumped group {} to {}.", self.current, path),
+                Err(e) => println_flushed!("error: can't write {}: {}", path, e),
+            },
+        }
+    }
+
+    // 绕过结点逻辑，以 from 的身份向 to 发送任意报文。
+    // from 为客户端 #0 时等待并返回 to 的回应
+    #[cfg(feature = "debug")]
+    pub fn inject(&mut self, from: usize, to: usize, dgram: Datagram) -> Option<Response> {
+        let addr = self.server_addr(to)?;
+        let current = self.current;
+        let (rt, group) = self.split();
+        let inbox = &mut group.client.as_mut()?.inbox;
+        let resp = rt.block_on(async move {
+            if let Ok(mut stream) = TcpStream::connect(addr).await {
+                let _ = stream.write_all(&dgram.encode(current, from)).await;
+            }
+            if from != 0 {
+                return None;
+            }
+            tokio::time::timeout(QUERY_TIMEOUT, async {
+                while let Some(Incoming { src, dgram }) = inbox.next().await {
+                    if let (true, Datagram::Response(resp)) = (src == to, dgram) {
+                        return Some(resp);
+                    }
+                }
+                None
+            })
+            .await
+            .ok()
+            .flatten()
+        });
+        match &resp {
+            Some(resp) => println_flushed!("Server #{} Response: {:?}", to, resp),
+            None => println_flushed!("Server #{} didn't respond.", to),
+        }
+        resp
+    }
+
+    // 在 addr 上开启 HTTP 网关，网关以 GATEWAY_ID 的身份加入地址表以便收到回应
+    #[cfg(feature = "http")]
+    pub fn start_http(&mut self, addr: SocketAddr, server_id: usize) {
+        use crate::gateway::{Gateway, GATEWAY_ID};
+
+        if !self.group().servers.contains_key(&server_id) {
+            println_flushed!("error: server id dosen't exist.");
+            return;
+        }
+        let addr_table = self.group().addr_table.clone().unwrap();
+        let bound = self.rt.block_on(async {
+            let http = tokio::net::TcpListener::bind(addr).await?;
+            let inflow = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+            Ok::<_, tokio::io::Error>((http, inflow))
+        });
+        let (http, inflow) = match bound {
+            Ok(bound) => bound,
+            Err(e) => {
+                println_flushed!("error: gateway can't listen: {}", e);
+                return;
+            }
+        };
+        addr_table
+            .write()
+            .unwrap()
+            .insert(GATEWAY_ID, inflow.local_addr().unwrap());
+
+        let (itx, irx) = mpsc::unbounded();
+        let (_, orx) = mpsc::unbounded();
+        let (shutdown, shutdown_rx) = Shutdown::new();
+        let proxy = Proxy::with_group(self.current, GATEWAY_ID, addr_table);
+        self.rt.spawn(named(
+            "proxy-gateway".to_string(),
+            proxy.clone().run(inflow, itx, orx, shutdown_rx.clone()),
+        ));
+        let gateway = Gateway::new(server_id, proxy, irx);
+        self.rt.spawn(named(
+            "http-gateway".to_string(),
+            gateway.serve(http, shutdown_rx),
+        ));
+        self.group_mut().gateway = Some(shutdown);
+        println_flushed!("HTTP gateway listening on {}.", addr);
+    }
+
+    // 在当前组开启一个客户端会话，监听回环地址上的任意端口
+    pub fn session(&mut self) -> Option<ClientSession> {
+        self.session_on(SocketAddr::from(([127, 0, 0, 1], 0)))
+    }
+
+    // 同 session，但监听 bind。每个会话各用一个 ID，收发任务运行在控制台的运行时上
+    pub fn session_on(&mut self, bind: SocketAddr) -> Option<ClientSession> {
+        let addr_table = match &self.group().addr_table {
+            Some(addr_table) => addr_table.clone(),
+            None => {
+                println_flushed!("error: servers haven't started.");
+                return None;
+            }
+        };
+        let current = self.current;
+        match self.rt.block_on(ClientSession::start(
+            current,
+            client::next_session_id(),
+            bind,
+            addr_table,
+        )) {
+            Ok(session) => Some(session),
+            Err(e) => {
+                println_flushed!("error: session can't listen: {}", e);
+                None
+            }
+        }
+    }
+
+    // 在 addr 上开启 WebSocket 事件流，只包含此刻本组已有的服务器
+    #[cfg(feature = "ws")]
+    pub fn start_ws(&mut self, addr: SocketAddr) {
+        let controls = self
+            .group()
+            .servers
+            .values()
+            .map(|server| server.control.clone())
+            .collect();
+        let listener = match self.rt.block_on(tokio::net::TcpListener::bind(addr)) {
+            Ok(listener) => listener,
+            Err(e) => {
+                println_flushed!("error: event stream can't listen: {}", e);
+                return;
+            }
+        };
+        let (shutdown, shutdown_rx) = Shutdown::new();
+        self.rt.spawn(named(
+            "ws-events".to_string(),
+            crate::ws::serve(listener, controls, shutdown_rx),
+        ));
+        self.group_mut().events = Some(shutdown);
+        println_flushed!("Event stream listening on ws://{}.", addr);
     }
 
     pub fn exit(self) {}
@@ -0,0 +1,21 @@
+use futures::channel::oneshot;
+use futures::future::{FutureExt, Shared};
+
+// 停机信号，可以克隆给同一台服务器的所有任务。
+// 发送端发送或被丢弃时，所有克隆都会收到信号
+#[derive(Debug, Clone)]
+pub struct Shutdown {
+    rx: Shared<oneshot::Receiver<()>>,
+}
+
+impl Shutdown {
+    pub fn new() -> (oneshot::Sender<()>, Self) {
+        let (tx, rx) = oneshot::channel();
+        (tx, Self { rx: rx.shared() })
+    }
+
+    // 等到收到停机信号，可以在 select! 中反复调用
+    pub async fn wait(&self) {
+        let _ = self.rx.clone().await;
+    }
+}
@@ -0,0 +1,26 @@
+use futures::FutureExt;
+use std::future::Future;
+use std::panic::AssertUnwindSafe;
+
+tokio::task_local! {
+    static TASK_NAME: String;
+}
+
+// 给任务起名（例如 `node-3`、`proxy-inflow-3`），任务 panic 时会报告它的名字
+pub async fn named<F: Future>(name: String, fut: F) -> F::Output {
+    let result = TASK_NAME
+        .scope(name.clone(), AssertUnwindSafe(fut).catch_unwind())
+        .await;
+    match result {
+        Ok(output) => output,
+        Err(e) => {
+            eprintln!("task `{}` panicked", name);
+            std::panic::resume_unwind(e)
+        }
+    }
+}
+
+// 当前任务的名字，不在命名任务中时返回 None
+pub fn current() -> Option<String> {
+    TASK_NAME.try_with(|name| name.clone()).ok()
+}
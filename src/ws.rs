@@ -0,0 +1,210 @@
+use futures::stream::select_all;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::prelude::*;
+use tokio::stream::StreamExt;
+
+use crate::paxos::node::NodeControl;
+use crate::shutdown::Shutdown;
+use crate::task_name::named;
+
+/*
+把结点事件以 JSON 文本帧推送给 WebSocket 客户端，浏览器可以据此实时演示协议：
+    ws://addr/  连接后收到之后发生的每个事件，例如 {"Decided":{"node":1,"seq":{...},"value":42}}
+只推送不接收，客户端发来的帧一律忽略。握手所需的 SHA-1 与 base64 在这里手写，不引入依赖。
+*/
+
+// RFC 6455 规定的握手常量
+const WS_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+// 升级请求要在这段时间内读完，闲置的连接不能一直占着任务
+const HANDSHAKE_DEADLINE: Duration = Duration::from_millis(1000);
+
+// 请求行与每个报头的最大长度
+const MAX_LINE_LEN: usize = 8192;
+
+// 逐个接受连接，每个连接各自订阅所有结点的事件，直到收到停机信号
+pub async fn serve(mut listener: TcpListener, controls: Vec<Arc<NodeControl>>, shutdown: Shutdown) {
+    loop {
+        tokio::select! {
+            accepted = listener.accept() => match accepted {
+                Ok((stream, _)) => {
+                    let stream_events = stream_events(stream, controls.clone(), shutdown.clone());
+                    tokio::spawn(named("ws-client".to_string(), stream_events));
+                }
+                Err(_) => break,
+            },
+            _ = shutdown.wait() => break,
+        }
+    }
+}
+
+async fn stream_events(stream: TcpStream, controls: Vec<Arc<NodeControl>>, shutdown: Shutdown) {
+    let mut stream = BufReader::new(stream);
+    let key = match tokio::time::timeout(HANDSHAKE_DEADLINE, read_key(&mut stream)).await {
+        Ok(Ok(Ok(key))) => key,
+        Ok(Ok(Err(status))) => return reject(&mut stream, status).await,
+        Err(_) => return reject(&mut stream, "408 Request Timeout").await,
+        Ok(Err(_)) => return,
+    };
+    // 先订阅再回应 101，客户端收到 101 之后发生的事件都能收到，之前的不会补发
+    let mut events = select_all(controls.iter().map(|control| control.subscribe()));
+    let response = format!(
+        "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Accept: {}\r\n\r\n",
+        accept_key(&key)
+    );
+    if stream
+        .get_mut()
+        .write_all(response.as_bytes())
+        .await
+        .is_err()
+    {
+        return;
+    }
+    loop {
+        let event = tokio::select! {
+            event = events.next() => match event {
+                Some(event) => event,
+                None => break,
+            },
+            _ = shutdown.wait() => break,
+        };
+        let text = crate::json::to_string(&event).unwrap();
+        if stream
+            .get_mut()
+            .write_all(&text_frame(&text))
+            .await
+            .is_err()
+        {
+            break;
+        }
+    }
+}
+
+// 读完升级请求的报头，取出 Sec-WebSocket-Key；出错时给出要回应的状态
+async fn read_key(
+    stream: &mut BufReader<TcpStream>,
+) -> Result<Result<String, &'static str>, tokio::io::Error> {
+    let mut key = None;
+    loop {
+        let mut header = String::new();
+        let read = stream
+            .take(MAX_LINE_LEN as u64)
+            .read_line(&mut header)
+            .await?;
+        if read == MAX_LINE_LEN && !header.ends_with('\n') {
+            return Ok(Err("431 Request Header Fields Too Large"));
+        }
+        if read == 0 || header.trim().is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.trim().eq_ignore_ascii_case("sec-websocket-key") {
+                key = Some(value.trim().to_string());
+            }
+        }
+    }
+    Ok(key.ok_or("400 Bad Request"))
+}
+
+async fn reject(stream: &mut BufReader<TcpStream>, status: &str) {
+    let response = format!("HTTP/1.1 {}\r\nConnection: close\r\n\r\n", status);
+    let _ = stream.get_mut().write_all(response.as_bytes()).await;
+}
+
+// Sec-WebSocket-Accept = base64(sha1(key + GUID))
+pub fn accept_key(key: &str) -> String {
+    base64(&sha1(format!("{}{}", key, WS_GUID).as_bytes()))
+}
+
+// 服务器发出的帧不加掩码：FIN + 文本操作码，随后是长度与数据
+fn text_frame(text: &str) -> Vec<u8> {
+    let len = text.len();
+    let mut frame = vec![0x81];
+    match len {
+        0..=125 => frame.push(len as u8),
+        126..=0xffff => {
+            frame.push(126);
+            frame.extend_from_slice(&(len as u16).to_be_bytes());
+        }
+        _ => {
+            frame.push(127);
+            frame.extend_from_slice(&(len as u64).to_be_bytes());
+        }
+    }
+    frame.extend_from_slice(text.as_bytes());
+    frame
+}
+
+fn sha1(data: &[u8]) -> [u8; 20] {
+    let mut h: [u32; 5] = [0x67452301, 0xefcdab89, 0x98badcfe, 0x10325476, 0xc3d2e1f0];
+    let mut message = data.to_vec();
+    message.push(0x80);
+    while message.len() % 64 != 56 {
+        message.push(0);
+    }
+    message.extend_from_slice(&((data.len() as u64) * 8).to_be_bytes());
+    for block in message.chunks(64) {
+        let mut w = [0u32; 80];
+        for i in 0..16 {
+            w[i] = u32::from_be_bytes([
+                block[4 * i],
+                block[4 * i + 1],
+                block[4 * i + 2],
+                block[4 * i + 3],
+            ]);
+        }
+        for i in 16..80 {
+            w[i] = (w[i - 3] ^ w[i - 8] ^ w[i - 14] ^ w[i - 16]).rotate_left(1);
+        }
+        let [mut a, mut b, mut c, mut d, mut e] = h;
+        for (i, &word) in w.iter().enumerate() {
+            let (f, k) = match i {
+                0..=19 => ((b & c) | (!b & d), 0x5a827999),
+                20..=39 => (b ^ c ^ d, 0x6ed9eba1),
+                40..=59 => ((b & c) | (b & d) | (c & d), 0x8f1bbcdc),
+                _ => (b ^ c ^ d, 0xca62c1d6),
+            };
+            let temp = a
+                .rotate_left(5)
+                .wrapping_add(f)
+                .wrapping_add(e)
+                .wrapping_add(k)
+                .wrapping_add(word);
+            e = d;
+            d = c;
+            c = b.rotate_left(30);
+            b = a;
+            a = temp;
+        }
+        for (h, v) in h.iter_mut().zip([a, b, c, d, e]) {
+            *h = h.wrapping_add(v);
+        }
+    }
+    let mut digest = [0u8; 20];
+    for (chunk, v) in digest.chunks_mut(4).zip(h) {
+        chunk.copy_from_slice(&v.to_be_bytes());
+    }
+    digest
+}
+
+fn base64(data: &[u8]) -> String {
+    const TABLE: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut out = String::new();
+    for chunk in data.chunks(3) {
+        let n = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |n, (i, &b)| n | (b as u32) << (16 - 8 * i));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(TABLE[(n >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
@@ -0,0 +1,182 @@
+use futures::channel::mpsc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use tokio::stream::StreamExt;
+
+use paxos::paxos::ballot::{BallotStrategy, CounterBallots, FloorBallots, TimestampBallots};
+use paxos::paxos::node::{Node, NodeControl};
+use paxos::paxos::proposal::{Datagram, Incoming, Request};
+use paxos::paxos::seq_num::SequenceNumber;
+use paxos::shutdown::Shutdown;
+
+#[test]
+fn test_counter_ballots() {
+    let mut rt = tokio::runtime::Runtime::new().unwrap();
+    rt.block_on(async {
+        let (itx, irx) = mpsc::unbounded();
+        let (otx, mut orx) = mpsc::unbounded();
+        let (_shutdown, shutdown_rx) = Shutdown::new();
+        let ballots = Box::new(CounterBallots::new(2));
+        let node = Node::with_ballots(2, (1..4).collect(), ballots, otx, irx);
+        tokio::spawn(node.run(shutdown_rx));
+
+        // 还没有选定值，每次提案都重新 prepare，序号依次为 1、2、3
+        for time_stamp in 1..4 {
+            let req = Request::Propose {
+                value: 7,
+                priority: 0,
+                seq: None,
+            };
+            itx.unbounded_send(Incoming {
+                src: 0,
+                dgram: Datagram::Request(req),
+            })
+            .unwrap();
+            let prepare = orx.next().await.unwrap().dgram;
+            let seq = SequenceNumber::new(2, time_stamp);
+            assert_eq!(prepare, Datagram::Request(Request::Prepare { seq }));
+        }
+    });
+}
+
+#[test]
+fn test_floor_ballots() {
+    let mut ballots = FloorBallots::new(1, CounterBallots::new(1));
+    assert_eq!(ballots.next(None), SequenceNumber::new(1, 1));
+
+    // 竞争者的序号更高时跳到它之上
+    let floor = SequenceNumber::new(3, 100);
+    let seq = ballots.next(Some(floor));
+    assert_eq!(seq, SequenceNumber::new(1, 101));
+    assert!(seq > floor);
+
+    // 跳过之后内层的序号虽然已经高于 floor，仍不能低于上一次给出的序号
+    let next = ballots.next(Some(SequenceNumber::new(3, 0)));
+    assert_eq!(next, SequenceNumber::new(1, 102));
+    assert_eq!(ballots.peek(None), SequenceNumber::new(1, 103));
+    assert_eq!(ballots.next(None), SequenceNumber::new(1, 103));
+}
+
+#[test]
+fn test_peek_seq() {
+    use paxos::paxos::harness::Harness;
+    use paxos::paxos::proposal::Response;
+
+    let ballots = Box::new(FloorBallots::new(1, CounterBallots::new(1)));
+    let mut harness = Harness::with_ballots(1, (1..4).collect(), ballots);
+    let peek = Incoming {
+        src: 0,
+        dgram: Datagram::Request(Request::PeekSeq),
+    };
+
+    // 承诺过更高的序号后，预览的序号也跳到它之上；多次预览不消耗序号
+    let promised = SequenceNumber::new(3, 10);
+    let prepare = Incoming {
+        src: 3,
+        dgram: Datagram::Request(Request::Prepare { seq: promised }),
+    };
+    harness.feed(vec![prepare]);
+    let peeked = harness.feed(vec![peek]);
+    let seq = match &peeked[..] {
+        [out] => match out.dgram {
+            Datagram::Response(Response::PeekSeq { seq }) => seq,
+            ref dgram => panic!("unexpected {:?}", dgram),
+        },
+        _ => panic!("unexpected {:?}", peeked),
+    };
+    assert_eq!(seq, SequenceNumber::new(1, 11));
+    let again = Incoming {
+        src: 0,
+        dgram: Datagram::Request(Request::PeekSeq),
+    };
+    assert_eq!(harness.feed(vec![again])[0].dgram, peeked[0].dgram);
+
+    // 下一个提案用的正是预览的序号
+    let req = Request::Propose {
+        value: 7,
+        priority: 0,
+        seq: None,
+    };
+    let outgoing = harness.feed(vec![Incoming {
+        src: 0,
+        dgram: Datagram::Request(req),
+    }]);
+    assert_eq!(
+        outgoing[0].dgram,
+        Datagram::Request(Request::Prepare { seq })
+    );
+}
+
+// 按模拟时钟给出序号，每一拍前进 10，不理会 floor
+#[derive(Debug)]
+struct Clock {
+    server_id: usize,
+    now: Arc<AtomicU64>,
+}
+
+impl BallotStrategy for Clock {
+    fn next(&mut self, floor: Option<SequenceNumber>) -> SequenceNumber {
+        self.peek(floor)
+    }
+
+    fn peek(&self, _floor: Option<SequenceNumber>) -> SequenceNumber {
+        let now = self.now.load(Ordering::Relaxed) as u128;
+        SequenceNumber::new(self.server_id, now * 10)
+    }
+}
+
+// #2 按时钟不停重试，#1 每次被抢先后跳到 #2 当前序号之上。一轮需要两拍，
+// 期间 #2 的序号超过 #1 就算被抢先，返回 #1 选定值前一共发起的轮数
+fn rounds_to_decide(mut ballots: impl BallotStrategy, now: &AtomicU64) -> u64 {
+    let rival = |now: &AtomicU64| SequenceNumber::new(2, now.load(Ordering::Relaxed) as u128 * 10);
+    for rounds in 1..=100 {
+        let seq = ballots.next(Some(rival(now)));
+        now.fetch_add(2, Ordering::Relaxed);
+        if seq > rival(now) {
+            return rounds;
+        }
+    }
+    u64::MAX
+}
+
+#[test]
+fn test_ballot_gap() {
+    let contend = |gap| {
+        let now = Arc::new(AtomicU64::new(1));
+        let clock = Clock {
+            server_id: 1,
+            now: now.clone(),
+        };
+        rounds_to_decide(FloorBallots::with_jitter(1, clock, gap, 453), &now)
+    };
+    // 只高出 1 时，两拍之后又被时钟超过，一直来回抢占
+    assert_eq!(contend(1), u64::MAX);
+    // 跳过两拍以上的时钟进度，第一轮就能完成
+    assert_eq!(contend(30), 1);
+    assert!(contend(15) < contend(1));
+
+    // 抖动后的序号仍然可以预览
+    let mut ballots = FloorBallots::with_jitter(1, CounterBallots::new(1), 10, 7);
+    let floor = Some(SequenceNumber::new(3, 100));
+    let peeked = ballots.peek(floor);
+    assert_eq!(ballots.next(floor), peeked);
+    assert!((110..120).contains(&peeked.time_stamp()));
+}
+
+#[test]
+fn test_clock_backwards() {
+    let control = Arc::new(NodeControl::default());
+    let mut ballots = TimestampBallots::new(1, control.clone());
+    let mut last = ballots.next(None);
+
+    // 时钟往回跳一分钟，之后又跳回来；公平平局规则下也一样
+    for (skew, fair) in [(-60_000, false), (-60_000, true), (0, true), (-1, false)] {
+        control.set_clock_skew(skew);
+        control.set_fair_tie_break(fair);
+        for _ in 0..3 {
+            let seq = ballots.next(None);
+            assert!(seq > last, "{:?} after {:?}", seq, last);
+            last = seq;
+        }
+    }
+}
@@ -0,0 +1,70 @@
+use futures::channel::mpsc;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+
+use paxos::net_proxy::{AddrTable, Proxy};
+use paxos::paxos::node::{Node, NodeControl};
+use paxos::paxos::proposal::AcceptedProposal;
+use paxos::paxos::seq_num::SequenceNumber;
+use paxos::paxos::store::{MemoryStore, StateStore};
+use paxos::shutdown::Shutdown;
+
+// 在当前运行时上启动 id 号结点及其代理，成员只有 #1、#2
+async fn spawn(
+    id: usize,
+    table: AddrTable,
+    store: MemoryStore,
+    catch_up: bool,
+) -> (Arc<NodeControl>, futures::channel::oneshot::Sender<()>) {
+    let proxy = Proxy::new(id, table);
+    let listener = proxy.bind().await.unwrap();
+    let (itx, irx) = mpsc::unbounded();
+    let (otx, orx) = mpsc::unbounded();
+    let (shutdown, shutdown_rx) = Shutdown::new();
+    let node = Node::with_store(id, (1..3).collect(), Box::new(store), otx, irx).unwrap();
+    let control = node.control();
+    control.set_catch_up(catch_up);
+    tokio::spawn(proxy.run(listener, itx, orx, shutdown_rx.clone()));
+    tokio::spawn(node.run(shutdown_rx));
+    (control, shutdown)
+}
+
+async fn caught_up(control: &NodeControl) -> bool {
+    let deadline = Instant::now() + Duration::from_millis(500);
+    while Instant::now() < deadline {
+        if control.commit_index() == 1 {
+            return true;
+        }
+        tokio::time::delay_for(Duration::from_millis(10)).await;
+    }
+    false
+}
+
+#[test]
+fn test_catch_up_on_join() {
+    let mut rt = tokio::runtime::Runtime::new().unwrap();
+    rt.block_on(async {
+        let table: HashMap<usize, SocketAddr> = (1..3)
+            .map(|id| (id, format!("127.0.0.1:{}", 10170 + id).parse().unwrap()))
+            .collect();
+        let table: AddrTable = Arc::new(RwLock::new(table));
+        let mut advanced = MemoryStore::default();
+        let chosen = AcceptedProposal::new(SequenceNumber::new(1, 1000), 3);
+        advanced.persist_chosen(chosen).unwrap();
+
+        // 落后的 #2 加入时告知自己的位置，#1 立即把选定的提案推给它；没有开启定期重播
+        let (_, advanced_shutdown) = spawn(1, table.clone(), advanced.clone(), false).await;
+        let (stale, stale_shutdown) = spawn(2, table.clone(), MemoryStore::default(), true).await;
+        assert!(caught_up(&stale).await);
+        drop((advanced_shutdown, stale_shutdown));
+        tokio::time::delay_for(Duration::from_millis(50)).await;
+
+        // 反过来由领先的一方加入：落后的 #2 回告自己的位置，再由 #1 推过去
+        let (stale, _stale_shutdown) = spawn(2, table.clone(), MemoryStore::default(), false).await;
+        assert_eq!(stale.commit_index(), 0);
+        let (_, _advanced_shutdown) = spawn(1, table, advanced, true).await;
+        assert!(caught_up(&stale).await);
+    });
+}
@@ -0,0 +1,135 @@
+use std::net::TcpStream;
+use std::process::{Child, Command, Stdio};
+use std::time::{Duration, Instant};
+
+use paxos::cli::{parse_config, Cli, CliError};
+
+const CONFIG: &str = r#"
+# 两台服务器，#0 是一次性客户端
+[servers]
+0 = "127.0.0.1:9860"
+1 = "127.0.0.1:9861"
+2 = "127.0.0.1:9862"
+"#;
+
+fn args(line: &str) -> Vec<String> {
+    line.split_whitespace().map(String::from).collect()
+}
+
+#[test]
+fn test_parse() {
+    assert_eq!(
+        Cli::parse(&args("node --id 3 --config a.toml --bind 0.0.0.0:9527")),
+        Some(Ok(Cli::Node {
+            id: 3,
+            config: "a.toml".to_string(),
+            bind: Some("0.0.0.0:9527".parse().unwrap()),
+        }))
+    );
+    assert_eq!(
+        Cli::parse(&args("propose --value 42 --server 3")),
+        Some(Ok(Cli::Propose {
+            server: 3,
+            value: 42,
+            config: "cluster.toml".to_string()
+        }))
+    );
+    assert!(matches!(
+        Cli::parse(&args("propose --server 3")),
+        Some(Err(CliError::Usage(_)))
+    ));
+    // 不是子命令时交给控制台
+    assert_eq!(Cli::parse(&[]), None);
+
+    let table = parse_config(CONFIG).unwrap();
+    assert_eq!(table.len(), 3);
+    assert_eq!(table[&2], "127.0.0.1:9862".parse().unwrap());
+    assert!(matches!(
+        parse_config("1 = nowhere"),
+        Err(CliError::Config(_))
+    ));
+}
+
+// 测试结束时杀掉常驻的结点进程
+struct Killed(Child);
+
+impl Drop for Killed {
+    fn drop(&mut self) {
+        let _ = self.0.kill();
+        let _ = self.0.wait();
+    }
+}
+
+fn paxos(line: &str, config: &str) -> Command {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_paxos"));
+    command.args(args(line)).args(["--config", config]);
+    command
+}
+
+#[test]
+fn test_node_processes() {
+    let config = std::env::temp_dir().join("paxos-test-cli.toml");
+    std::fs::write(&config, CONFIG).unwrap();
+    let config = config.to_str().unwrap();
+
+    let _nodes: Vec<Killed> = (1..3)
+        .map(|id| {
+            let child = paxos(&format!("node --id {}", id), config)
+                .stdout(Stdio::null())
+                .spawn()
+                .unwrap();
+            Killed(child)
+        })
+        .collect();
+    // 等两个结点都开始监听
+    let deadline = Instant::now() + Duration::from_secs(5);
+    for port in [9861, 9862] {
+        while TcpStream::connect(("127.0.0.1", port)).is_err() {
+            assert!(Instant::now() < deadline);
+            std::thread::sleep(Duration::from_millis(20));
+        }
+    }
+
+    let output = paxos("propose --server 2 --value 42", config)
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("Server #2 Answer: 42"), "{}", stdout);
+
+    // 另一个客户端提案得到同一个值
+    let output = paxos("propose --server 1 --value 7", config)
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("Server #1 Answer: 42"), "{}", stdout);
+}
+
+fn demo(flag: Option<&str>) -> String {
+    let output = Command::new(env!("CARGO_BIN_EXE_paxos"))
+        .arg("--demo")
+        .args(flag)
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(output.status.success(), "{}", stdout);
+    assert!(stdout.contains("Consensus reached: value"), "{}", stdout);
+    stdout
+}
+
+// 二进制与测试链接的是同一个库，演示模式走的正是库里的控制台。
+// 各次演示用同一组端口，因此依次运行
+#[test]
+fn test_demo_binary() {
+    let normal = demo(None);
+    assert!(normal.contains("handle req"), "{}", normal);
+
+    // 安静模式下没有逐条消息的日志，但仍然报告结果
+    let quiet = demo(Some("--quiet"));
+    assert!(!quiet.contains("handle req"), "{}", quiet);
+    assert!(!quiet.contains("opened connection"), "{}", quiet);
+
+    let verbose = demo(Some("--verbose"));
+    assert!(verbose.contains("handle req"), "{}", verbose);
+    assert!(verbose.contains("opened connection"), "{}", verbose);
+}
@@ -0,0 +1,100 @@
+use paxos::shell::{Command, ParseCommandError};
+
+fn error(line: &str) -> String {
+    line.parse::<Command>().unwrap_err().to_string()
+}
+
+#[test]
+fn test_parse_errors() {
+    assert_eq!("   ".parse::<Command>(), Err(ParseCommandError::Empty));
+    assert_eq!(error("frobnicate 1"), "unknown command `frobnicate`");
+    assert_eq!(
+        error("propose 1"),
+        "too few arguments, usage: propose [--wait] <id> <value> [priority]"
+    );
+    assert_eq!(error("stats now"), "too many arguments, usage: stats");
+    assert_eq!(
+        error("redirect maybe"),
+        "invalid arguments, usage: redirect on|off"
+    );
+    assert_eq!(error("p one 5"), "expected a number, got `one`");
+    assert_eq!(error("slow 2 -5"), "expected a number, got `-5`");
+    assert_eq!(
+        error("migrate 1 nowhere"),
+        "expected an address like 127.0.0.1:9527, got `nowhere`"
+    );
+    // 别名与全名给出同样的提示
+    assert_eq!(error("s"), error("start"));
+}
+
+#[test]
+fn test_usages() {
+    // 每种写法各一条，参数个数不对时都应给出用法，而不是当作未知命令
+    let mut lines = vec![
+        "s 3",
+        "start 3",
+        "p 1 5",
+        "propose --wait 1 5 2",
+        "forcepropose 1 5",
+        "q 1",
+        "query --within 100 1",
+        "qa",
+        "queryall",
+        "m 1 127.0.0.1:9000",
+        "migrate 1 127.0.0.1:9000",
+        "skew 1 -5",
+        "probe",
+        "probe 2",
+        "gossip 2",
+        "redirect on",
+        "tiebreak fair",
+        "slow 1 10",
+        "drop 1 100 7",
+        "proposer 1",
+        "g 1",
+        "group 1",
+        "who 1",
+        "pause 1",
+        "barrier",
+        "stats",
+        "metrics 50",
+        "lag",
+        "ping 1",
+        "pingall",
+        "dump",
+        "conns 1",
+        "stress 1 2",
+        "resume 1",
+        "x",
+        "exit",
+    ];
+    if cfg!(feature = "debug") {
+        lines.extend(["lie 1 off", "seq 1"]);
+    }
+    if cfg!(feature = "http") {
+        lines.push("http 127.0.0.1:8080 1");
+    }
+    if cfg!(feature = "ws") {
+        lines.push("ws 127.0.0.1:8081");
+    }
+    for line in lines {
+        assert!(line.parse::<Command>().is_ok(), "{}", line);
+        let name = line.split_whitespace().next().unwrap();
+        let wrong = format!("{} a b c d e f g", name);
+        assert!(error(&wrong).contains("usage: "), "{}", wrong);
+    }
+}
+
+#[test]
+#[cfg(feature = "debug")]
+fn test_parse_inject_errors() {
+    assert_eq!(
+        error("inject 0 1"),
+        "too few arguments, usage: inject <from> <to> <json>"
+    );
+    assert!(error("inject 0 1 {\"Nope\":1}").starts_with("invalid datagram: json error"));
+    assert_eq!(
+        error("inject x 1 {\"Request\":\"Query\"}"),
+        "expected a number, got `x`"
+    );
+}
@@ -0,0 +1,58 @@
+#![cfg(feature = "debug")]
+
+use futures::channel::mpsc;
+
+use paxos::paxos::node::{DebugState, Node};
+use paxos::paxos::proposal::{AcceptedProposal, Datagram, Incoming, Request};
+use paxos::paxos::seq_num::SequenceNumber;
+
+fn request(src: usize, req: Request) -> Incoming {
+    Incoming {
+        src,
+        dgram: Datagram::Request(req),
+    }
+}
+
+#[test]
+fn test_debug_state() {
+    let (_itx, irx) = mpsc::unbounded();
+    let (otx, _orx) = mpsc::unbounded();
+    let mut node = Node::new(1, (1..4).collect(), otx, irx);
+    assert_eq!(
+        node.debug_state(),
+        DebugState {
+            last_promised: None,
+            last_accepted_proposal: None,
+            chosen: None,
+        }
+    );
+
+    // 承诺给更高的序号，更低的 prepare 不改变承诺
+    let high = SequenceNumber::new(3, 2000);
+    let low = SequenceNumber::new(2, 1000);
+    node.handle(request(3, Request::Prepare { seq: high }));
+    node.handle(request(2, Request::Prepare { seq: low }));
+    assert_eq!(node.debug_state().last_promised, Some(high));
+
+    // 低于承诺的 accept 被拒绝，等于承诺的被接受
+    node.handle(request(2, Request::Accept { seq: low, value: 1 }));
+    assert_eq!(node.debug_state().last_accepted_proposal, None);
+    node.handle(request(
+        3,
+        Request::Accept {
+            seq: high,
+            value: 2,
+        },
+    ));
+    let accepted = Some(AcceptedProposal::new(high, 2));
+    assert_eq!(node.debug_state().last_accepted_proposal, accepted);
+
+    node.handle(request(
+        3,
+        Request::Learn {
+            seq: high,
+            value: 2,
+        },
+    ));
+    assert_eq!(node.debug_state().chosen, accepted);
+}
@@ -0,0 +1,40 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+use paxos::shell::Console;
+
+const SEED: u64 = 1;
+
+// #1 发出的报文按 30% 丢弃，返回丢弃计数以及各服务器学习到的值
+fn run(port: usize) -> (HashMap<usize, u64>, Vec<Option<u32>>) {
+    let mut console = Console::new();
+    console.start_servers(5, port);
+    console.set_drop_rate(1, 300, SEED);
+    console.propose(1, 6);
+    assert!(console.await_quiescent(Duration::from_secs(2)));
+
+    // 关掉丢包之前先取计数，#1 对客户端的回应才不会被丢
+    let dropped = console.dropped(1).unwrap();
+    console.set_drop_rate(1, 0, 0);
+    let answers = (1..6).map(|id| console.query(id)).collect();
+    console.exit();
+    (dropped, answers)
+}
+
+#[test]
+fn test_drop_counter() {
+    let (dropped, answers) = run(9900);
+    let total: u64 = dropped.values().sum();
+    assert!(total > 0);
+    assert!(dropped.keys().all(|id| (2..6).contains(id)));
+
+    // 丢了一些报文，提案者仍然选定了值，学习到值的结点彼此一致
+    assert_eq!(answers[0], Some(6));
+    assert!(answers
+        .iter()
+        .all(|&answer| answer.is_none() || answer == Some(6)));
+
+    // 同一个种子丢弃的报文数相同
+    let (again, _) = run(9910);
+    assert_eq!(again.values().sum::<u64>(), total);
+}
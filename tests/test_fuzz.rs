@@ -0,0 +1,82 @@
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, RwLock};
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use paxos::net_proxy::Proxy;
+use paxos::paxos::proposal::{self, AcceptedProposal, Datagram, Request, Response};
+use paxos::paxos::seq_num::SequenceNumber;
+
+// 每种输入各跑这么多次，固定种子保证失败可以复现
+const ITERATIONS: usize = 200_000;
+const SEED: u64 = 448;
+
+fn samples() -> Vec<Datagram> {
+    let seq = SequenceNumber::new(2, 1000);
+    vec![
+        Datagram::Request(Request::Prepare { seq }),
+        Datagram::Request(Request::Accept { seq, value: 7 }),
+        Datagram::Request(Request::Ping { nonce: u64::MAX }),
+        Datagram::Response(Response::Prepare {
+            seq,
+            accepted: Some(AcceptedProposal::new(seq, 7)),
+        }),
+        Datagram::Response(Response::Query { val: Some(7) }),
+    ]
+}
+
+// 随机字节、截断和翻转合法报文，解码只能返回错误或报文，绝不能 panic
+fn inputs() -> impl Iterator<Item = Vec<u8>> {
+    let mut rng = StdRng::seed_from_u64(SEED);
+    let samples: Vec<Vec<u8>> = samples()
+        .iter()
+        .map(|dgram| dgram.encode_with_src(0).to_vec())
+        .collect();
+    (0..ITERATIONS).map(move |i| match i % 3 {
+        0 => {
+            let len = rng.gen_range(0..64);
+            (0..len).map(|_| rng.gen()).collect()
+        }
+        1 => {
+            let frame = &samples[rng.gen_range(0..samples.len())];
+            frame[..rng.gen_range(0..=frame.len())].to_vec()
+        }
+        _ => {
+            let mut frame = samples[rng.gen_range(0..samples.len())].clone();
+            for _ in 0..rng.gen_range(1..4) {
+                let at = rng.gen_range(0..frame.len());
+                frame[at] = rng.gen();
+            }
+            frame
+        }
+    })
+}
+
+#[test]
+fn test_fuzz_decode() {
+    let mut rng = StdRng::seed_from_u64(SEED);
+    for _ in 0..ITERATIONS {
+        let len = rng.gen_range(0..64);
+        let data: Vec<u8> = (0..len).map(|_| rng.gen()).collect();
+        let _ = proposal::decode(&data);
+    }
+}
+
+#[test]
+fn test_fuzz_read_incoming() {
+    let addr: SocketAddr = "127.0.0.1:10040".parse().unwrap();
+    let table: HashMap<usize, SocketAddr> = (0..2).map(|id| (id, addr)).collect();
+    let proxy = Proxy::new(1, Arc::new(RwLock::new(table)));
+    let mut rt = tokio::runtime::Runtime::new().unwrap();
+    rt.block_on(async {
+        for input in inputs() {
+            // 一段输入里可能有多个报文，读到出错为止
+            let mut reader = &input[..];
+            while let Ok((src, _)) = proxy.read_incoming(&mut reader).await {
+                assert!(src < 2);
+            }
+        }
+    });
+}
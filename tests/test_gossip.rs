@@ -0,0 +1,43 @@
+use paxos::paxos::harness::Network;
+use paxos::paxos::node::NodeState;
+use paxos::paxos::proposal::{Datagram, Incoming, Request};
+use paxos::paxos::seq_num::SequenceNumber;
+
+#[test]
+fn test_gossip() {
+    // 7 个结点，每个只转告 2 个，必须经过多跳才能传遍。转告对象随机，
+    // 并不保证每次都能传遍（漏掉的靠定期重播补上），这里固定一个能传遍的种子
+    let mut network = Network::with_seed(7, 1);
+    for id in 1..8 {
+        network.node(id).unwrap().control().set_gossip_fanout(2);
+    }
+
+    // 模拟提案者只来得及告诉 #1 就宕机了
+    let seq = SequenceNumber::new(1, 1000);
+    let learn = Datagram::Request(Request::Learn { seq, value: 11 });
+    network.send(
+        1,
+        Incoming {
+            src: 0,
+            dgram: learn,
+        },
+    );
+    network.run(1000);
+    for id in 1..8 {
+        assert_eq!(network.node(id).unwrap().node().state(), NodeState::Decided);
+    }
+
+    // #1 只转告了 2 个结点，其余的是从别的结点第一次听说的
+    let learns: Vec<(usize, usize)> = network
+        .trace()
+        .iter()
+        .filter(|(_, _, dgram)| matches!(dgram, Datagram::Request(Request::Learn { .. })))
+        .map(|&(src, dst, _)| (src, dst))
+        .collect();
+    assert_eq!(learns.iter().filter(|&&(src, _)| src == 1).count(), 2);
+    let relayed = (2..8).filter(|&id| {
+        let first = learns.iter().find(|&&(_, dst)| dst == id).unwrap();
+        first.0 != 1
+    });
+    assert!(relayed.count() >= 4);
+}
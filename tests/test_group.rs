@@ -0,0 +1,28 @@
+use std::{thread, time::Duration};
+
+use paxos::shell::Console;
+
+#[test]
+fn test_independent_groups() {
+    let mut console = Console::new();
+    console.select_group(1);
+    console.start_servers(3, 9730);
+    console.select_group(2);
+    console.start_servers(3, 9740);
+    thread::sleep(Duration::from_millis(100));
+
+    // 两组各自选定不同的值，互不干扰
+    console.select_group(1);
+    console.propose(1, 11);
+    console.select_group(2);
+    console.propose(1, 22);
+    thread::sleep(Duration::from_millis(300));
+
+    for (group, val) in [(1, 11), (2, 22)] {
+        console.select_group(group);
+        for i in 1..4 {
+            assert_eq!(console.query_wait(i, Duration::from_secs(1)), Some(val));
+        }
+    }
+    console.exit()
+}
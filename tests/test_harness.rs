@@ -0,0 +1,383 @@
+use paxos::paxos::ballot::CounterBallots;
+use paxos::paxos::harness::{Harness, Network};
+use paxos::paxos::node::NodeState;
+use paxos::paxos::proposal::{
+    AcceptedProposal, Datagram, DecisionRecord, Incoming, Proposal, Request, Response,
+};
+use paxos::paxos::seq_num::SequenceNumber;
+
+fn request(src: usize, req: Request) -> Incoming {
+    Incoming {
+        src,
+        dgram: Datagram::Request(req),
+    }
+}
+
+fn response(src: usize, resp: Response) -> Incoming {
+    Incoming {
+        src,
+        dgram: Datagram::Response(resp),
+    }
+}
+
+#[test]
+fn test_promise() {
+    let mut harness = Harness::new(1, (1..4).collect());
+    let seq = SequenceNumber::new(2, 1000);
+    let outgoing = harness.feed(vec![request(2, Request::Prepare { seq })]);
+    assert_eq!(outgoing.len(), 1);
+    assert_eq!(outgoing[0].dst, (2..3).collect());
+    assert_eq!(
+        outgoing[0].dgram,
+        Datagram::Response(Response::Prepare {
+            seq,
+            accepted: None
+        })
+    );
+
+    // 更低的 prepare 不产生任何报文
+    let low = SequenceNumber::new(3, 10);
+    assert!(harness
+        .feed(vec![request(3, Request::Prepare { seq: low })])
+        .is_empty());
+}
+
+#[test]
+fn test_full_round() {
+    let mut harness = Harness::with_ballots(1, (1..4).collect(), Box::new(CounterBallots::new(1)));
+    let seq = SequenceNumber::new(1, 1);
+    let propose = Request::Propose {
+        value: 6,
+        priority: 0,
+        seq: None,
+    };
+    let outgoing = harness.feed(vec![request(0, propose)]);
+    assert_eq!(
+        outgoing[0].dgram,
+        Datagram::Request(Request::Prepare { seq })
+    );
+
+    let promises = (1..3).map(|src| {
+        let accepted = None;
+        response(src, Response::Prepare { seq, accepted })
+    });
+    let outgoing = harness.feed(promises);
+    assert_eq!(
+        outgoing[0].dgram,
+        Datagram::Request(Request::Accept { seq, value: 6 })
+    );
+
+    harness.feed((1..3).map(|src| response(src, Response::Accepted { seq })));
+    assert_eq!(harness.node().state(), NodeState::Decided);
+}
+
+#[test]
+fn test_late_conflicting_promise() {
+    let mut harness = Harness::with_ballots(1, (1..6).collect(), Box::new(CounterBallots::new(1)));
+    let seq = SequenceNumber::new(1, 1);
+    let propose = Request::Propose {
+        value: 6,
+        priority: 0,
+        seq: None,
+    };
+    harness.feed(vec![request(0, propose)]);
+    let promises = (1..4).map(|src| {
+        let accepted = None;
+        response(src, Response::Prepare { seq, accepted })
+    });
+    let outgoing = harness.feed(promises);
+    assert_eq!(
+        outgoing[0].dgram,
+        Datagram::Request(Request::Accept { seq, value: 6 })
+    );
+
+    // 凑够多数派之后才到的承诺带着别的值，既不能改掉提案的值，也不能再发一轮 accept
+    let accepted = Some(AcceptedProposal::new(SequenceNumber::new(4, 0), 9));
+    let late = response(4, Response::Prepare { seq, accepted });
+    assert!(harness.feed(vec![late]).is_empty());
+    assert_eq!(harness.node().state(), NodeState::Accepting);
+    assert_eq!(harness.control().stats().violations, 0);
+
+    let outgoing = harness.feed((1..4).map(|src| response(src, Response::Accepted { seq })));
+    assert!(outgoing
+        .iter()
+        .any(|out| out.dgram == Datagram::Request(Request::Learn { seq, value: 6 })));
+    assert_eq!(harness.node().state(), NodeState::Decided);
+}
+
+#[test]
+fn test_value_immutable() {
+    let mut proposal = Proposal::new(SequenceNumber::new(1, 1), 6);
+    assert_eq!(proposal.value(), None);
+    assert_eq!(proposal.set_value(7), Ok(()));
+    // 设定同一个值没有问题，改成别的值被拒绝，原值保持不变
+    assert_eq!(proposal.set_value(7), Ok(()));
+    assert_eq!(proposal.set_value(6), Err(7));
+    assert_eq!(proposal.value(), Some(7));
+}
+
+fn learn(value: u32) -> Incoming {
+    let seq = SequenceNumber::new(2, 1000 + value as u128);
+    request(2, Request::Learn { seq, value })
+}
+
+#[test]
+#[should_panic]
+fn test_strict_violation() {
+    let mut harness = Harness::new(1, (1..4).collect());
+    harness.feed(vec![learn(1), learn(2)]);
+}
+
+#[test]
+fn test_lenient_violation() {
+    let mut harness = Harness::new(1, (1..4).collect());
+    harness.control().set_lenient(true);
+
+    // 学习到与选定值不同的值只记录违例，结点保留原来的选定值继续服务
+    harness.feed(vec![learn(1), learn(2)]);
+    assert_eq!(harness.control().stats().violations, 1);
+    let outgoing = harness.feed(vec![request(0, Request::Query)]);
+    assert_eq!(
+        outgoing[0].dgram,
+        Datagram::Response(Response::Query { val: Some(1) })
+    );
+
+    // 没有提案时收到的回应同样只是记录
+    let seq = SequenceNumber::new(1, 1);
+    harness.feed(vec![response(3, Response::Accepted { seq })]);
+    assert_eq!(harness.control().stats().violations, 2);
+}
+
+fn propose(value: u32) -> Incoming {
+    let req = Request::Propose {
+        value,
+        priority: 0,
+        seq: None,
+    };
+    request(0, req)
+}
+
+fn chosen(network: &Network, id: usize) -> Option<u32> {
+    let node = network.node(id).unwrap().node();
+    node.snapshot().chosen.map(|chosen| chosen.val)
+}
+
+#[test]
+fn test_reorder() {
+    for seed in 0..20 {
+        let mut network = Network::new(3);
+        network.set_reorder(Some(seed));
+        network.send(1, propose(3));
+        network.run(1000);
+
+        // 乱序只会让结点忽略过时的回应，一个提案者时所有结点都学习到它的值
+        for id in 1..4 {
+            assert_eq!(chosen(&network, id), Some(3), "seed {}", seed);
+        }
+    }
+}
+
+#[test]
+fn test_reorder_competing() {
+    for seed in 0..20 {
+        let mut network = Network::new(5);
+        network.set_reorder(Some(seed));
+        network.send(1, propose(1));
+        network.send(2, propose(2));
+        network.run(10_000);
+
+        // 没有重试，被抢占的提案就此停下；序号更高的那个不会再被抢占，
+        // 无论怎样投递都能选定。选定了的结点必须一致
+        let values: Vec<u32> = (1..6).filter_map(|id| chosen(&network, id)).collect();
+        assert!(!values.is_empty(), "seed {}", seed);
+        assert!(values.windows(2).all(|w| w[0] == w[1]), "seed {}", seed);
+    }
+}
+
+#[test]
+fn test_self_in_peers() {
+    // 只列出 #2、#3 时同样把自己算进成员，多数派仍是 3 个中的 2 个
+    let ballots = Box::new(CounterBallots::new(1));
+    let mut harness = Harness::with_ballots(1, (2..4).collect(), ballots);
+    let seq = SequenceNumber::new(1, 1);
+    let propose = Request::Propose {
+        value: 6,
+        priority: 0,
+        seq: None,
+    };
+    let outgoing = harness.feed(vec![request(0, propose)]);
+    assert_eq!(outgoing[0].dst, (1..4).collect());
+
+    // 自己的承诺只算一票，重复送达也不会凑成多数派
+    let promise = || {
+        let accepted = None;
+        response(1, Response::Prepare { seq, accepted })
+    };
+    assert!(harness.feed(vec![promise(), promise()]).is_empty());
+    let accepted = None;
+    let outgoing = harness.feed(vec![response(2, Response::Prepare { seq, accepted })]);
+    assert_eq!(
+        outgoing[0].dgram,
+        Datagram::Request(Request::Accept { seq, value: 6 })
+    );
+}
+
+#[test]
+fn test_single_node() {
+    // 成员为空时只有自己：广播投递给自己，转告时没有别的结点，什么都不发
+    let mut harness = Harness::new(1, Default::default());
+    harness.control().set_gossip_fanout(2);
+    let propose = Request::Propose {
+        value: 4,
+        priority: 0,
+        seq: None,
+    };
+    let mut pending = harness.feed(vec![request(0, propose)]);
+    let mut steps = 0;
+    while let Some(outgoing) = pending.pop() {
+        assert!(!outgoing.dst.is_empty(), "{:?}", outgoing);
+        if outgoing.dst.contains(&1) {
+            let incoming = Incoming {
+                src: 1,
+                dgram: outgoing.dgram,
+            };
+            pending.extend(harness.feed(vec![incoming]));
+        }
+        steps += 1;
+        assert!(steps < 100);
+    }
+    assert_eq!(harness.node().snapshot().chosen.map(|c| c.val), Some(4));
+}
+
+#[test]
+fn test_decision_record() {
+    let mut harness = Harness::with_ballots(1, (1..6).collect(), Box::new(CounterBallots::new(1)));
+    let propose = || Request::Propose {
+        value: 6,
+        priority: 0,
+        seq: None,
+    };
+    // 第一轮没有凑够承诺就重新提案，记录中算作两轮
+    harness.feed(vec![request(0, propose()), request(0, propose())]);
+    assert!(harness
+        .feed(vec![request(0, Request::WaitDecision)])
+        .is_empty());
+    let seq = SequenceNumber::new(1, 2);
+    let promises = (1..4).map(|src| {
+        let accepted = None;
+        response(src, Response::Prepare { seq, accepted })
+    });
+    harness.feed(promises);
+
+    // 多数派正是回应了 accepted 的 #2、#4、#5
+    let outgoing = harness.feed([2, 4, 5].map(|src| response(src, Response::Accepted { seq })));
+    let record = DecisionRecord {
+        value: 6,
+        ballot: seq,
+        deciding_quorum: [2, 4, 5].into_iter().collect(),
+        rounds: 2,
+    };
+    let decision = outgoing.iter().find(|out| out.dst.contains(&0)).unwrap();
+    assert_eq!(
+        decision.dgram,
+        Datagram::Response(Response::Decision {
+            value: 6,
+            record: Some(record.clone())
+        })
+    );
+
+    // 选定之后再问也能拿到同一份记录
+    let outgoing = harness.feed(vec![request(0, Request::WaitDecision)]);
+    assert_eq!(
+        outgoing[0].dgram,
+        Datagram::Response(Response::Decision {
+            value: 6,
+            record: Some(record)
+        })
+    );
+}
+
+// 同一个种子下，乱序投递、gossip 抽取与序号都一样，收发的报文逐条相同
+#[test]
+fn test_seeded_network() {
+    let run = |seed| {
+        let mut network = Network::with_seed(5, seed);
+        for id in 1..6 {
+            network.node(id).unwrap().control().set_gossip_fanout(2);
+        }
+        network.send(1, propose(1));
+        network.send(2, propose(2));
+        network.run(10_000);
+        network.trace().to_vec()
+    };
+    let trace = run(42);
+    assert!(!trace.is_empty());
+    assert_eq!(trace, run(42));
+    assert_ne!(trace, run(43));
+}
+
+#[test]
+fn test_already_chosen() {
+    let mut harness = Harness::new(1, (1..4).collect());
+    harness.feed(vec![learn(4)]);
+
+    // 提出已选定的值立即得到回应，不发起新一轮
+    let outgoing = harness.feed(vec![propose(4)]);
+    assert_eq!(outgoing.len(), 1);
+    assert_eq!(outgoing[0].dst, (0..1).collect());
+    assert_eq!(
+        outgoing[0].dgram,
+        Datagram::Response(Response::AlreadyChosen { value: 4 })
+    );
+    assert_eq!(harness.node().state(), NodeState::Decided);
+
+    // 带着不属于本结点的旧序号也是一样，不会因为序号不对而被悄悄丢掉
+    let propose = Request::Propose {
+        value: 4,
+        priority: 0,
+        seq: Some(SequenceNumber::new(2, 1)),
+    };
+    let outgoing = harness.feed(vec![request(0, propose)]);
+    assert_eq!(outgoing.len(), 1);
+    assert_eq!(
+        outgoing[0].dgram,
+        Datagram::Response(Response::AlreadyChosen { value: 4 })
+    );
+}
+
+#[test]
+fn test_already_chosen_keeps_ballots() {
+    // 选定之后的重复提案不会用掉序号，下一个序号仍是选定前的那个
+    let mut harness = Harness::with_ballots(1, (1..4).collect(), Box::new(CounterBallots::new(1)));
+    harness.feed(vec![learn(4)]);
+    harness.feed(vec![propose(4), propose(5)]);
+    let seq = harness.feed(vec![request(0, Request::PeekSeq)]);
+    assert_eq!(
+        seq[0].dgram,
+        Datagram::Response(Response::PeekSeq {
+            seq: SequenceNumber::new(1, 1)
+        })
+    );
+}
+
+#[test]
+fn test_learn_overrides_accepted() {
+    let accept = |seq, value| request(3, Request::Accept { seq, value });
+
+    // 接受过 #3 的值 5，之后学到更高提案选定的 8：以学到的为准，不算违例
+    let mut harness = Harness::new(1, (1..4).collect());
+    harness.feed(vec![accept(SequenceNumber::new(3, 10), 5)]);
+    let seq = SequenceNumber::new(2, 20);
+    harness.feed(vec![request(2, Request::Learn { seq, value: 8 })]);
+    assert_eq!(harness.node().snapshot().chosen.map(|c| c.val), Some(8));
+    assert_eq!(harness.control().stats().violations, 0);
+
+    // 选定的提案不高于自己接受的另一个值，说明有人违背了协议
+    let mut harness = Harness::new(1, (1..4).collect());
+    harness.control().set_lenient(true);
+    harness.feed(vec![accept(SequenceNumber::new(3, 10), 5)]);
+    let seq = SequenceNumber::new(2, 5);
+    harness.feed(vec![request(2, Request::Learn { seq, value: 8 })]);
+    assert_eq!(harness.node().snapshot().chosen.map(|c| c.val), Some(8));
+    assert_eq!(harness.control().stats().violations, 1);
+}
@@ -0,0 +1,85 @@
+#![cfg(feature = "http")]
+
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::{thread, time::Duration};
+
+use paxos::shell::Console;
+
+// 发出一个 HTTP 请求，返回状态行和响应体
+fn http(method: &str, path: &str, body: &str) -> (String, String) {
+    let head = format!(
+        "{} {} HTTP/1.1\r\nHost: localhost\r\nContent-Length: {}\r\n\r\n",
+        method,
+        path,
+        body.len()
+    );
+    raw(9790, &(head + body))
+}
+
+// 原样发出 request，返回状态行和响应体
+fn raw(port: u16, request: &str) -> (String, String) {
+    let mut stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+    stream.write_all(request.as_bytes()).unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    let (head, body) = response.split_once("\r\n\r\n").unwrap();
+    (head.lines().next().unwrap().to_string(), body.to_string())
+}
+
+#[test]
+fn test_http_gateway() {
+    let mut console = Console::new();
+    console.start_servers(3, 9791);
+    console.start_http("127.0.0.1:9790".parse().unwrap(), 1);
+
+    let (status, _) = http("POST", "/propose", r#"{"value":42}"#);
+    assert_eq!(status, "HTTP/1.1 202 Accepted");
+
+    let mut body = String::new();
+    for _ in 0..20 {
+        let (status, answer) = http("GET", "/value", "");
+        assert_eq!(status, "HTTP/1.1 200 OK");
+        body = answer;
+        if body != r#"{"value":null}"# {
+            break;
+        }
+        thread::sleep(Duration::from_millis(50));
+    }
+    assert_eq!(body, r#"{"value":42}"#);
+    console.exit()
+}
+
+#[test]
+fn test_http_limits() {
+    let mut console = Console::new();
+    console.start_servers(3, 10261);
+    console.start_http("127.0.0.1:10260".parse().unwrap(), 1);
+
+    // 请求体的长度由客户端给出，过大或者无法解析时不分配也不读
+    let (status, _) = raw(
+        10260,
+        "POST /propose HTTP/1.1\r\nContent-Length: 4000000000\r\n\r\n",
+    );
+    assert_eq!(status, "HTTP/1.1 413 Payload Too Large");
+    let (status, _) = raw(
+        10260,
+        "POST /propose HTTP/1.1\r\nContent-Length: lots\r\n\r\n",
+    );
+    assert_eq!(status, "HTTP/1.1 400 Bad Request");
+
+    // 闲置的连接超时后被打发，之后的请求照常处理
+    let idle = TcpStream::connect("127.0.0.1:10260").unwrap();
+    let start = std::time::Instant::now();
+    let (status, _) = raw(10260, "GET /value HTTP/1.1\r\n\r\n");
+    assert_eq!(status, "HTTP/1.1 200 OK");
+    assert!(start.elapsed() < Duration::from_secs(3));
+    let mut response = String::new();
+    (&idle).read_to_string(&mut response).unwrap();
+    assert!(
+        response.starts_with("HTTP/1.1 408 Request Timeout"),
+        "{}",
+        response
+    );
+    console.exit()
+}
@@ -0,0 +1,31 @@
+use std::io::Write;
+use std::net::TcpStream;
+use std::time::Duration;
+
+use paxos::paxos::proposal::{Datagram, Request};
+use paxos::paxos::seq_num::SequenceNumber;
+use paxos::shell::Console;
+
+#[test]
+fn test_dropped_connection() {
+    let mut console = Console::new();
+    console.start_servers(3, 9630);
+
+    // 发送半个报文后断开连接
+    let buf = Datagram::Request(Request::Learn {
+        seq: SequenceNumber::new(2, 1000),
+        value: 5,
+    })
+    .encode_with_src(2);
+    let mut stream = TcpStream::connect("127.0.0.1:9631").unwrap();
+    stream.write_all(&buf[..buf.len() - 1]).unwrap();
+    drop(stream);
+
+    // 新的连接照常送达
+    let mut stream = TcpStream::connect("127.0.0.1:9631").unwrap();
+    stream.write_all(&buf).unwrap();
+    drop(stream);
+
+    assert_eq!(console.query_wait(1, Duration::from_secs(5)), Some(5));
+    console.exit()
+}
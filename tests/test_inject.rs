@@ -0,0 +1,75 @@
+#![cfg(feature = "debug")]
+
+use paxos::paxos::proposal::{Datagram, Request, Response};
+use paxos::paxos::seq_num::SequenceNumber;
+use paxos::shell::{Command, Console};
+use std::time::Duration;
+
+#[test]
+fn test_inject() {
+    let mut console = Console::new();
+    console.start_servers(3, 9720);
+
+    let json = r#"inject 0 1 {"Request":{"Prepare":{"seq":{"time_stamp":1000,"server_id":3}}}}"#;
+    let seq = SequenceNumber::new(3, 1000);
+    let prepare = Datagram::Request(Request::Prepare { seq });
+    assert_eq!(json.parse(), Ok(Command::Inject(0, 1, prepare.clone())));
+
+    // 构造的 prepare 得到承诺
+    let resp = console.inject(0, 1, prepare);
+    assert_eq!(
+        resp,
+        Some(Response::Prepare {
+            seq,
+            accepted: None
+        })
+    );
+
+    // 序号低于承诺的 accept 被忽略
+    let low = SequenceNumber::new(3, 500);
+    let accept = Datagram::Request(Request::Accept { seq: low, value: 1 });
+    assert_eq!(console.inject(0, 1, accept), None);
+    console.exit()
+}
+
+#[test]
+fn test_liar() {
+    let mut console = Console::new();
+    console.start_servers(3, 9810);
+
+    // #1 和 #2 接受了 7，#1 已经学习到，7 就是选定的值
+    let seq = SequenceNumber::new(1, 1000);
+    for to in 1..3 {
+        let accept = Datagram::Request(Request::Accept { seq, value: 7 });
+        assert_eq!(
+            console.inject(0, to, accept),
+            Some(Response::Accepted { seq })
+        );
+    }
+    console.inject(0, 1, Datagram::Request(Request::Learn { seq, value: 7 }));
+    assert_eq!(console.query(1), Some(7));
+
+    // #2 谎报自己接受的是 8，#1 又迟迟不回应，#3 于是选定了另一个值。
+    // 这说明经典 Paxos 不能容忍拜占庭故障
+    console.lie(2, true);
+    console.slow(1, 5000);
+    console.propose(3, 9);
+    let chosen = console.query_wait(3, Duration::from_secs(2));
+    assert_eq!(chosen, Some(8));
+    console.exit()
+}
+
+#[test]
+fn test_peek_seq() {
+    assert_eq!("seq 2".parse(), Ok(Command::Seq(2)));
+
+    let mut console = Console::new();
+    console.start_servers(3, 10030);
+    // 不同服务器的序号由 server_id 区分
+    let first = console.peek_seq(1).unwrap();
+    let second = console.peek_seq(2).unwrap();
+    assert_eq!((first.server_id(), second.server_id()), (1, 2));
+    // 预览不产生提案
+    assert_eq!(console.query(1), None);
+    console.exit()
+}
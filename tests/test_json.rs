@@ -0,0 +1,60 @@
+use std::collections::HashMap;
+
+use paxos::json;
+use paxos::paxos::proposal::{AcceptedProposal, Datagram, Request, Response};
+use paxos::paxos::seq_num::SequenceNumber;
+
+#[test]
+fn test_datagram_roundtrip() {
+    let seq = SequenceNumber::new(2, u64::MAX as u128 + 1);
+    let dgrams = vec![
+        Datagram::Request(Request::Query),
+        Datagram::Request(Request::Accept { seq, value: 7 }),
+        Datagram::Response(Response::Prepare {
+            seq,
+            accepted: None,
+        }),
+        Datagram::Response(Response::Prepare {
+            seq,
+            accepted: Some(AcceptedProposal::new(seq, 7)),
+        }),
+    ];
+    for dgram in dgrams {
+        let text = json::to_string(&dgram).unwrap();
+        assert_eq!(json::from_str::<Datagram>(&text).unwrap(), dgram);
+    }
+    assert_eq!(
+        json::to_string(&Datagram::Request(Request::Query)).unwrap(),
+        r#"{"Request":"Query"}"#
+    );
+}
+
+#[test]
+fn test_map_and_errors() {
+    let map: HashMap<usize, Option<String>> = vec![(1, Some("a \"b\"".to_string())), (2, None)]
+        .into_iter()
+        .collect();
+    let text = json::to_string(&map).unwrap();
+    assert_eq!(
+        json::from_str::<HashMap<usize, Option<String>>>(&text).unwrap(),
+        map
+    );
+
+    assert!(json::from_str::<Datagram>(r#"{"Request":"Nope"}"#).is_err());
+    assert!(json::from_str::<Datagram>(r#"{"Request":"Query"} x"#).is_err());
+    assert!(json::from_str::<u8>("300").is_err());
+}
+
+#[test]
+fn test_depth_limit() {
+    // 嵌套过深时报错，而不是递归到栈溢出
+    let deep = "[".repeat(100_000) + &"]".repeat(100_000);
+    let err = json::from_str::<Vec<()>>(&deep).unwrap_err();
+    assert!(err.to_string().contains("nested too deeply"), "{}", err);
+
+    let shallow = "[".repeat(64) + &"]".repeat(64);
+    assert!(!json::from_str::<Datagram>(&shallow)
+        .unwrap_err()
+        .to_string()
+        .contains("nested too deeply"));
+}
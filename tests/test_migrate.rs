@@ -0,0 +1,23 @@
+use std::{thread, time::Duration};
+
+use paxos::shell::Console;
+
+#[test]
+fn test_migrate() {
+    let mut console = Console::new();
+    console.start_servers(3, 9600);
+    thread::sleep(Duration::from_millis(100));
+
+    // 迁移后的结点仍然可以提出提案并参与表决
+    console.migrate(3, "127.0.0.1:9610".parse().unwrap());
+    console.propose(3, 42);
+    thread::sleep(Duration::from_millis(300));
+    for i in 1..4 {
+        assert_eq!(console.query(i), Some(42));
+    }
+
+    // 迁移时保留已学习到的值
+    console.migrate(2, "127.0.0.1:9611".parse().unwrap());
+    assert_eq!(console.query(2), Some(42));
+    console.exit()
+}
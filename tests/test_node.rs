@@ -0,0 +1,487 @@
+use std::time::Duration;
+
+use futures::channel::{mpsc, oneshot};
+use std::sync::Arc;
+
+use paxos::paxos::node::{Node, NodeControl, NodeState};
+use paxos::paxos::proposal::{
+    AcceptedProposal, Datagram, Incoming, NodeSnapshot, Outgoing, Request, Response,
+};
+use paxos::paxos::seq_num::SequenceNumber;
+use paxos::paxos::{Rx, Tx};
+use paxos::shutdown::Shutdown;
+use tokio::stream::StreamExt;
+
+struct TestNode {
+    tx: Tx<Incoming>,
+    rx: Rx<Outgoing>,
+    control: Arc<NodeControl>,
+    _shutdown: oneshot::Sender<()>,
+}
+
+// 不经过网络直接驱动一个结点，通过它的输入端和输出端观察行为
+fn spawn_node(node_id: usize) -> TestNode {
+    let (itx, irx) = mpsc::unbounded();
+    let (otx, orx) = mpsc::unbounded();
+    let (shutdown, shutdown_rx) = Shutdown::new();
+    let node = Node::new(node_id, (1..4).collect(), otx, irx);
+    let control = node.control();
+    tokio::spawn(node.run(shutdown_rx));
+    TestNode {
+        tx: itx,
+        rx: orx,
+        control,
+        _shutdown: shutdown,
+    }
+}
+
+fn request(src: usize, req: Request) -> Incoming {
+    Incoming {
+        src,
+        dgram: Datagram::Request(req),
+    }
+}
+
+fn propose_request(value: u32) -> Incoming {
+    request(
+        0,
+        Request::Propose {
+            value,
+            priority: 0,
+            seq: None,
+        },
+    )
+}
+
+async fn propose(node: &mut TestNode) -> SequenceNumber {
+    node.tx.unbounded_send(propose_request(1)).unwrap();
+    match node.rx.next().await.unwrap().dgram {
+        Datagram::Request(Request::Prepare { seq }) => seq,
+        dgram => panic!("unexpected {:?}", dgram),
+    }
+}
+
+#[test]
+fn test_clock_skew() {
+    let mut rt = tokio::runtime::Runtime::new().unwrap();
+    rt.block_on(async {
+        let mut fast = spawn_node(1);
+        let mut slow = spawn_node(2);
+        let mut acceptor = spawn_node(3);
+        fast.control.set_clock_skew(60_000);
+
+        // 时钟快的结点先提案，后提案的结点反而序号更小
+        let fast_seq = propose(&mut fast).await;
+        tokio::time::delay_for(Duration::from_millis(10)).await;
+        let slow_seq = propose(&mut slow).await;
+        assert!(fast_seq > slow_seq);
+
+        // 决策结点承诺给快结点之后，忽略慢结点的 prepare
+        acceptor
+            .tx
+            .unbounded_send(request(1, Request::Prepare { seq: fast_seq }))
+            .unwrap();
+        let outgoing = acceptor.rx.next().await.unwrap();
+        assert!(outgoing.dst.contains(&1));
+        assert!(matches!(
+            outgoing.dgram,
+            Datagram::Response(Response::Prepare { accepted: None, .. })
+        ));
+        acceptor
+            .tx
+            .unbounded_send(request(2, Request::Prepare { seq: slow_seq }))
+            .unwrap();
+        let ignored = tokio::time::timeout(Duration::from_millis(100), acceptor.rx.next()).await;
+        assert!(ignored.is_err());
+    });
+}
+
+#[test]
+fn test_acceptor_only() {
+    let mut rt = tokio::runtime::Runtime::new().unwrap();
+    rt.block_on(async {
+        let mut node = spawn_node(1);
+        node.control.set_proposer(Some(2));
+
+        // 客户端的提案原样转交给 #2，自己不发 prepare
+        for value in 0..3 {
+            node.tx.unbounded_send(propose_request(value)).unwrap();
+            let outgoing = node.rx.next().await.unwrap();
+            assert_eq!(outgoing.dst, (2..3).collect());
+            match outgoing.dgram {
+                Datagram::Request(Request::Propose { value: v, .. }) => assert_eq!(v, value),
+                dgram => panic!("unexpected {:?}", dgram),
+            }
+        }
+
+        // #1 仍然正常做决策者
+        let seq = SequenceNumber::new(2, 1000);
+        node.tx
+            .unbounded_send(request(2, Request::Prepare { seq }))
+            .unwrap();
+        let outgoing = node.rx.next().await.unwrap();
+        assert_eq!(
+            outgoing.dgram,
+            Datagram::Response(Response::Prepare {
+                seq,
+                accepted: None
+            })
+        );
+    });
+}
+
+fn response(src: usize, resp: Response) -> Incoming {
+    Incoming {
+        src,
+        dgram: Datagram::Response(resp),
+    }
+}
+
+#[test]
+fn test_proposer_learns_locally() {
+    let mut rt = tokio::runtime::Runtime::new().unwrap();
+    rt.block_on(async {
+        let mut node = spawn_node(1);
+        node.tx.unbounded_send(propose_request(8)).unwrap();
+        let seq = match node.rx.next().await.unwrap().dgram {
+            Datagram::Request(Request::Prepare { seq }) => seq,
+            dgram => panic!("unexpected {:?}", dgram),
+        };
+        for src in 2..4 {
+            node.tx
+                .unbounded_send(response(
+                    src,
+                    Response::Prepare {
+                        seq,
+                        accepted: None,
+                    },
+                ))
+                .unwrap();
+        }
+        let accept = node.rx.next().await.unwrap().dgram;
+        assert_eq!(accept, Datagram::Request(Request::Accept { seq, value: 8 }));
+        for src in 2..4 {
+            node.tx
+                .unbounded_send(response(src, Response::Accepted { seq }))
+                .unwrap();
+        }
+        let learn = node.rx.next().await.unwrap().dgram;
+        assert_eq!(learn, Datagram::Request(Request::Learn { seq, value: 8 }));
+
+        // 广播出去的 Learn 没有送回给自己，查询时也已经学习到了
+        node.tx.unbounded_send(request(0, Request::Query)).unwrap();
+        let answer = node.rx.next().await.unwrap().dgram;
+        assert_eq!(answer, Datagram::Response(Response::Query { val: Some(8) }));
+    });
+}
+
+#[test]
+fn test_state_transitions() {
+    let mut rt = tokio::runtime::Runtime::new().unwrap();
+    rt.block_on(async {
+        let mut node = spawn_node(1);
+        assert_eq!(node.control.state(), NodeState::Idle);
+
+        let seq = propose(&mut node).await;
+        assert_eq!(node.control.state(), NodeState::Preparing);
+
+        for src in 2..4 {
+            node.tx
+                .unbounded_send(response(
+                    src,
+                    Response::Prepare {
+                        seq,
+                        accepted: None,
+                    },
+                ))
+                .unwrap();
+        }
+        node.rx.next().await.unwrap();
+        assert_eq!(node.control.state(), NodeState::Accepting);
+
+        for src in 2..4 {
+            node.tx
+                .unbounded_send(response(src, Response::Accepted { seq }))
+                .unwrap();
+        }
+        node.rx.next().await.unwrap();
+        assert_eq!(node.control.state(), NodeState::Decided);
+    });
+}
+
+#[test]
+fn test_explicit_seq() {
+    let mut rt = tokio::runtime::Runtime::new().unwrap();
+    rt.block_on(async {
+        let mut node = spawn_node(1);
+
+        // 指定的高序号原样用于 prepare
+        let high = SequenceNumber::new(1, u64::MAX as u128);
+        let req = Request::Propose {
+            value: 1,
+            priority: 0,
+            seq: Some(high),
+        };
+        node.tx.unbounded_send(request(0, req)).unwrap();
+        let prepare = node.rx.next().await.unwrap().dgram;
+        assert_eq!(prepare, Datagram::Request(Request::Prepare { seq: high }));
+
+        // 不高于已用过的序号，或者属于别的结点，都会被拒绝
+        for seq in [
+            SequenceNumber::new(1, 1000),
+            SequenceNumber::new(2, u128::MAX),
+        ] {
+            let req = Request::Propose {
+                value: 2,
+                priority: 0,
+                seq: Some(seq),
+            };
+            node.tx.unbounded_send(request(0, req)).unwrap();
+        }
+        let ignored = tokio::time::timeout(Duration::from_millis(100), node.rx.next()).await;
+        assert!(ignored.is_err());
+    });
+}
+
+#[test]
+fn test_snapshot_restore() {
+    let mut rt = tokio::runtime::Runtime::new().unwrap();
+    rt.block_on(async {
+        let (itx, irx) = mpsc::unbounded();
+        let (otx, mut orx) = mpsc::unbounded();
+        let (shutdown, shutdown_rx) = Shutdown::new();
+        let node = tokio::spawn(Node::new(1, (1..4).collect(), otx, irx).run(shutdown_rx));
+
+        // 承诺了较高的序号，接受了较低序号之前的值，还没有选定
+        let low = SequenceNumber::new(2, 1000);
+        let high = SequenceNumber::new(3, 2000);
+        itx.unbounded_send(request(2, Request::Accept { seq: low, value: 6 }))
+            .unwrap();
+        itx.unbounded_send(request(3, Request::Prepare { seq: high }))
+            .unwrap();
+        orx.next().await.unwrap();
+        orx.next().await.unwrap();
+        shutdown.send(()).unwrap();
+        let snapshot = node.await.unwrap().snapshot();
+        assert_eq!(snapshot.last_promised, Some(high));
+        assert_eq!(snapshot.last_accepted, Some(AcceptedProposal::new(low, 6)));
+        assert_eq!(snapshot.chosen, None);
+
+        // 快照可以序列化保存，恢复出的结点状态完全相同
+        let bytes = bincode::serialize(&snapshot).unwrap();
+        let saved: NodeSnapshot = bincode::deserialize(&bytes).unwrap();
+        let (_itx, irx) = mpsc::unbounded();
+        let (otx, _orx) = mpsc::unbounded();
+        let restored = Node::restore(1, (1..4).collect(), saved, otx, irx);
+        assert_eq!(restored.snapshot(), snapshot);
+    });
+}
+
+// 把一个结点发出的下一条报文投递给 dst 中仍在 alive 里的结点
+async fn route(nodes: &mut [TestNode], from: usize, alive: &[usize]) -> Datagram {
+    let Outgoing { dst, dgram } = nodes[from - 1].rx.next().await.unwrap();
+    for id in dst.into_iter().filter(|id| alive.contains(id)) {
+        let incoming = Incoming {
+            src: from,
+            dgram: dgram.clone(),
+        };
+        nodes[id - 1].tx.unbounded_send(incoming).unwrap();
+    }
+    dgram
+}
+
+#[test]
+fn test_adopt_after_proposer_crash() {
+    let mut rt = tokio::runtime::Runtime::new().unwrap();
+    rt.block_on(async {
+        let mut nodes: Vec<TestNode> = (1..4).map(spawn_node).collect();
+
+        // #1 提出 1，prepare 与 accept 都送达 #2、#3
+        nodes[0].tx.unbounded_send(propose_request(1)).unwrap();
+        route(&mut nodes, 1, &[1, 2, 3]).await;
+        for id in 1..4 {
+            route(&mut nodes, id, &[1]).await;
+        }
+        let accept = route(&mut nodes, 1, &[2, 3]).await;
+        let first_seq = match accept {
+            Datagram::Request(Request::Accept { seq, value: 1 }) => seq,
+            dgram => panic!("unexpected {:?}", dgram),
+        };
+        // #1 在收到 accepted 之前宕机，之后不再与它收发，谁都没有学习到值
+        for id in 2..4 {
+            route(&mut nodes, id, &[]).await;
+        }
+
+        // #2 提出 2，但必须把 #1 的值推进到底
+        let alive = [2, 3];
+        nodes[1].tx.unbounded_send(propose_request(2)).unwrap();
+        route(&mut nodes, 2, &alive).await;
+        for id in 2..4 {
+            route(&mut nodes, id, &alive).await;
+        }
+        let accept = route(&mut nodes, 2, &alive).await;
+        let seq = match accept {
+            Datagram::Request(Request::Accept { seq, value }) => {
+                assert_eq!(value, 1);
+                seq
+            }
+            dgram => panic!("unexpected {:?}", dgram),
+        };
+        assert!(seq > first_seq);
+        for id in 2..4 {
+            route(&mut nodes, id, &alive).await;
+        }
+        let learn = route(&mut nodes, 2, &alive).await;
+        assert_eq!(learn, Datagram::Request(Request::Learn { seq, value: 1 }));
+    });
+}
+
+#[test]
+fn test_preempted_promise() {
+    let mut rt = tokio::runtime::Runtime::new().unwrap();
+    rt.block_on(async {
+        let mut node = spawn_node(1);
+        let seq = propose(&mut node).await;
+
+        // #2 接受过更高的提案，它的承诺被忽略，结点不会崩溃
+        let higher = SequenceNumber::new(3, u128::MAX);
+        let accepted = Some(AcceptedProposal::new(higher, 5));
+        node.tx
+            .unbounded_send(response(2, Response::Prepare { seq, accepted }))
+            .unwrap();
+        let accepted = None;
+        node.tx
+            .unbounded_send(response(3, Response::Prepare { seq, accepted }))
+            .unwrap();
+        let ignored = tokio::time::timeout(Duration::from_millis(100), node.rx.next()).await;
+        assert!(ignored.is_err());
+        assert_eq!(node.control.state(), NodeState::Preparing);
+
+        // 凑够正常的承诺后照常推进
+        let accepted = None;
+        node.tx
+            .unbounded_send(response(1, Response::Prepare { seq, accepted }))
+            .unwrap();
+        let accept = node.rx.next().await.unwrap().dgram;
+        assert_eq!(accept, Datagram::Request(Request::Accept { seq, value: 1 }));
+    });
+}
+
+#[test]
+fn test_commit_index() {
+    let mut rt = tokio::runtime::Runtime::new().unwrap();
+    rt.block_on(async {
+        let mut node = spawn_node(1);
+        assert_eq!(node.control.commit_index(), 0);
+
+        // 重复的 learn 不会让提交位置再前进
+        let seq = SequenceNumber::new(2, 1000);
+        for _ in 0..2 {
+            node.tx
+                .unbounded_send(request(2, Request::Learn { seq, value: 4 }))
+                .unwrap();
+        }
+        node.tx.unbounded_send(request(0, Request::Query)).unwrap();
+        node.rx.next().await.unwrap();
+        assert_eq!(node.control.commit_index(), 1);
+    });
+}
+
+#[test]
+fn test_redirect() {
+    let mut rt = tokio::runtime::Runtime::new().unwrap();
+    rt.block_on(async {
+        let mut node = spawn_node(1);
+        let addr = "127.0.0.1:9999".parse().unwrap();
+        node.control.set_proposer(Some(2));
+        node.control.set_proposer_addr(Some(addr));
+        node.control.set_redirect(true);
+
+        // 客户端被告知去找 #2
+        node.tx.unbounded_send(propose_request(1)).unwrap();
+        let outgoing = node.rx.next().await.unwrap();
+        assert_eq!(outgoing.dst, (0..1).collect());
+        assert_eq!(
+            outgoing.dgram,
+            Datagram::Response(Response::Redirect { leader: 2, addr })
+        );
+
+        // 其他服务器转来的提案仍然照常转交
+        let req = Request::Propose {
+            value: 1,
+            priority: 0,
+            seq: None,
+        };
+        node.tx.unbounded_send(request(3, req.clone())).unwrap();
+        let outgoing = node.rx.next().await.unwrap();
+        assert_eq!(outgoing.dst, (2..3).collect());
+        assert_eq!(outgoing.dgram, Datagram::Request(req));
+    });
+}
+
+#[test]
+fn test_relearn() {
+    let mut rt = tokio::runtime::Runtime::new().unwrap();
+    rt.block_on(async {
+        let mut nodes: Vec<TestNode> = (1..4).map(spawn_node).collect();
+        nodes[0].control.set_relearn_interval(50);
+
+        // #1 学习到值，但发给 #3 的 learn 丢了
+        let seq = SequenceNumber::new(2, 1000);
+        nodes[0]
+            .tx
+            .unbounded_send(request(2, Request::Learn { seq, value: 4 }))
+            .unwrap();
+        tokio::time::delay_for(Duration::from_millis(10)).await;
+        assert_eq!(nodes[2].control.state(), NodeState::Idle);
+
+        // 周期性重播不发给自己，#3 最终学习到值
+        let relearn = Request::Learn { seq, value: 4 };
+        let Outgoing { dst, dgram } = nodes[0].rx.next().await.unwrap();
+        assert_eq!(dst, (2..4).collect());
+        assert_eq!(dgram, Datagram::Request(relearn.clone()));
+        nodes[2].tx.unbounded_send(request(1, relearn)).unwrap();
+        tokio::time::delay_for(Duration::from_millis(10)).await;
+        assert_eq!(nodes[2].control.state(), NodeState::Decided);
+
+        // 之后仍然定期重播
+        let again = tokio::time::timeout(Duration::from_millis(200), nodes[0].rx.next()).await;
+        assert!(again.is_ok());
+    });
+}
+
+#[test]
+fn test_stall_watchdog() {
+    let mut rt = tokio::runtime::Runtime::new().unwrap();
+    rt.block_on(async {
+        let mut node = spawn_node(1);
+        node.control.set_stall_timeout(100);
+
+        // 回应全部丢失，提案停在 Preparing，超时后被放弃
+        let stalled = propose(&mut node).await;
+        assert_eq!(node.control.state(), NodeState::Preparing);
+        tokio::time::delay_for(Duration::from_millis(200)).await;
+        assert_eq!(node.control.state(), NodeState::Idle);
+        assert_eq!(node.control.stats().abandoned, 1);
+
+        // 被放弃的提案迟到的承诺不算违例，之后的提案照常推进
+        let late = Response::Prepare {
+            seq: stalled,
+            accepted: None,
+        };
+        node.tx.unbounded_send(response(2, late)).unwrap();
+        let seq = propose(&mut node).await;
+        assert!(seq > stalled);
+        assert_eq!(node.control.state(), NodeState::Preparing);
+        for src in 2..4 {
+            let promise = Response::Prepare {
+                seq,
+                accepted: None,
+            };
+            node.tx.unbounded_send(response(src, promise)).unwrap();
+        }
+        node.rx.next().await.unwrap();
+        assert_eq!(node.control.state(), NodeState::Accepting);
+        assert_eq!(node.control.stats().violations, 0);
+    });
+}
@@ -0,0 +1,20 @@
+use std::time::Duration;
+
+use paxos::prelude::*;
+
+// 只通过 prelude 使用本库
+#[test]
+fn test_prelude() {
+    let mut console = Console::new();
+    console.start_servers(3, 10090);
+    let value: ValueType = 4;
+    console.propose(1, value);
+    assert_eq!(console.query_wait(2, Duration::from_secs(2)), Some(value));
+
+    let chosen: AcceptedProposal = console.who(3).unwrap();
+    assert_eq!(chosen.seq.server_id(), 1);
+    let stats: ClusterStats = console.stats();
+    assert_eq!(stats.decided, 1);
+    assert_eq!("stats".parse(), Ok(Command::Stats));
+    console.exit()
+}
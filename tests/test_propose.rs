@@ -1,14 +1,15 @@
 use std::{thread, time::Duration};
 
-use paxos::shell::Console;
-use rand::{seq::SliceRandom, thread_rng};
+use paxos::shell::{Command, Console};
+use rand::seq::SliceRandom;
 
 #[test]
 fn test() {
-    loop {
+    for seed in 0..3 {
         let mut console = Console::new();
+        console.set_seed(seed);
         let mut vec: Vec<i32> = (1..21).collect();
-        vec.shuffle(&mut thread_rng());
+        vec.shuffle(console.rng());
         console.start_servers(20, 9527);
         for i in vec {
             console.propose(i as usize, i as u32);
@@ -20,3 +21,160 @@ fn test() {
         console.exit()
     }
 }
+
+#[test]
+fn test_start_banner() {
+    let banner = paxos::shell::start_banner(5, 9527);
+    assert!(banner.starts_with("paxos v"));
+    assert!(banner.ends_with(
+        ": started 5 acceptors on ports 9528..9532, quorum=3, transport=TCP, client #0 on port 9527."
+    ));
+}
+
+#[test]
+fn test_start_zero() {
+    let mut console = Console::new();
+    console.start_servers(0, 9760);
+
+    // 连客户端都没有启动，端口仍然空闲
+    assert!(std::net::TcpListener::bind("127.0.0.1:9760").is_ok());
+    assert_eq!(console.query(1), None);
+    console.exit()
+}
+
+#[test]
+fn test_propose_timed() {
+    // 只有加上 --wait 才等到选定，否则发出就返回
+    assert_eq!("propose 1 7".parse(), Ok(Command::Propose(1, 7, 0)));
+    assert_eq!(
+        "propose --wait 1 7 2".parse(),
+        Ok(Command::ProposeWait(1, 7, 2))
+    );
+
+    let mut console = Console::new();
+    console.start_servers(3, 9850);
+
+    let start = std::time::Instant::now();
+    let (chosen, elapsed) = console
+        .propose_timed(1, 7, 0, Duration::from_secs(2))
+        .unwrap();
+    let wall = start.elapsed();
+    assert_eq!(chosen, 7);
+    assert!(elapsed <= wall);
+
+    // 已经选定后再提案，很快就能得到同一个值
+    let (chosen, _) = console
+        .propose_timed(2, 8, 0, Duration::from_secs(2))
+        .unwrap();
+    assert_eq!(chosen, 7);
+    console.exit()
+}
+
+#[test]
+fn test_propose_redirect() {
+    assert_eq!(
+        "redirect on".parse(),
+        Ok(paxos::shell::Command::Redirect(true))
+    );
+
+    let mut console = Console::new();
+    console.start_servers(3, 9950);
+    console.set_proposer(Some(3));
+    console.set_redirect(true);
+
+    // #1 不是提案者，客户端跟随重定向改向 #3 提案
+    let (chosen, _) = console
+        .propose_timed(1, 8, 0, Duration::from_secs(2))
+        .unwrap();
+    assert_eq!(chosen, 8);
+    assert_eq!(console.who(3).unwrap().proposer(), 3);
+    console.exit();
+
+    // 不等结果的提案同样跟随重定向；提案者迁移后，重定向给出的是它的新地址
+    let mut console = Console::new();
+    console.start_servers(3, 10310);
+    console.set_proposer(Some(3));
+    console.set_redirect(true);
+    console.migrate(3, "127.0.0.1:10315".parse().unwrap());
+    console.propose(1, 9);
+    assert_eq!(console.query_wait(3, Duration::from_secs(2)), Some(9));
+    assert_eq!(console.who(3).unwrap().proposer(), 3);
+    console.exit()
+}
+
+#[test]
+fn test_log_dir() {
+    let dir = std::env::temp_dir().join(format!("paxos-log-{}", std::process::id()));
+    let mut console = Console::new();
+    console.start_servers(2, 10050);
+    console.set_log_dir(&dir).unwrap();
+    console.propose(1, 7);
+    assert_eq!(console.query_wait(2, Duration::from_secs(2)), Some(7));
+    assert!(console.await_quiescent(Duration::from_secs(2)));
+    console.exit();
+
+    // 每个文件只有自己的日志
+    for (id, other) in [(1, 2), (2, 1)] {
+        let log = std::fs::read_to_string(dir.join(format!("node-{}.log", id))).unwrap();
+        assert!(log.contains(&format!("Server #{} handle", id)), "{}", log);
+        assert!(!log.contains(&format!("Server #{} ", other)), "{}", log);
+    }
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn test_force_propose() {
+    assert_eq!(
+        "forcepropose 5 9".parse(),
+        Ok(paxos::shell::Command::ForcePropose(5, 9))
+    );
+
+    let mut console = Console::new();
+    console.start_servers(5, 10080);
+    console.force_propose(5, 9);
+    assert!(console.wait_for_value(9, Duration::from_secs(2)));
+    assert!(console.await_quiescent(Duration::from_secs(2)));
+
+    // #5 自己走完了整轮，提案没有经过客户端
+    for id in 1..6 {
+        assert_eq!(console.who(id).map(|p| (p.proposer(), p.val)), Some((5, 9)));
+    }
+    let stats = console.stats();
+    assert_eq!((stats.proposals, stats.rounds, stats.decided), (1, 1, 1));
+    assert_eq!(stats.messages["prepare"], 5);
+    assert_eq!(stats.messages["accepted"], 5);
+    console.exit()
+}
+
+#[test]
+fn test_dedicated_runtimes() {
+    let mut console = Console::new();
+    console.set_dedicated_runtimes(true);
+    console.start_servers(5, 10110);
+    for id in 1..6 {
+        console.propose(id, id as u32);
+    }
+    assert!(console.await_quiescent(Duration::from_secs(5)));
+    let chosen = console.who(1).map(|p| p.val).unwrap();
+    assert!(console.wait_for_value(chosen, Duration::from_secs(2)));
+
+    // 迁移时等待的是独立线程交回的结点，状态照样带过去
+    console.migrate(3, "127.0.0.1:10120".parse().unwrap());
+    assert_eq!(console.who(3).map(|p| p.val), Some(chosen));
+    console.exit()
+}
+
+#[test]
+fn test_propose_read() {
+    let mut console = Console::new();
+    console.start_servers(3, 10180);
+    // 提案经 #1 重定向到提案者 #2 选定，读回时 #1 也必须已经学习到
+    console.set_proposer(Some(2));
+    console.set_redirect(true);
+    assert_eq!(console.propose_read(1, 5), Some(5));
+    assert_eq!(console.query(1), Some(5));
+
+    // 落选的提案读回的是胜出的值
+    assert_eq!(console.propose_read(3, 6), Some(5));
+    console.exit()
+}
@@ -0,0 +1,683 @@
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use futures::channel::mpsc;
+
+use paxos::net_proxy::{
+    AddrTable, ConnState, Proxy, ProxyError, SocketOptions, FRAME_HEADER_LEN, MAX_FRAME_LEN,
+};
+use paxos::paxos::node::Node;
+use paxos::paxos::proposal::{
+    self, Datagram, Outgoing, Request, Response, DECODE_LIMIT, PROTOCOL_VERSION,
+};
+use paxos::paxos::seq_num::SequenceNumber;
+use paxos::shutdown::Shutdown;
+use tokio::io::BufReader;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::prelude::*;
+use tokio::stream::StreamExt;
+
+// 地址表中只有 #0 和 #1
+fn proxy(port: u16) -> Arc<Proxy> {
+    let addr: SocketAddr = format!("127.0.0.1:{}", port).parse().unwrap();
+    let table: HashMap<usize, SocketAddr> = (0..2).map(|id| (id, addr)).collect();
+    Proxy::new(1, Arc::new(RwLock::new(table)))
+}
+
+fn seq() -> SequenceNumber {
+    SequenceNumber::new(1, 1000)
+}
+
+fn frame(version: u8, src: u64, len: u64, data: &[u8]) -> Vec<u8> {
+    let mut buf = vec![version];
+    buf.extend_from_slice(&0u64.to_be_bytes());
+    buf.extend_from_slice(&src.to_be_bytes());
+    buf.extend_from_slice(&len.to_be_bytes());
+    buf.extend_from_slice(data);
+    buf
+}
+
+// 把原始字节发给代理，返回它的解析结果
+fn read_raw(port: u16, bytes: Vec<u8>) -> Result<(usize, Datagram), ProxyError> {
+    let mut rt = tokio::runtime::Runtime::new().unwrap();
+    rt.block_on(async move {
+        let proxy = proxy(port);
+        let mut listener = TcpListener::bind(("127.0.0.1", port)).await.unwrap();
+        let mut stream = TcpStream::connect(("127.0.0.1", port)).await.unwrap();
+        stream.write_all(&bytes).await.unwrap();
+        drop(stream);
+        let (mut socket, _) = listener.accept().await.unwrap();
+        proxy.read_incoming(&mut socket).await
+    })
+}
+
+#[test]
+fn test_read_valid() {
+    let dgram = Datagram::Request(Request::Learn {
+        seq: seq(),
+        value: 3,
+    });
+    let (src, dgram) = read_raw(9640, dgram.encode_with_src(0).to_vec()).unwrap();
+    assert_eq!(src, 0);
+    assert!(matches!(
+        dgram,
+        Datagram::Request(Request::Learn { value: 3, .. })
+    ));
+}
+
+#[test]
+fn test_coalesced_frames() {
+    let mut rt = tokio::runtime::Runtime::new().unwrap();
+    rt.block_on(async {
+        let proxy = proxy(9647);
+        let mut listener = TcpListener::bind(("127.0.0.1", 9647)).await.unwrap();
+        let mut stream = TcpStream::connect(("127.0.0.1", 9647)).await.unwrap();
+
+        // 两个报文在一次 write_all 中发出
+        let mut bytes = Datagram::Request(Request::Learn {
+            seq: seq(),
+            value: 1,
+        })
+        .encode_with_src(0)
+        .to_vec();
+        bytes.extend_from_slice(&Datagram::Request(Request::Query).encode_with_src(1));
+        stream.write_all(&bytes).await.unwrap();
+
+        let (socket, _) = listener.accept().await.unwrap();
+        let mut reader = BufReader::new(socket);
+        let first = proxy.read_incoming(&mut reader).await.unwrap();
+        let second = proxy.read_incoming(&mut reader).await.unwrap();
+        assert_eq!(
+            first,
+            (
+                0,
+                Datagram::Request(Request::Learn {
+                    seq: seq(),
+                    value: 1
+                })
+            )
+        );
+        assert_eq!(second, (1, Datagram::Request(Request::Query)));
+    });
+}
+
+#[test]
+fn test_version_mismatch() {
+    let result = read_raw(9641, frame(PROTOCOL_VERSION + 1, 0, 0, &[]));
+    assert!(matches!(result, Err(ProxyError::VersionMismatch(v)) if v == PROTOCOL_VERSION + 1));
+}
+
+#[test]
+fn test_unknown_peer() {
+    let result = read_raw(9642, frame(PROTOCOL_VERSION, 42, 0, &[]));
+    assert!(matches!(result, Err(ProxyError::UnknownPeer(42))));
+}
+
+#[test]
+fn test_frame_too_large() {
+    let len = MAX_FRAME_LEN as u64 + 1;
+    let result = read_raw(9643, frame(PROTOCOL_VERSION, 0, len, &[]));
+    assert!(matches!(result, Err(ProxyError::FrameTooLarge(l)) if l == MAX_FRAME_LEN + 1));
+}
+
+#[test]
+fn test_decode_failed() {
+    let result = read_raw(9644, frame(PROTOCOL_VERSION, 0, 4, &[0xff; 4]));
+    assert!(matches!(result, Err(ProxyError::Decode(_))));
+}
+
+#[test]
+fn test_truncated_frame() {
+    // 数据部分读到一半就关闭，与报文头截断一样是协议错误
+    let result = read_raw(9645, frame(PROTOCOL_VERSION, 0, 8, &[0; 4]));
+    assert!(matches!(result, Err(ProxyError::TruncatedPayload(4, 8))));
+    assert_eq!(
+        result.unwrap_err().to_string(),
+        "connection closed after 4 of 8 payload bytes"
+    );
+}
+
+#[test]
+fn test_send_unknown_peer() {
+    let mut rt = tokio::runtime::Runtime::new().unwrap();
+    let result = rt.block_on(proxy(9646).send(42, &Datagram::Request(Request::Query)));
+    assert!(matches!(result, Err(ProxyError::UnknownPeer(42))));
+}
+
+#[test]
+fn test_wrong_group() {
+    let dgram = Datagram::Request(Request::Query);
+    let result = read_raw(9648, dgram.encode(1, 0).to_vec());
+    assert!(matches!(result, Err(ProxyError::WrongGroup(1))));
+}
+
+#[test]
+fn test_batch_window() {
+    let mut rt = tokio::runtime::Runtime::new().unwrap();
+    rt.block_on(async {
+        let table: HashMap<usize, SocketAddr> = vec![
+            (0, "127.0.0.1:9781".parse().unwrap()),
+            (1, "127.0.0.1:9780".parse().unwrap()),
+        ]
+        .into_iter()
+        .collect();
+        let table = Arc::new(RwLock::new(table));
+        let sender = Proxy::new(1, table.clone());
+        let receiver = Proxy::new(0, table);
+        let mut listener = receiver.bind().await.unwrap();
+        sender.set_batch_window(50);
+
+        let (itx, _irx) = mpsc::unbounded();
+        let (otx, orx) = mpsc::unbounded();
+        let (_shutdown, shutdown_rx) = Shutdown::new();
+        let sender_listener = sender.bind().await.unwrap();
+        tokio::spawn(sender.clone().run(sender_listener, itx, orx, shutdown_rx));
+
+        // 窗口内陆续发出的报文合并成一次写
+        let learn = |value| Outgoing {
+            dst: std::iter::once(0).collect(),
+            dgram: Datagram::Request(Request::Learn { seq: seq(), value }),
+        };
+        otx.unbounded_send(learn(0)).unwrap();
+        tokio::time::delay_for(Duration::from_millis(10)).await;
+        // 还在攒批次的报文也算作排队中
+        assert_eq!(sender.queued(), 1);
+        for value in 1..5 {
+            otx.unbounded_send(learn(value)).unwrap();
+        }
+
+        let (socket, _) = listener.accept().await.unwrap();
+        let mut socket = BufReader::new(socket);
+        for value in 0..5 {
+            let (_, dgram) = receiver.read_incoming(&mut socket).await.unwrap();
+            assert!(matches!(
+                dgram,
+                Datagram::Request(Request::Learn { value: v, .. }) if v == value
+            ));
+        }
+        assert!(receiver.read_incoming(&mut socket).await.is_err());
+        let second = tokio::time::timeout(Duration::from_millis(200), listener.accept()).await;
+        assert!(second.is_err(), "batch was split across connections");
+        assert_eq!(sender.queued(), 0);
+    });
+}
+
+#[test]
+fn test_read_deadline() {
+    let mut rt = tokio::runtime::Runtime::new().unwrap();
+    rt.block_on(async {
+        let proxy = proxy(9782);
+        proxy.set_read_deadline(100);
+        let listener = proxy.bind().await.unwrap();
+        let (itx, _irx) = mpsc::unbounded();
+        let (_otx, orx) = mpsc::unbounded();
+        let (_shutdown, shutdown_rx) = Shutdown::new();
+        tokio::spawn(proxy.run(listener, itx, orx, shutdown_rx));
+
+        // 只发报文头，然后保持连接不动
+        let mut stream = TcpStream::connect(("127.0.0.1", 9782)).await.unwrap();
+        stream
+            .write_all(&frame(PROTOCOL_VERSION, 0, 8, &[]))
+            .await
+            .unwrap();
+        let mut buf = [0u8; 1];
+        let read = tokio::time::timeout(Duration::from_secs(1), stream.read(&mut buf)).await;
+        assert!(
+            matches!(read, Ok(Ok(0))),
+            "stalled connection wasn't dropped"
+        );
+    });
+}
+
+#[test]
+fn test_self_delivery() {
+    let mut rt = tokio::runtime::Runtime::new().unwrap();
+    rt.block_on(async {
+        // 地址表里 #1 的地址上没有人监听，走 TCP 必然失败
+        let table: HashMap<usize, SocketAddr> = vec![
+            (0, "127.0.0.1:9801".parse().unwrap()),
+            (1, "127.0.0.1:9801".parse().unwrap()),
+        ]
+        .into_iter()
+        .collect();
+        let proxy = Proxy::new(1, Arc::new(RwLock::new(table)));
+        let listener = TcpListener::bind("127.0.0.1:9800").await.unwrap();
+        let (itx, mut irx) = mpsc::unbounded();
+        let (otx, orx) = mpsc::unbounded();
+        let (_shutdown, shutdown_rx) = Shutdown::new();
+        tokio::spawn(proxy.run(listener, itx, orx, shutdown_rx));
+
+        let dgram = Datagram::Request(Request::Query);
+        otx.unbounded_send(Outgoing {
+            dst: std::iter::once(1).collect(),
+            dgram: dgram.clone(),
+        })
+        .unwrap();
+        let incoming = tokio::time::timeout(Duration::from_secs(1), irx.next())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!((incoming.src, incoming.dgram), (1, dgram));
+    });
+}
+
+#[test]
+fn test_decode_limit() {
+    // 数据部分超过解码上限时按上限拒绝，即使开头是一个合法的报文
+    let dgram = Datagram::Request(Request::Ping { nonce: 7 });
+    let mut data = dgram.encode_with_src(0)[FRAME_HEADER_LEN..].to_vec();
+    assert_eq!(proposal::decode(&data).unwrap(), dgram);
+
+    // 合法报文后面多出一个字节也不接受
+    data.push(0);
+    assert!(proposal::decode(&data).is_err());
+
+    data.resize(DECODE_LIMIT as usize + 1, 0);
+    let result = proposal::decode(&data);
+    assert!(matches!(
+        result.map_err(|e| *e),
+        Err(bincode::ErrorKind::SizeLimit)
+    ));
+}
+
+#[test]
+fn test_bind_addr() {
+    let mut rt = tokio::runtime::Runtime::new().unwrap();
+    rt.block_on(async {
+        // #1 监听所有网卡，但在地址表中公布回环地址
+        let table: AddrTable = Arc::new(RwLock::new(
+            vec![
+                (0, "127.0.0.1:9890".parse().unwrap()),
+                (1, "127.0.0.1:9891".parse().unwrap()),
+            ]
+            .into_iter()
+            .collect(),
+        ));
+        let bind_addr = "0.0.0.0:9891".parse().unwrap();
+        let server = Proxy::with_bind_addr(0, 1, table.clone(), Some(bind_addr));
+        let listener = server.bind().await.unwrap();
+        assert_eq!(listener.local_addr().unwrap(), bind_addr);
+        let (itx, irx) = mpsc::unbounded();
+        let (otx, orx) = mpsc::unbounded();
+        let (_server_shutdown, shutdown_rx) = Shutdown::new();
+        tokio::spawn(server.run(listener, itx, orx, shutdown_rx.clone()));
+        tokio::spawn(Node::new(1, (1..2).collect(), otx, irx).run(shutdown_rx));
+
+        let client = Proxy::new(0, table);
+        let listener = client.bind().await.unwrap();
+        let (itx, mut inbox) = mpsc::unbounded();
+        let (_otx, orx) = mpsc::unbounded();
+        let (_client_shutdown, shutdown_rx) = Shutdown::new();
+        tokio::spawn(client.clone().run(listener, itx, orx, shutdown_rx));
+
+        // 请求发往公布的地址，回应按公布的地址送回客户端
+        client
+            .send(1, &Datagram::Request(Request::Query))
+            .await
+            .unwrap();
+        let incoming = tokio::time::timeout(Duration::from_secs(1), inbox.next())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(incoming.src, 1);
+        assert_eq!(
+            incoming.dgram,
+            Datagram::Response(Response::Query { val: None })
+        );
+    });
+}
+
+#[test]
+fn test_slow_destination() {
+    let mut rt = tokio::runtime::Runtime::new().unwrap();
+    rt.block_on(async {
+        // #2 的监听队列已经塞满又从不 accept，连它的连接迟迟建立不起来
+        let slow = std::net::TcpListener::bind("127.0.0.1:9931").unwrap();
+        let _backlog: Vec<_> = (0..200)
+            .map_while(|_| {
+                let addr = slow.local_addr().unwrap();
+                std::net::TcpStream::connect_timeout(&addr, Duration::from_millis(100)).ok()
+            })
+            .collect();
+
+        let table: HashMap<usize, SocketAddr> = vec![
+            (0, "127.0.0.1:9930".parse().unwrap()),
+            (1, "127.0.0.1:9932".parse().unwrap()),
+            (2, "127.0.0.1:9931".parse().unwrap()),
+        ]
+        .into_iter()
+        .collect();
+        let sender = Proxy::new(1, Arc::new(RwLock::new(table)));
+        sender.set_send_concurrency(1);
+        let listener = sender.bind().await.unwrap();
+        let (itx, _irx) = mpsc::unbounded();
+        let (otx, orx) = mpsc::unbounded();
+        let (_shutdown, shutdown_rx) = Shutdown::new();
+        tokio::spawn(sender.run(listener, itx, orx, shutdown_rx));
+
+        // 先给慢结点排上几批，再发给正常的 #0
+        let mut healthy = TcpListener::bind("127.0.0.1:9930").await.unwrap();
+        for value in 0..3 {
+            otx.unbounded_send(Outgoing {
+                dst: std::iter::once(2).collect(),
+                dgram: Datagram::Request(Request::Learn { seq: seq(), value }),
+            })
+            .unwrap();
+            tokio::time::delay_for(Duration::from_millis(10)).await;
+        }
+        otx.unbounded_send(Outgoing {
+            dst: std::iter::once(0).collect(),
+            dgram: Datagram::Request(Request::Query),
+        })
+        .unwrap();
+        let (socket, _) = tokio::time::timeout(Duration::from_millis(200), healthy.accept())
+            .await
+            .unwrap()
+            .unwrap();
+        let peer = proxy(9930);
+        let (src, dgram) = peer
+            .read_incoming(&mut BufReader::new(socket))
+            .await
+            .unwrap();
+        assert_eq!((src, dgram), (1, Datagram::Request(Request::Query)));
+    });
+}
+
+#[test]
+fn test_max_inflows() {
+    let mut rt = tokio::runtime::Runtime::new().unwrap();
+    rt.block_on(async {
+        let proxy = proxy(9960);
+        proxy.set_max_inflows(2);
+        let listener = proxy.bind().await.unwrap();
+        let (itx, mut irx) = mpsc::unbounded();
+        let (_otx, orx) = mpsc::unbounded();
+        let (_shutdown, shutdown_rx) = Shutdown::new();
+        tokio::spawn(proxy.clone().run(listener, itx, orx, shutdown_rx));
+
+        // 两个连接占满上限，第三个连接被关闭
+        let mut first = TcpStream::connect("127.0.0.1:9960").await.unwrap();
+        let _second = TcpStream::connect("127.0.0.1:9960").await.unwrap();
+        tokio::time::delay_for(Duration::from_millis(50)).await;
+        let mut excess = TcpStream::connect("127.0.0.1:9960").await.unwrap();
+        let mut buf = [0u8; 1];
+        let read = tokio::time::timeout(Duration::from_secs(1), excess.read(&mut buf))
+            .await
+            .unwrap();
+        assert!(matches!(read, Ok(0) | Err(_)));
+        assert_eq!(proxy.rejected(), 1);
+
+        // 已有的连接照常收发
+        let dgram = Datagram::Request(Request::Query);
+        first.write_all(&dgram.encode_with_src(0)).await.unwrap();
+        let incoming = tokio::time::timeout(Duration::from_secs(1), irx.next())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!((incoming.src, incoming.dgram), (0, dgram));
+    });
+}
+
+#[test]
+fn test_retry_after_reset() {
+    let mut rt = tokio::runtime::Runtime::new().unwrap();
+    rt.block_on(async {
+        // #0 是假的对端，#1 是发送方
+        let table: HashMap<usize, SocketAddr> = vec![
+            (0, "127.0.0.1:10020".parse().unwrap()),
+            (1, "127.0.0.1:10021".parse().unwrap()),
+        ]
+        .into_iter()
+        .collect();
+        let sender = Proxy::new(1, Arc::new(RwLock::new(table)));
+        let mut peer = TcpListener::bind("127.0.0.1:10020").await.unwrap();
+        let listener = sender.bind().await.unwrap();
+        let (itx, _irx) = mpsc::unbounded();
+        let (otx, orx) = mpsc::unbounded();
+        let (_shutdown, shutdown_rx) = Shutdown::new();
+
+        // 先排好队再启动，所有报文合成一批发出。这一批大到写入时必然填满缓冲区，
+        // 对端中途重置连接时写入一定会出错
+        let mut len = 0;
+        for nonce in 0..400_000 {
+            let dgram = Datagram::Request(Request::Ping { nonce });
+            len += dgram.encode_with_src(1).len();
+            otx.unbounded_send(Outgoing {
+                dst: std::iter::once(0).collect(),
+                dgram,
+            })
+            .unwrap();
+        }
+        tokio::spawn(sender.clone().run(listener, itx, orx, shutdown_rx));
+
+        // 第一个连接不读就关闭，未读的数据让内核发出 RST
+        let (reset, _) = peer.accept().await.unwrap();
+        tokio::time::delay_for(Duration::from_millis(100)).await;
+        drop(reset);
+
+        // 重试的连接收到完整的报文
+        let (mut retried, _) = peer.accept().await.unwrap();
+        let mut received = Vec::new();
+        retried.read_to_end(&mut received).await.unwrap();
+        assert_eq!(received.len(), len);
+        assert!(sender.retries() >= 1);
+    });
+}
+
+#[test]
+fn test_active_inflows() {
+    let mut rt = tokio::runtime::Runtime::new().unwrap();
+    rt.block_on(async {
+        let proxy = proxy(10070);
+        proxy.set_log_connections(true);
+        let listener = proxy.bind().await.unwrap();
+        let (itx, mut irx) = mpsc::unbounded();
+        let (_otx, orx) = mpsc::unbounded();
+        let (_shutdown, shutdown_rx) = Shutdown::new();
+        tokio::spawn(proxy.clone().run(listener, itx, orx, shutdown_rx));
+        let settle = || tokio::time::delay_for(Duration::from_millis(50));
+        assert_eq!(proxy.active_inflows(), 0);
+
+        let mut first = TcpStream::connect("127.0.0.1:10070").await.unwrap();
+        let second = TcpStream::connect("127.0.0.1:10070").await.unwrap();
+        settle().await;
+        assert_eq!(proxy.active_inflows(), 2);
+
+        // 发完报文后正常关闭
+        let dgram = Datagram::Request(Request::Query);
+        first.write_all(&dgram.encode_with_src(0)).await.unwrap();
+        drop(first);
+        assert_eq!(irx.next().await.unwrap().dgram, dgram);
+        settle().await;
+        assert_eq!(proxy.active_inflows(), 1);
+
+        // 什么都没发就关闭的连接同样计入
+        drop(second);
+        settle().await;
+        assert_eq!(proxy.active_inflows(), 0);
+    });
+}
+
+#[test]
+fn test_socket_options() {
+    let mut rt = tokio::runtime::Runtime::new().unwrap();
+    rt.block_on(async {
+        let addr: SocketAddr = "127.0.0.1:10150".parse().unwrap();
+        let table: AddrTable = Arc::new(RwLock::new(std::iter::once((1, addr)).collect()));
+        let options = SocketOptions {
+            backlog: 16,
+            reuse_address: true,
+            nodelay: true,
+        };
+        let proxy = Proxy::with_socket_options(0, 1, table, None, options);
+        assert_eq!(proxy.socket_options(), options);
+
+        // 发出的与接受的连接都关掉了 Nagle
+        let mut listener = proxy.bind().await.unwrap();
+        let stream = proxy.connect(addr).await.unwrap();
+        assert!(stream.nodelay().unwrap());
+        let (socket, _) = listener.accept().await.unwrap();
+        assert!(!socket.nodelay().unwrap());
+        proxy.configure(&socket).unwrap();
+        assert!(socket.nodelay().unwrap());
+
+        // 由监听方先关闭，端口上留下 TIME_WAIT 的连接，仍然可以立即重新监听
+        drop(socket);
+        drop(listener);
+        drop(stream);
+        let listener = proxy.bind().await.unwrap();
+        assert_eq!(listener.local_addr().unwrap(), addr);
+
+        // 不设 SO_REUSEADDR 时同样的情形下无法重新监听。端口由系统分配，
+        // 免得上一次运行留下的 TIME_WAIT 让第一次监听就失败
+        let options = SocketOptions {
+            reuse_address: false,
+            ..options
+        };
+        let table: AddrTable = Arc::default();
+        let any = "127.0.0.1:0".parse().unwrap();
+        let proxy = Proxy::with_socket_options(0, 1, table.clone(), Some(any), options);
+        let mut listener = proxy.bind().await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let stream = proxy.connect(addr).await.unwrap();
+        let (socket, _) = listener.accept().await.unwrap();
+        drop(socket);
+        drop(listener);
+        drop(stream);
+        tokio::time::delay_for(Duration::from_millis(50)).await;
+        let proxy = Proxy::with_socket_options(0, 1, table, Some(addr), options);
+        assert!(proxy.bind().await.is_err());
+    });
+}
+
+#[test]
+fn test_truncated_header() {
+    // 报文头读到一半就关闭是协议错误，一个字节都没有则是正常关闭
+    let mut partial = frame(PROTOCOL_VERSION, 0, 0, &[]);
+    partial.truncate(10);
+    let result = read_raw(10220, partial.clone());
+    assert!(matches!(result, Err(ProxyError::TruncatedHeader(10))));
+    assert_eq!(
+        result.unwrap_err().to_string(),
+        "connection closed after 10 of 25 header bytes"
+    );
+    let result = read_raw(10221, Vec::new());
+    assert!(
+        matches!(result, Err(ProxyError::Io(e)) if e.kind() == std::io::ErrorKind::UnexpectedEof)
+    );
+
+    // 运行中的代理记下截断的连接，正常关闭的不算
+    let mut rt = tokio::runtime::Runtime::new().unwrap();
+    rt.block_on(async {
+        let proxy = proxy(10210);
+        let listener = proxy.bind().await.unwrap();
+        let (itx, _irx) = mpsc::unbounded();
+        let (_otx, orx) = mpsc::unbounded();
+        let (_shutdown, shutdown_rx) = Shutdown::new();
+        tokio::spawn(proxy.clone().run(listener, itx, orx, shutdown_rx));
+
+        let mut truncated = TcpStream::connect("127.0.0.1:10210").await.unwrap();
+        truncated.write_all(&partial).await.unwrap();
+        drop(truncated);
+        drop(TcpStream::connect("127.0.0.1:10210").await.unwrap());
+        tokio::time::delay_for(Duration::from_millis(50)).await;
+        assert_eq!(proxy.protocol_errors(), 1);
+
+        // 完整的报文头加上一半数据同样记下
+        let body = Datagram::Request(Request::Query).encode(0, 0);
+        let mut half = body.to_vec();
+        half.truncate(FRAME_HEADER_LEN + (body.len() - FRAME_HEADER_LEN) / 2);
+        let mut truncated = TcpStream::connect("127.0.0.1:10210").await.unwrap();
+        truncated.write_all(&half).await.unwrap();
+        drop(truncated);
+        tokio::time::delay_for(Duration::from_millis(50)).await;
+        assert_eq!(proxy.protocol_errors(), 2);
+    });
+}
+
+#[test]
+fn test_proxy_log() {
+    let path = std::env::temp_dir().join(format!("paxos-proxy-log-{}", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let mut rt = tokio::runtime::Runtime::new().unwrap();
+    rt.block_on(async {
+        let proxy = proxy(10300);
+        proxy.set_log_file(&path).unwrap();
+        proxy.set_log_connections(true);
+        let listener = proxy.bind().await.unwrap();
+        let (itx, _irx) = mpsc::unbounded();
+        let (_otx, orx) = mpsc::unbounded();
+        let (_shutdown, shutdown_rx) = Shutdown::new();
+        tokio::spawn(proxy.clone().run(listener, itx, orx, shutdown_rx));
+        let send = |bytes: Vec<u8>| async move {
+            let mut stream = TcpStream::connect("127.0.0.1:10300").await.unwrap();
+            stream.write_all(&bytes).await.unwrap();
+            drop(stream);
+            tokio::time::delay_for(Duration::from_millis(50)).await;
+        };
+
+        // 出错的连接与连接的开关都写到设定的去处，而不是标准输出
+        send(frame(PROTOCOL_VERSION + 1, 0, 0, &[])).await;
+        let log = std::fs::read_to_string(&path).unwrap();
+        assert!(
+            log.contains("error: proxy #1 dropped connection from unknown peer"),
+            "{}",
+            log
+        );
+        assert!(log.contains("Proxy #1 closed connection from unknown peer"));
+
+        // 安静模式下什么都不写
+        proxy.set_quiet(true);
+        send(frame(PROTOCOL_VERSION + 1, 0, 0, &[])).await;
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), log);
+    });
+    let _ = std::fs::remove_file(&path);
+}
+
+#[test]
+fn test_conn_directions() {
+    let mut rt = tokio::runtime::Runtime::new().unwrap();
+    rt.block_on(async {
+        let addr: SocketAddr = "127.0.0.1:10330".parse().unwrap();
+        let table: AddrTable = Arc::new(RwLock::new(
+            vec![(0, "127.0.0.1:10331".parse().unwrap()), (1, addr)]
+                .into_iter()
+                .collect(),
+        ));
+        let sender = Proxy::new(0, table.clone());
+        let receiver = Proxy::new(1, table);
+        let listener = receiver.bind().await.unwrap();
+        let (itx, mut irx) = mpsc::unbounded();
+        let (_otx, orx) = mpsc::unbounded();
+        let (_shutdown, shutdown_rx) = Shutdown::new();
+        tokio::spawn(receiver.clone().run(listener, itx, orx, shutdown_rx));
+
+        // 按重定向给的地址发送，表里没有 #2 也记在 #2 名下，只有发送方向
+        let ping = Datagram::Request(Request::Ping { nonce: 1 });
+        sender.send_to(2, addr, &ping).await.unwrap();
+        let conn = sender.conns()[&2];
+        assert_eq!(conn.outbound, Some(ConnState::Connected));
+        assert_eq!(conn.inbound, None);
+        assert!(conn.bytes_sent > 0);
+
+        // 接收方只有接收方向
+        irx.next().await.unwrap();
+        let conn = receiver.conns()[&0];
+        assert_eq!(conn.inbound, Some(ConnState::Connected));
+        assert_eq!(conn.outbound, None);
+
+        // 同一连接上先来一个好报文再来一个坏报文，接收方向记为失败，发送方向不受影响
+        let mut bytes = ping.encode_with_src(0).to_vec();
+        bytes.extend_from_slice(&frame(PROTOCOL_VERSION + 1, 0, 0, &[]));
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream.write_all(&bytes).await.unwrap();
+        irx.next().await.unwrap();
+        let mut buf = [0u8; 1];
+        let _ = tokio::time::timeout(Duration::from_secs(1), stream.read(&mut buf)).await;
+        let conn = receiver.conns()[&0];
+        assert_eq!(conn.inbound, Some(ConnState::Failed));
+        assert_eq!(conn.outbound, None);
+    });
+}
@@ -0,0 +1,384 @@
+use std::io::Write;
+use std::net::TcpStream;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use paxos::net_proxy::ConnState;
+use paxos::paxos::node::READ_TIMEOUT;
+use paxos::paxos::proposal::{Datagram, Request};
+use paxos::paxos::seq_num::SequenceNumber;
+use paxos::shell::{ClusterDump, Command, Console};
+
+// 以客户端 #0 的身份直接发送请求
+fn send(port: u16, req: Request) {
+    let mut stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+    stream
+        .write_all(&Datagram::Request(req).encode_with_src(0))
+        .unwrap();
+}
+
+#[test]
+fn test_query_wait() {
+    let mut console = Console::new();
+    console.start_servers(3, 9620);
+    console.propose(1, 7);
+    // 不需要 sleep，节点学习到值后才会回应
+    for i in 1..4 {
+        assert_eq!(console.query_wait(i, Duration::from_secs(5)), Some(7));
+    }
+    console.exit()
+}
+
+#[test]
+fn test_probe() {
+    let mut console = Console::new();
+    console.start_servers(3, 9650);
+
+    // 手动发起一轮进行到一半的提案：全体 prepare，只有 #1 accept
+    let seq = SequenceNumber::new(3, 1000);
+    for port in 9651..9654 {
+        send(port, Request::Prepare { seq });
+    }
+    send(9651, Request::Accept { seq, value: 9 });
+    thread::sleep(Duration::from_millis(100));
+
+    let report = console.probe();
+    assert!(report.states.len() >= 2);
+    assert_eq!(report.highest_promised(), Some(seq));
+    if report.states.contains_key(&1) {
+        let accepted = report.highest_accepted().unwrap();
+        assert_eq!((accepted.seq, accepted.val), (seq, 9));
+    }
+    // probe 不做承诺，也不会影响结果
+    assert_eq!(console.probe().highest_promised(), Some(seq));
+
+    // 单独探查一台：只有 #1 接受过值
+    assert_eq!("probe 2".parse(), Ok(Command::ProbeServer(2)));
+    let state = console.probe_server(1).unwrap();
+    assert_eq!(state.last_promised, Some(seq));
+    assert_eq!(state.last_accepted.map(|p| (p.seq, p.val)), Some((seq, 9)));
+    let state = console.probe_server(2).unwrap();
+    assert_eq!(
+        (state.last_promised, state.last_accepted),
+        (Some(seq), None)
+    );
+    assert_eq!(console.probe_server(9), None);
+    console.exit()
+}
+
+#[test]
+fn test_can_still_choose() {
+    let mut console = Console::new();
+    console.start_servers(3, 10060);
+
+    // 还没有人接受过值，任何值都有机会
+    let report = console.probe();
+    assert_eq!(report.acceptors, 3);
+    assert!(report.can_still_choose(5));
+
+    // 多数派在同一个提案中接受了 9，之后只有 9 能被选定
+    let seq = SequenceNumber::new(3, 1000);
+    for port in 10061..10064 {
+        send(port, Request::Prepare { seq });
+        send(port, Request::Accept { seq, value: 9 });
+    }
+    thread::sleep(Duration::from_millis(100));
+
+    let report = console.probe();
+    assert_eq!(report.locked_in().map(|p| (p.seq, p.val)), Some((seq, 9)));
+    assert!(!report.can_still_choose(5));
+    assert!(report.can_still_choose(9));
+    console.exit()
+}
+
+#[test]
+fn test_slow_acceptor() {
+    let mut console = Console::new();
+    console.start_servers(3, 9670);
+    console.slow(3, 5000);
+
+    // #1 #2 组成的多数派足以作出决定，不需要等 #3
+    console.propose(1, 21);
+    for i in 1..3 {
+        assert_eq!(console.query_wait(i, Duration::from_secs(2)), Some(21));
+    }
+    console.exit()
+}
+
+#[test]
+fn test_priority() {
+    // 低优先级的提案来自序号更大的结点，且发出得更晚，不加优先级时通常是它胜出
+    let mut wins = 0;
+    for trial in 0..10 {
+        let mut console = Console::new();
+        console.start_servers(3, 9680 + trial * 4);
+        console.propose_with_priority(1, 100, 9);
+        console.propose_with_priority(3, 300, 0);
+        if console.query_wait(2, Duration::from_secs(2)) == Some(100) {
+            wins += 1;
+        }
+        console.exit()
+    }
+    assert!(wins >= 8, "high priority won only {} of 10", wins);
+}
+
+#[test]
+fn test_who() {
+    let mut console = Console::new();
+    console.start_servers(3, 9750);
+    console.propose(2, 42);
+    assert_eq!(console.query_wait(1, Duration::from_secs(5)), Some(42));
+
+    // 选定的提案记录了提出它的服务器
+    for i in 1..4 {
+        let chosen = console.who(i).unwrap();
+        assert_eq!(chosen.val, 42);
+        assert_eq!(chosen.proposer(), 2);
+    }
+    console.exit()
+}
+
+#[test]
+fn test_pause() {
+    let mut console = Console::new();
+    console.start_servers(3, 9830);
+    console.pause(3, true);
+    console.propose(1, 4);
+    assert_eq!(console.query_wait(1, Duration::from_secs(5)), Some(4));
+
+    // 暂停的结点不回应，恢复后处理排队的消息
+    assert_eq!(console.query_wait(3, Duration::from_millis(300)), None);
+    console.pause(3, false);
+    assert_eq!(console.query_wait(3, Duration::from_secs(5)), Some(4));
+
+    // 空闲的结点正等着下一条消息时被暂停，到达的第一条消息也留到恢复后处理
+    assert!(console.await_quiescent(Duration::from_secs(2)));
+    console.pause(2, true);
+    assert_eq!(console.query(2), None);
+    console.pause(2, false);
+    assert_eq!(console.query(2), Some(4));
+    console.exit()
+}
+
+#[test]
+fn test_await_quiescent() {
+    let mut console = Console::new();
+    console.start_servers(3, 9840);
+    for i in 1..4 {
+        console.propose(i, 3);
+    }
+
+    // 安静之后不必再等，直接查询就是稳定的结果
+    assert!(console.await_quiescent(Duration::from_secs(5)));
+    for i in 1..4 {
+        assert_eq!(console.query(i), Some(3));
+    }
+    console.exit()
+}
+
+#[test]
+fn test_query_all() {
+    let mut console = Console::new();
+    console.start_servers(5, 9870);
+    console.propose(1, 3);
+    assert_eq!(console.query_wait(1, Duration::from_secs(2)), Some(3));
+    assert!(console.await_quiescent(Duration::from_secs(2)));
+
+    // 暂停的服务器不处理查询，相当于宕机
+    console.pause(4, true);
+    console.pause(5, true);
+    let start = Instant::now();
+    let report = console.query_all(Duration::from_millis(200), Duration::from_secs(1));
+    assert!(start.elapsed() < Duration::from_secs(1));
+    assert_eq!(report.answers.len(), 3);
+    assert!((1..4).all(|id| report.answers[&id] == Some(3)));
+    assert_eq!(report.missing, vec![4, 5]);
+
+    // 恢复后 #4 与 #5 迟到的回应不能算作下一次的回答
+    console.pause(4, false);
+    console.pause(5, false);
+    thread::sleep(Duration::from_millis(100));
+    console.pause(5, true);
+    let report = console.query_all(Duration::from_millis(200), Duration::from_secs(1));
+    assert_eq!(report.missing, vec![5]);
+    console.exit()
+}
+
+#[test]
+fn test_wait_for_value() {
+    let mut console = Console::new();
+    console.start_servers(3, 9940);
+    assert!(!console.wait_for_value(5, Duration::from_millis(100)));
+
+    console.propose(2, 5);
+    let start = Instant::now();
+    assert!(console.wait_for_value(5, Duration::from_secs(2)));
+    assert!(start.elapsed() < Duration::from_secs(2));
+    // 选定的不是这个值，等到超时
+    assert!(!console.wait_for_value(6, Duration::from_millis(100)));
+    console.exit()
+}
+
+#[test]
+fn test_ping() {
+    assert_eq!("ping 2".parse(), Ok(Command::Ping(2)));
+    assert_eq!("pingall".parse(), Ok(Command::PingAll));
+
+    let mut console = Console::new();
+    console.start_servers(3, 10100);
+    // #2 处理每条消息前都停顿 100ms，相当于链路上多了这么多时延
+    console.slow(2, 100);
+    let report = console.ping_all();
+    assert_eq!(report.len(), 3);
+    let slow = report[&2];
+    assert!(slow.min >= Duration::from_millis(100), "{:?}", slow);
+    assert!(slow.max < Duration::from_millis(300), "{:?}", slow);
+    assert!(slow.min <= slow.avg && slow.avg <= slow.max);
+    for id in [1, 3] {
+        assert!(report[&id].max < Duration::from_millis(100), "{:?}", report);
+    }
+    console.exit()
+}
+
+#[test]
+fn test_dump() {
+    assert_eq!("dump".parse(), Ok(Command::Dump(None)));
+    assert_eq!(
+        "dump /tmp/x.json".parse(),
+        Ok(Command::Dump(Some("/tmp/x.json".to_string())))
+    );
+    assert_eq!(
+        "DUMP /tmp/MyDump.json".parse(),
+        Ok(Command::Dump(Some("/tmp/MyDump.json".to_string())))
+    );
+
+    let mut console = Console::new();
+    console.start_servers(3, 10160);
+    console.set_proposer(Some(2));
+    console.propose(2, 5);
+    assert!(console.await_quiescent(Duration::from_secs(2)));
+
+    let path = std::env::temp_dir().join(format!("paxos-Dump-{}.json", std::process::id()));
+    console.dump_to(Some(path.to_str().unwrap()));
+    let json = std::fs::read_to_string(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    let dump: ClusterDump = paxos::json::from_str(json.trim()).unwrap();
+
+    assert_eq!(dump.group, 0);
+    let ids: Vec<usize> = dump.servers.iter().map(|server| server.id).collect();
+    assert_eq!(ids, vec![1, 2, 3]);
+    for server in &dump.servers {
+        let port = 10160 + server.id as u16;
+        assert_eq!(server.addr, Some(([127, 0, 0, 1], port).into()));
+        let role = if server.id == 2 {
+            "proposer"
+        } else {
+            "acceptor"
+        };
+        assert_eq!(server.role, role);
+        assert_eq!(server.state, "Decided");
+        assert_eq!(server.chosen.map(|chosen| chosen.val), Some(5));
+        let accepted = server.last_accepted.unwrap();
+        assert_eq!(server.last_promised, Some(accepted.seq));
+        assert_eq!(server.commit_index, 1);
+        assert!(server.metrics["handled"] > 0);
+        assert_eq!(server.metrics["msg.prepare"], 1);
+    }
+    assert_eq!(dump.servers[1].metrics["decided"], 1);
+    console.exit()
+}
+
+#[test]
+fn test_query_within() {
+    assert_eq!(
+        "query --within 200 3".parse(),
+        Ok(Command::QueryWithin(3, 200))
+    );
+
+    let mut console = Console::new();
+    console.start_servers(3, 10200);
+    // 还没有收到过 learn 或心跳时无从判断
+    assert_eq!(console.query_within(3, Duration::from_secs(1)), None);
+
+    // read-index 的心跳轮之后，“还没有学习到”在界限之内可信，过了界限就过时了
+    assert_eq!(console.query_read_index(1), None);
+    assert_eq!(console.query_within(3, Duration::from_secs(1)), Some(None));
+    thread::sleep(Duration::from_millis(300));
+    assert_eq!(console.query_within(3, Duration::from_millis(200)), None);
+    assert_eq!(console.query_within(3, Duration::from_secs(5)), Some(None));
+
+    // 选定的值不会再变，学习到的值无论多旧都接受
+    console.propose(1, 4);
+    assert!(console.wait_for_value(4, Duration::from_secs(2)));
+    thread::sleep(Duration::from_millis(300));
+    assert_eq!(
+        console.query_within(3, Duration::from_millis(100)),
+        Some(Some(4))
+    );
+    console.exit()
+}
+
+#[test]
+fn test_conns() {
+    assert_eq!("conns 2".parse(), Ok(Command::Conns(2)));
+
+    let mut console = Console::new();
+    console.start_servers(3, 10230);
+    assert_eq!(console.conns(1), Some(Default::default()));
+    console.set_proposer(Some(2));
+    console.propose(2, 5);
+    assert!(console.await_quiescent(Duration::from_secs(2)));
+
+    // 提案者收到过客户端的请求，与其他决策结点互有往来，发给自己的报文不经过网络
+    let conns = console.conns(2).unwrap();
+    assert_eq!(conns.keys().copied().collect::<Vec<_>>(), vec![0, 1, 3]);
+    assert!(conns[&0].bytes_received > 0);
+    assert_eq!(conns[&0].inbound, Some(ConnState::Connected));
+    for peer in [1, 3] {
+        let conn = conns[&peer];
+        assert_eq!(conn.outbound, Some(ConnState::Connected));
+        assert_eq!(conn.inbound, Some(ConnState::Connected));
+        assert!(conn.bytes_sent > 0 && conn.bytes_received > 0, "{:?}", conn);
+    }
+    assert_eq!(console.conns(9), None);
+    console.exit();
+}
+
+#[test]
+fn test_query_read_index() {
+    assert_eq!(
+        "query --read-index 2".parse(),
+        Ok(Command::QueryReadIndex(2))
+    );
+
+    let mut console = Console::new();
+    console.start_servers(3, 10240);
+    console.set_proposer(Some(2));
+    console.set_redirect(true);
+    assert_eq!(console.query_read_index(2), None);
+
+    // 写完立即读，读到的就是刚写入的值；问决策者时转到提案者
+    let chosen = console.propose_timed(2, 7, 0, Duration::from_secs(2));
+    assert_eq!(chosen.map(|(value, _)| value), Some(7));
+    assert_eq!(console.query_read_index(2), Some(7));
+    assert_eq!(console.query_read_index(1), Some(7));
+
+    // 没有多数派回应心跳时，提案者到期后回 read_failed，客户端不必等到自己超时
+    console.pause(1, true);
+    console.pause(3, true);
+    let start = Instant::now();
+    assert_eq!(console.query_read_index(2), None);
+    let elapsed = start.elapsed();
+    assert!(elapsed >= READ_TIMEOUT, "{:?}", elapsed);
+    assert!(
+        elapsed < READ_TIMEOUT + Duration::from_millis(400),
+        "{:?}",
+        elapsed
+    );
+
+    // 恢复后迟到的心跳回应找不到作废的读，新的读照常完成
+    console.pause(1, false);
+    console.pause(3, false);
+    assert_eq!(console.query_read_index(2), Some(7));
+    console.exit();
+}
@@ -0,0 +1,9 @@
+use paxos::paxos::majority;
+
+#[test]
+fn test_majority() {
+    let expected = [(1, 1), (2, 2), (3, 2), (4, 3), (5, 3), (6, 4)];
+    for (n, quorum) in expected {
+        assert_eq!(majority(n), quorum, "n = {}", n);
+    }
+}
@@ -0,0 +1,37 @@
+use std::collections::HashMap;
+
+use paxos::paxos::seq_num::SequenceNumber;
+
+const SERVERS: usize = 5;
+const ROUNDS: u128 = 1000;
+
+// 每个时间戳下所有服务器同时提案，统计各服务器胜出的次数
+fn wins(ballot: fn(usize, u128) -> SequenceNumber) -> HashMap<usize, u128> {
+    let mut wins = HashMap::new();
+    for time_stamp in 0..ROUNDS {
+        let winner = (1..=SERVERS)
+            .map(|id| ballot(id, time_stamp))
+            .max()
+            .unwrap();
+        *wins.entry(winner.server_id()).or_default() += 1;
+    }
+    wins
+}
+
+#[test]
+fn test_fair_tie_break() {
+    // 默认总是 ID 最大的胜出
+    assert_eq!(wins(SequenceNumber::new)[&SERVERS], ROUNDS);
+
+    // 公平模式下每台服务器都能赢到大约 1/5
+    let wins = wins(SequenceNumber::fair);
+    for id in 1..=SERVERS {
+        let share = wins.get(&id).copied().unwrap_or_default();
+        assert!((100..300).contains(&share), "{:?}", wins);
+    }
+
+    // 时间戳仍然优先，不同服务器的序号不会相等
+    assert!(SequenceNumber::fair(1, 2) > SequenceNumber::fair(5, 1));
+    assert_ne!(SequenceNumber::fair(1, 7), SequenceNumber::fair(2, 7));
+    assert_eq!(SequenceNumber::fair(3, 7), SequenceNumber::fair(3, 7));
+}
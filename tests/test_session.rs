@@ -0,0 +1,129 @@
+use std::time::Duration;
+
+use futures::future::join_all;
+use paxos::client::SessionError;
+use paxos::shell::Console;
+
+#[test]
+fn test_concurrent_proposals() {
+    let mut console = Console::new();
+    console.start_servers(3, 9980);
+    let session = console.session().unwrap();
+
+    // 50 个提案同时在途，分散到各台服务器，每个都拿到同一个选定值
+    let mut rt = tokio::runtime::Runtime::new().unwrap();
+    let results =
+        rt.block_on(join_all((0..50).map(|i| {
+            session.propose(i % 3 + 1, i as u32, Duration::from_secs(3))
+        })));
+    let chosen = console.query(1).unwrap();
+    assert!(chosen < 50);
+    assert!(results.iter().all(|result| *result == Ok(chosen)));
+    console.exit()
+}
+
+#[test]
+fn test_session_redirect() {
+    let mut console = Console::new();
+    console.start_servers(3, 9990);
+    console.set_proposer(Some(2));
+    console.set_redirect(true);
+    let session = console.session().unwrap();
+
+    let mut rt = tokio::runtime::Runtime::new().unwrap();
+    let chosen = rt.block_on(session.propose(1, 6, Duration::from_secs(3)));
+    assert_eq!(chosen, Ok(6));
+    assert_eq!(console.who(2).unwrap().proposer(), 2);
+    console.exit()
+}
+
+#[test]
+fn test_max_in_flight() {
+    let mut console = Console::new();
+    console.start_servers(3, 10130);
+    let session = console.session().unwrap().with_max_in_flight(5);
+
+    // 100 个提案同时发起，最多 5 个在途，其余排队，最终都拿到选定值
+    let mut rt = tokio::runtime::Runtime::new().unwrap();
+    let results =
+        rt.block_on(join_all((0..100).map(|i| {
+            session.propose(i % 3 + 1, i as u32, Duration::from_secs(3))
+        })));
+    let chosen = console.query(1).unwrap();
+    assert!(results.iter().all(|result| *result == Ok(chosen)));
+    assert!((1..=5).contains(&session.peak_in_flight()));
+    assert_eq!(session.in_flight(), 0);
+    console.exit()
+}
+
+#[test]
+fn test_propose_decision() {
+    let mut console = Console::new();
+    console.start_servers(3, 10140);
+    let session = console.session().unwrap();
+
+    let mut rt = tokio::runtime::Runtime::new().unwrap();
+    let (chosen, record) = rt
+        .block_on(session.propose_decision(2, 8, Duration::from_secs(3)))
+        .unwrap();
+    let record = record.unwrap();
+    assert_eq!((chosen, record.value), (8, 8));
+    assert_eq!(record.ballot.server_id(), 2);
+    assert_eq!(record.rounds, 1);
+    assert_eq!(record.deciding_quorum.len(), 2);
+    assert!(record.deciding_quorum.iter().all(|id| (1..4).contains(id)));
+
+    // 没有选定该值的服务器只给出值
+    assert!(console.wait_for_value(8, Duration::from_secs(2)));
+    let answer = rt.block_on(session.propose_decision(3, 9, Duration::from_secs(3)));
+    assert_eq!(answer, Ok((8, None)));
+    console.exit()
+}
+
+#[test]
+fn test_session_ids() {
+    let mut console = Console::new();
+    console.start_servers(3, 10280);
+
+    // 每个会话各用一个 ID，在指定的地址上监听
+    let bind = "127.0.0.1:10285".parse().unwrap();
+    let first = console.session_on(bind).unwrap();
+    let second = console.session().unwrap();
+    assert_ne!(first.id(), second.id());
+    assert!(std::net::TcpListener::bind(bind).is_err());
+    let mut rt = tokio::runtime::Runtime::new().unwrap();
+    let chosen = rt.block_on(first.propose(1, 3, Duration::from_secs(3)));
+    assert_eq!(chosen, Ok(3));
+    assert_eq!(
+        rt.block_on(second.propose(2, 4, Duration::from_secs(3))),
+        Ok(3)
+    );
+    console.exit()
+}
+
+#[test]
+fn test_session_retries() {
+    let mut console = Console::new();
+    console.start_servers(3, 10290);
+    console.pause(1, true);
+
+    // 暂停的服务器不回应，不重发时直接超时
+    let session = console.session().unwrap();
+    let mut rt = tokio::runtime::Runtime::new().unwrap();
+    let timeout = Duration::from_millis(300);
+    assert_eq!(
+        rt.block_on(session.propose(1, 5, timeout)),
+        Err(SessionError::Timeout(1))
+    );
+
+    // 重发期间服务器恢复，之后的一次等到了选定值
+    let session = session.with_retries(10);
+    let proposing = std::thread::spawn(move || {
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        rt.block_on(session.propose(1, 5, timeout))
+    });
+    std::thread::sleep(Duration::from_millis(500));
+    console.pause(1, false);
+    assert_eq!(proposing.join().unwrap(), Ok(5));
+    console.exit()
+}
@@ -0,0 +1,40 @@
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use futures::channel::mpsc;
+use paxos::net_proxy::Proxy;
+use paxos::paxos::node::Node;
+use paxos::shutdown::Shutdown;
+use tokio::net::TcpStream;
+
+#[test]
+fn test_shutdown_all_tasks() {
+    let mut rt = tokio::runtime::Runtime::new().unwrap();
+    rt.block_on(async {
+        let addr: SocketAddr = "127.0.0.1:9770".parse().unwrap();
+        let table: HashMap<usize, SocketAddr> = (0..2).map(|id| (id, addr)).collect();
+        let proxy = Proxy::new(1, Arc::new(RwLock::new(table)));
+        let listener = proxy.bind().await.unwrap();
+
+        let (itx, irx) = mpsc::unbounded();
+        let (otx, orx) = mpsc::unbounded();
+        let node = Node::new(1, (1..2).collect(), otx, irx);
+        let (shutdown, shutdown_rx) = Shutdown::new();
+        let proxy = tokio::spawn(proxy.run(listener, itx, orx, shutdown_rx.clone()));
+        let node = tokio::spawn(node.run(shutdown_rx));
+
+        // 一个一直不发数据的连接，收发任务的通道也都没有关闭
+        let _idle = TcpStream::connect(addr).await.unwrap();
+        tokio::time::delay_for(Duration::from_millis(50)).await;
+
+        shutdown.send(()).unwrap();
+        let joined = tokio::time::timeout(Duration::from_secs(1), async {
+            proxy.await.unwrap().unwrap();
+            node.await.unwrap();
+        })
+        .await;
+        assert!(joined.is_ok(), "tasks didn't exit after shutdown");
+    });
+}
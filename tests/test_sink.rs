@@ -0,0 +1,72 @@
+use futures::channel::mpsc;
+use std::time::Duration;
+use tokio::stream::StreamExt;
+
+use paxos::paxos::node::{Node, NodeState};
+use paxos::paxos::proposal::{Datagram, Incoming, Request};
+use paxos::paxos::seq_num::SequenceNumber;
+use paxos::paxos::sink::FileSink;
+use paxos::shutdown::Shutdown;
+
+fn propose(value: u32) -> Incoming {
+    Incoming {
+        src: 0,
+        dgram: Datagram::Request(Request::Propose {
+            value,
+            priority: 0,
+            seq: None,
+        }),
+    }
+}
+
+#[test]
+fn test_file_sink() {
+    let path = std::env::temp_dir().join(format!("paxos-sink-{}", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let mut rt = tokio::runtime::Runtime::new().unwrap();
+    rt.block_on(async {
+        // 几个单结点集群依次选定各自的值，都追加到同一个文件
+        let mut nodes = Vec::new();
+        for (id, value) in [(1, 3), (2, 1), (3, 2)] {
+            let (itx, irx) = mpsc::unbounded();
+            let (otx, mut orx) = mpsc::unbounded();
+            let (shutdown, shutdown_rx) = Shutdown::new();
+            let sink = Box::new(FileSink::open(&path).unwrap());
+            let node = Node::new(id, Default::default(), otx, irx).with_sink(sink);
+            let control = node.control();
+            tokio::spawn(node.run(shutdown_rx));
+            // 单结点集群的报文都发给自己，这里代替代理送回
+            let loopback = itx.clone();
+            tokio::spawn(async move {
+                while let Some(outgoing) = orx.next().await {
+                    let incoming = Incoming {
+                        src: id,
+                        dgram: outgoing.dgram,
+                    };
+                    let _ = loopback.unbounded_send(incoming);
+                }
+            });
+            itx.unbounded_send(propose(value)).unwrap();
+            while control.state() != NodeState::Decided {
+                tokio::time::delay_for(Duration::from_millis(5)).await;
+            }
+            nodes.push((itx, shutdown));
+        }
+
+        // 重复的 learn 不会再追加
+        let learn = Request::Learn {
+            seq: SequenceNumber::new(1, 1),
+            value: 3,
+        };
+        let (itx, _) = &nodes[0];
+        itx.unbounded_send(Incoming {
+            src: 2,
+            dgram: Datagram::Request(learn),
+        })
+        .unwrap();
+        tokio::time::delay_for(Duration::from_millis(50)).await;
+    });
+    let exported = std::fs::read_to_string(&path).unwrap();
+    assert_eq!(exported, "0 3\n0 1\n0 2\n");
+    std::fs::remove_file(&path).unwrap();
+}
@@ -0,0 +1,127 @@
+use std::time::Duration;
+
+use paxos::shell::{Command, Console};
+
+#[test]
+fn test_stats() {
+    assert_eq!("stats".parse(), Ok(Command::Stats));
+
+    let mut console = Console::new();
+    console.start_servers(3, 9920);
+    assert_eq!(console.stats().rounds_per_decision(), None);
+
+    console.propose(1, 5);
+    assert_eq!(console.query_wait(1, Duration::from_secs(2)), Some(5));
+    // 已经选定后的提案不再发起新的一轮
+    console.propose(2, 6);
+    assert!(console.await_quiescent(Duration::from_secs(2)));
+
+    let stats = console.stats();
+    assert_eq!(stats.proposals, 2);
+    assert_eq!(stats.rounds, 1);
+    assert_eq!(stats.decided, 1);
+    assert_eq!(stats.rounds_per_decision(), Some(1.0));
+    assert_eq!(stats.dropped, 0);
+    // 一轮中每台服务器各收到一次 prepare、accept 与 learn
+    for kind in ["prepare", "accept", "learn", "promise", "accepted"] {
+        assert_eq!(stats.messages[kind], 3, "{}", kind);
+    }
+    console.exit()
+}
+
+#[test]
+fn test_lag() {
+    assert_eq!("lag".parse(), Ok(Command::Lag));
+
+    let mut console = Console::new();
+    console.start_servers(3, 9970);
+    assert_eq!(console.lag().highest, 0);
+
+    // #3 暂停期间其他服务器选定了值
+    console.pause(3, true);
+    console.propose(1, 5);
+    assert!(console.wait_for_value(5, Duration::from_secs(2)));
+    let report = console.lag();
+    assert_eq!(report.highest, 1);
+    assert_eq!(report.lag(1), Some(0));
+    assert_eq!(report.lag(3), Some(1));
+
+    // 恢复后处理完积压的消息就追上了
+    console.pause(3, false);
+    assert!(console.await_quiescent(Duration::from_secs(2)));
+    assert_eq!(console.lag().lag(3), Some(0));
+    console.exit()
+}
+
+#[test]
+fn test_stress() {
+    assert_eq!("stress 10 8".parse(), Ok(Command::Stress(10, 8)));
+
+    let mut console = Console::new();
+    console.set_verbosity(paxos::shell::Verbosity::Quiet);
+    console.start_servers(5, 10190);
+    let report = console.stress(Duration::from_secs(2), 8);
+    // 只达成了一次共识，其余的提案都直接拿到它
+    assert_eq!(report.decisions, 1);
+    assert!(report.answered() > 1);
+    assert!(report.throughput() > 0.0);
+    assert_eq!(report.failures, 0);
+    assert_eq!(report.violations, 0);
+    assert!(report.percentile(50.0) <= report.percentile(99.0));
+    console.exit()
+}
+
+#[test]
+fn test_metrics_line() {
+    assert_eq!("metrics 50".parse(), Ok(Command::Metrics(50)));
+
+    let dir = std::env::temp_dir().join(format!("paxos-metrics-{}", std::process::id()));
+    let mut console = Console::new();
+    console.start_servers(3, 10250);
+    console.set_log_dir(&dir).unwrap();
+    console.set_metrics_interval(50);
+    console.propose(1, 7);
+    assert!(console.wait_for_value(7, Duration::from_secs(2)));
+    std::thread::sleep(Duration::from_millis(200));
+    console.exit();
+
+    // 提案者 #1 发出过 prepare 与 accept，并由它选定了值
+    let log = std::fs::read_to_string(dir.join("node-1.log")).unwrap();
+    let line = log
+        .lines()
+        .rfind(|line| line.starts_with("metrics: server #1 "))
+        .unwrap_or_else(|| panic!("no metrics line in {}", log));
+    for counter in ["prepares=1 ", "accepts=1 ", "nacks=0 ", "decisions=1 "] {
+        assert!(line.contains(counter), "{}", line);
+    }
+    assert!(line.contains(" connections="), "{}", line);
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn test_metrics_rearm() {
+    let dir = std::env::temp_dir().join(format!("paxos-rearm-{}", std::process::id()));
+    let mut console = Console::new();
+    console.start_servers(3, 10320);
+    console.set_log_dir(&dir).unwrap();
+    let lines = || {
+        let log = std::fs::read_to_string(dir.join("node-1.log")).unwrap_or_default();
+        log.lines()
+            .filter(|line| line.starts_with("metrics: "))
+            .count()
+    };
+
+    // 新间隔从设置时算起，结点启动得早也不会立即输出
+    std::thread::sleep(Duration::from_millis(300));
+    console.set_metrics_interval(200);
+    std::thread::sleep(Duration::from_millis(100));
+    assert_eq!(lines(), 0);
+
+    // 从很长的间隔改短后不必等旧的计时到期
+    console.set_metrics_interval(60_000);
+    console.set_metrics_interval(50);
+    std::thread::sleep(Duration::from_millis(300));
+    assert!(lines() > 0);
+    console.exit();
+    std::fs::remove_dir_all(dir).unwrap();
+}
@@ -0,0 +1,80 @@
+use futures::channel::mpsc;
+use std::time::{Duration, Instant};
+use tokio::stream::StreamExt;
+
+use paxos::paxos::node::Node;
+use paxos::paxos::proposal::{AcceptedProposal, Datagram, Incoming, Request, Response};
+use paxos::paxos::seq_num::SequenceNumber;
+use paxos::paxos::store::{FileStore, MemoryStore, StateStore};
+use paxos::shutdown::Shutdown;
+
+fn prepare(seq: SequenceNumber) -> Incoming {
+    Incoming {
+        src: 2,
+        dgram: Datagram::Request(Request::Prepare { seq }),
+    }
+}
+
+#[test]
+fn test_promise_waits_for_store() {
+    let mut rt = tokio::runtime::Runtime::new().unwrap();
+    rt.block_on(async {
+        let store = MemoryStore::default();
+        store.set_delay(Duration::from_millis(200));
+        let (itx, irx) = mpsc::unbounded();
+        let (otx, mut orx) = mpsc::unbounded();
+        let (_shutdown, shutdown_rx) = Shutdown::new();
+        let node = Node::with_store(1, (1..4).collect(), Box::new(store.clone()), otx, irx);
+        tokio::spawn(node.unwrap().run(shutdown_rx));
+
+        // 承诺写完之后才回应
+        let seq = SequenceNumber::new(2, 1000);
+        let start = Instant::now();
+        itx.unbounded_send(prepare(seq)).unwrap();
+        let promise = orx.next().await.unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(200));
+        assert_eq!(store.snapshot().last_promised, Some(seq));
+        assert_eq!(
+            promise.dgram,
+            Datagram::Response(Response::Prepare {
+                seq,
+                accepted: None
+            })
+        );
+
+        // 写入失败时不回应，也不记下这个承诺
+        store.set_delay(Duration::ZERO);
+        store.set_failing(true);
+        let higher = SequenceNumber::new(2, 2000);
+        itx.unbounded_send(prepare(higher)).unwrap();
+        let silent = tokio::time::timeout(Duration::from_millis(200), orx.next()).await;
+        assert!(silent.is_err());
+        assert_eq!(store.snapshot().last_promised, Some(seq));
+        assert_eq!(store.writes(), 1);
+    });
+}
+
+#[test]
+fn test_file_store() {
+    let path = std::env::temp_dir().join(format!("paxos-store-{}", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let seq = SequenceNumber::new(2, 1000);
+    let accepted = AcceptedProposal::new(seq, 7);
+    {
+        let mut store = FileStore::open(&path).unwrap();
+        assert_eq!(store.load().unwrap(), Default::default());
+        store.persist_promise(seq).unwrap();
+        store.persist_accept(accepted).unwrap();
+    }
+
+    // 重新打开后结点带着上次的状态启动
+    let (_itx, irx) = mpsc::unbounded();
+    let (otx, _orx) = mpsc::unbounded();
+    let store = Box::new(FileStore::open(&path).unwrap());
+    let node = Node::with_store(1, (1..4).collect(), store, otx, irx).unwrap();
+    let snapshot = node.snapshot();
+    assert_eq!(snapshot.last_promised, Some(seq));
+    assert_eq!(snapshot.last_accepted, Some(accepted));
+    assert_eq!(snapshot.chosen, None);
+    std::fs::remove_file(&path).unwrap();
+}
@@ -0,0 +1,20 @@
+use paxos::task_name::{self, named};
+
+#[test]
+fn test_task_name() {
+    let mut rt = tokio::runtime::Runtime::new().unwrap();
+    rt.block_on(async {
+        let name = tokio::spawn(named("node-3".to_string(), async { task_name::current() }))
+            .await
+            .unwrap();
+        assert!(name.unwrap().contains('3'));
+        assert_eq!(task_name::current(), None);
+
+        // panic 的任务照常以 JoinError 结束
+        let panicked = tokio::spawn(named("proxy-inflow-3".to_string(), async {
+            panic!("boom");
+        }))
+        .await;
+        assert!(panicked.is_err());
+    });
+}
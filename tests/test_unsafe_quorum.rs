@@ -0,0 +1,69 @@
+#![cfg(feature = "debug")]
+
+use paxos::paxos::harness::Network;
+use paxos::paxos::proposal::{Datagram, Incoming, Request};
+
+fn propose(value: u32) -> Incoming {
+    let req = Request::Propose {
+        value,
+        priority: 0,
+        seq: None,
+    };
+    Incoming {
+        src: 0,
+        dgram: Datagram::Request(req),
+    }
+}
+
+fn chosen(network: &Network, id: usize) -> Option<u32> {
+    let node = network.node(id).unwrap().node();
+    node.snapshot().chosen.map(|chosen| chosen.val)
+}
+
+#[test]
+fn test_connected_quorum_diverges() {
+    let mut network = Network::new(5);
+    let (left, right) = ([1, 2], [3, 4, 5]);
+    network.partition(&left);
+
+    // 正常的多数派下，只有 3 个结点的一边才能选定值
+    network.send(1, propose(1));
+    network.send(3, propose(2));
+    network.run(1000);
+    assert_eq!(chosen(&network, 1), None);
+    assert_eq!(chosen(&network, 3), Some(2));
+
+    // 按连通结点计算多数派后，两边都能选定，而且选定了不同的值
+    let mut network = Network::new(5);
+    network.partition(&left);
+    for side in [&left[..], &right[..]] {
+        for &id in side {
+            let control = network.node(id).unwrap().control();
+            control.set_connected_quorum(Some(side.iter().copied().collect()));
+            control.set_lenient(true);
+        }
+    }
+    network.send(1, propose(1));
+    network.send(3, propose(2));
+    network.run(1000);
+    assert_eq!(chosen(&network, 1), Some(1));
+    assert_eq!(chosen(&network, 3), Some(2));
+
+    // 分区恢复后，安全性检查发现不一致
+    network.heal();
+    let chosen_left = network.node(1).unwrap().node().snapshot().chosen.unwrap();
+    let learn = Request::Learn {
+        seq: chosen_left.seq,
+        value: chosen_left.val,
+    };
+    network.send(
+        3,
+        Incoming {
+            src: 1,
+            dgram: Datagram::Request(learn),
+        },
+    );
+    network.run(10);
+    let control = network.node(3).unwrap().control();
+    assert_eq!(control.stats().violations, 1);
+}
@@ -0,0 +1,116 @@
+#![cfg(feature = "ws")]
+
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpStream;
+use std::time::Duration;
+
+use paxos::shell::Console;
+use paxos::ws::accept_key;
+
+// 读出一个不带掩码的文本帧
+fn read_text(stream: &mut impl Read) -> String {
+    let mut head = [0u8; 2];
+    stream.read_exact(&mut head).unwrap();
+    assert_eq!(head[0], 0x81);
+    let len = match head[1] {
+        126 => {
+            let mut len = [0u8; 2];
+            stream.read_exact(&mut len).unwrap();
+            u16::from_be_bytes(len) as usize
+        }
+        len => len as usize,
+    };
+    let mut text = vec![0u8; len];
+    stream.read_exact(&mut text).unwrap();
+    String::from_utf8(text).unwrap()
+}
+
+#[test]
+fn test_accept_key() {
+    // RFC 6455 中的例子
+    assert_eq!(
+        accept_key("dGhlIHNhbXBsZSBub25jZQ=="),
+        "s3pPLMBiTxaQ9kYGzzhZRbK+xOo="
+    );
+}
+
+#[test]
+fn test_event_stream() {
+    let mut console = Console::new();
+    console.start_servers(3, 10000);
+    console.start_ws("127.0.0.1:10010".parse().unwrap());
+
+    let stream = TcpStream::connect("127.0.0.1:10010").unwrap();
+    stream
+        .set_read_timeout(Some(Duration::from_secs(3)))
+        .unwrap();
+    let mut stream = BufReader::new(stream);
+    write!(
+        stream.get_mut(),
+        "GET / HTTP/1.1\r\nHost: localhost\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\nSec-WebSocket-Version: 13\r\n\r\n"
+    )
+    .unwrap();
+    let mut status = String::new();
+    stream.read_line(&mut status).unwrap();
+    assert_eq!(status.trim(), "HTTP/1.1 101 Switching Protocols");
+    loop {
+        let mut header = String::new();
+        stream.read_line(&mut header).unwrap();
+        if header.trim().is_empty() {
+            break;
+        }
+    }
+
+    // 提案后先看到提案开始，最终看到 #1 选定了值
+    console.propose(1, 42);
+    let first = read_text(&mut stream);
+    assert!(
+        first.starts_with(r#"{"ProposalStarted":{"node":1,"#),
+        "{}",
+        first
+    );
+    loop {
+        let event = read_text(&mut stream);
+        if event.starts_with(r#"{"Decided":{"node":1,"#) {
+            assert!(event.ends_with(r#""value":42}}"#), "{}", event);
+            break;
+        }
+    }
+    console.exit()
+}
+
+#[test]
+fn test_handshake_limits() {
+    let mut console = Console::new();
+    console.start_servers(3, 10271);
+    console.start_ws("127.0.0.1:10270".parse().unwrap());
+    let status = |request: &[u8]| {
+        let mut stream = TcpStream::connect("127.0.0.1:10270").unwrap();
+        stream.write_all(request).unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        response.lines().next().unwrap_or_default().to_string()
+    };
+
+    // 报头读满 8192 字节还没有换行就不再读，没有 key 的请求不升级。
+    // 过长的报头正好 8192 字节，免得未读的数据让对方关闭时重置连接
+    let mut long = b"GET / HTTP/1.1\r\nX-Padding: ".to_vec();
+    long.resize(16 + 8192, b'a');
+    assert_eq!(
+        status(&long),
+        "HTTP/1.1 431 Request Header Fields Too Large"
+    );
+    assert_eq!(
+        status(b"GET / HTTP/1.1\r\n\r\n"),
+        "HTTP/1.1 400 Bad Request"
+    );
+
+    // 闲置的连接超时后被打发
+    let start = std::time::Instant::now();
+    assert_eq!(
+        status(b"GET / HTTP/1.1\r\n"),
+        "HTTP/1.1 408 Request Timeout"
+    );
+    assert!(start.elapsed() < Duration::from_secs(3));
+    console.exit()
+}